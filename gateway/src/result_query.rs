@@ -155,6 +155,45 @@ pub struct CPRankingEntry {
 	pub num_reasons: i32,
 }
 
+#[derive(juniper::GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct DojinTypeCount {
+	/// 作品类型
+	pub dojin_type: String,
+	/// 以该类型提名的次数
+	pub count: i32
+}
+
+/// 用于同人作品
+#[derive(juniper::GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct DojinRankingEntry {
+	/// 排名
+	pub rank: i32,
+	/// 展示排名
+	pub display_rank: i32,
+	/// 作品id
+	pub work_id: String,
+	/// 作品链接
+	pub url: String,
+	/// 作品名
+	pub title: String,
+	/// 作者
+	pub author: String,
+	/// 封面
+	pub image_url: Option<String>,
+	/// 提名数
+	pub nomination_count: i32,
+	/// 提名占比
+	pub nomination_percentage: f64,
+	/// 各类型提名数
+	pub type_breakdown: Vec<DojinTypeCount>,
+	/// 首次提名时间
+	pub first_nominated_at: Option<DateTime<Utc>>,
+	/// 理由
+	pub reasons: Vec<String>,
+	/// 理由数量
+	pub num_reasons: i32,
+}
+
 
 #[derive(juniper::GraphQLObject, Clone, Serialize, Deserialize)]
 pub struct RankingGlobal {
@@ -182,6 +221,73 @@ pub struct CPRanking {
 	pub global: RankingGlobal
 }
 
+#[derive(juniper::GraphQLObject, Clone, Serialize, Deserialize)]
+pub struct DojinRanking {
+	pub entries: Vec<DojinRankingEntry>,
+	pub global: RankingGlobal
+}
+
+/// 实时结果的类别
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveRankingKind {
+	Chars,
+	Musics,
+	Cps
+}
+
+/// 投票期间的实时排名，CP的名字为成员排序后用x连接
+#[derive(juniper::GraphQLObject, Debug, Clone, Serialize, Deserialize)]
+pub struct LiveRankingEntry {
+	/// 排名
+	pub rank: i32,
+	/// 展示排名
+	pub display_rank: i32,
+	/// 角色名/音乐名/CP名
+	pub name: String,
+	/// 票数
+	pub vote_count: i32,
+	/// 本命票数
+	pub first_vote_count: i32,
+	/// 票数占比
+	pub vote_percentage: f64,
+	/// 本命占比
+	pub first_percentage: f64,
+	/// 男性票数
+	pub male_vote_count: i32,
+	/// 女性票数
+	pub female_vote_count: i32,
+	/// 男性比例 P(male|voted)
+	pub male_percentage_per_char: f64,
+	/// 女性比例
+	pub female_percentage_per_char: f64,
+	/// 趋势
+	pub trend: Vec<VotingTrendItem>,
+	/// 本命趋势
+	pub trend_first: Vec<VotingTrendItem>,
+}
+
+#[derive(juniper::GraphQLObject, Clone, Serialize, Deserialize)]
+pub struct LiveRanking {
+	pub kind: LiveRankingKind,
+	pub vote_year: i32,
+	/// 读取快照时为快照名
+	pub snapshot: Option<String>,
+	/// 计数的生成时间
+	pub generated_at: DateTime<Utc>,
+	pub entries: Vec<LiveRankingEntry>,
+	pub global: RankingGlobal
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LiveRankingRequest {
+	pub vote_year: i32,
+	pub kind: LiveRankingKind,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub snapshot: Option<String>
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RankingQueryRequest {
 	#[serde(skip_serializing_if = "Option::is_none")]
@@ -477,6 +583,16 @@ pub async fn queryCPRanking_impl(context: &Context, query: Option<String>, vote_
 	Ok(post_result)
 }
 
+pub async fn queryDojinRanking_impl(context: &Context, query: Option<String>, vote_start: DateTime<Utc>, vote_year: i32) -> FieldResult<DojinRanking> {
+	let query_json = RankingQueryRequest {
+		query,
+		vote_start,
+		vote_year
+	};
+	let post_result: DojinRanking = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/dojins-rank/", RESULT_QUERY), query_json).await?;
+	Ok(post_result)
+}
+
 pub async fn queryCPReasons_impl(context: &Context, query: Option<String>, vote_start: DateTime<Utc>, vote_year: i32, rank: i32) -> FieldResult<Reasons> {
 	let query_json = ReasonsRequest {
 		query,
@@ -573,3 +689,13 @@ pub async fn queryMusicsCovote_impl(context: &Context, query: Option<String>, vo
 	let post_result: CovoteResponse = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/musics-covote/", RESULT_QUERY), query_json).await?;
 	Ok(post_result)
 }
+
+pub async fn queryLiveRanking_impl(context: &Context, vote_year: i32, kind: LiveRankingKind, snapshot: Option<String>) -> FieldResult<LiveRanking> {
+	let query_json = LiveRankingRequest {
+		vote_year,
+		kind,
+		snapshot
+	};
+	let post_result: LiveRanking = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/live-rank/", RESULT_QUERY), query_json).await?;
+	Ok(post_result)
+}
@@ -9,7 +9,10 @@ use crate::result_query::CPRankingEntry;
 use crate::result_query::CharacterOrMusicRanking;
 use crate::result_query::CompletionRate;
 use crate::result_query::CovoteResponse;
+use crate::result_query::DojinRanking;
 use crate::result_query::GlobalStats;
+use crate::result_query::LiveRanking;
+use crate::result_query::LiveRankingKind;
 use crate::result_query::QueryQuestionnaireResponse;
 use crate::result_query::RankingEntry;
 use crate::result_query::Reasons;
@@ -25,6 +28,7 @@ use crate::submit_handler::MusicSubmitRestQuery;
 use crate::submit_handler::PaperSubmitGQL;
 use crate::submit_handler::PaperSubmitRestQuery;
 use crate::submit_handler::WorkSubmitGQL;
+use crate::submit_handler::WorkSubmitRestQuery;
 use crate::user_manager::EmailLoginInputs;
 use crate::user_manager::EmailLoginInputsForExistingVoters;
 use crate::user_manager::LoginResults;
@@ -106,6 +110,11 @@ impl Query {
 		submit_handler::getSubmitCPVote_impl(context, vote_token).await
 	}
 
+	/// Get Work
+	async fn getSubmitWorkVote(context: &Context, vote_token: String) -> FieldResult<WorkSubmitRestQuery> {
+		submit_handler::getSubmitWorkVote_impl(context, vote_token).await
+	}
+
 	/// Get Paper
 	async fn getSubmitPaperVote(context: &Context, vote_token: String) -> FieldResult<PaperSubmitRestQuery> {
 		submit_handler::getSubmitPaperVote_impl(context, vote_token).await
@@ -115,6 +124,21 @@ impl Query {
 	async fn getSubmitDojinVote(context: &Context, vote_token: String) -> FieldResult<DojinSubmitRestQuery> {
 		submit_handler::getSubmitDojinVote_impl(context, vote_token).await
 	}
+
+	/// 投票规则
+	async fn getVotingRules(context: &Context) -> FieldResult<submit_handler::VotingRules> {
+		submit_handler::getVotingRules_impl(context).await
+	}
+
+	/// 读取草稿
+	async fn getDraft(context: &Context, vote_token: String, category: submit_handler::VoteCategory) -> FieldResult<submit_handler::Draft> {
+		submit_handler::getDraft_impl(context, vote_token, category).await
+	}
+
+	/// 投票统计
+	async fn getVotingStatistics(context: &Context) -> FieldResult<submit_handler::VotingStatistics> {
+		submit_handler::getVotingStatistics_impl(context).await
+	}
 	
 	// ------------------------------------------------
 	//     result_query
@@ -154,6 +178,9 @@ impl Query {
 	async fn queryCPRanking(context: &Context, query: Option<String>, vote_start: DateTime<Utc>, vote_year: i32) -> FieldResult<CPRanking> {
 		result_query::queryCPRanking_impl(context, query, vote_start, vote_year).await
 	}
+	async fn queryDojinRanking(context: &Context, query: Option<String>, vote_start: DateTime<Utc>, vote_year: i32) -> FieldResult<DojinRanking> {
+		result_query::queryDojinRanking_impl(context, query, vote_start, vote_year).await
+	}
 	async fn queryCPReasons(context: &Context, query: Option<String>, vote_start: DateTime<Utc>, vote_year: i32, rank: i32) -> FieldResult<Reasons> {
 		result_query::queryCPReasons_impl(context, query, vote_start, vote_year, rank).await
 	}
@@ -189,6 +216,10 @@ impl Query {
 	async fn queryMusicsCovote(context: &Context, query: Option<String>, vote_start: DateTime<Utc>, vote_year: i32, top_k: i32) -> FieldResult<CovoteResponse> {
 		result_query::queryMusicsCovote_impl(context, query, vote_start, vote_year, top_k).await
 	}
+	/// 投票期间的实时排名，snapshot为空时返回最新计数
+	async fn queryLiveRanking(context: &Context, vote_year: i32, kind: LiveRankingKind, snapshot: Option<String>) -> FieldResult<LiveRanking> {
+		result_query::queryLiveRanking_impl(context, vote_year, kind, snapshot).await
+	}
 }
 
 pub struct Mutation;
@@ -251,11 +282,31 @@ impl Mutation {
 		user_manager::update_password(context, user_token, old_password, new_password).await
 	}
 
+	/// 忘记密码时向验证过的邮箱或手机发送验证码，email和phone二选一
+	async fn request_password_reset_code(context: &Context, email: Option<String>, phone: Option<String>) -> FieldResult<bool> {
+		user_manager::request_password_reset_code(context, email, phone).await
+	}
+
+	/// 使用验证码重置密码，之前的登录全部失效
+	async fn reset_password(context: &Context, email: Option<String>, phone: Option<String>, verify_code: String, new_password: String) -> FieldResult<bool> {
+		user_manager::reset_password(context, email, phone, verify_code, new_password).await
+	}
+
 	/// 账号注销
 	async fn remove_voter(context: &Context, user_token: String, old_password: Option<String>) -> FieldResult<bool> {
 		user_manager::remove_voter(context, user_token, old_password).await
 	}
 
+	/// 解绑QQ
+	async fn unbind_qq(context: &Context, user_token: String) -> FieldResult<bool> {
+		user_manager::unbind_qq(context, user_token).await
+	}
+
+	/// 关联PatchyVideo账号，patchyvideo_token由PatchyVideo签发
+	async fn link_patchyvideo(context: &Context, user_token: String, patchyvideo_token: String) -> FieldResult<bool> {
+		user_manager::link_patchyvideo(context, user_token, patchyvideo_token).await
+	}
+
 	// ------------------------------------------------
 	//     submit_handler
 	// ------------------------------------------------
@@ -275,6 +326,21 @@ impl Mutation {
 		submit_handler::submitCPVote_impl(context, &content).await
 	}
 
+	/// work
+	async fn submitWorkVote(context: &Context, content: WorkSubmitGQL) -> FieldResult<bool> {
+		submit_handler::submitWorkVote_impl(context, &content).await
+	}
+
+	/// 保存草稿，不计入投票
+	async fn saveDraft(context: &Context, vote_token: String, category: submit_handler::VoteCategory, content: String) -> FieldResult<bool> {
+		submit_handler::saveDraft_impl(context, vote_token, category, content).await
+	}
+
+	/// 撤回某一类别的投票，不指定类别时撤回所有类别，返回实际被撤回的类别
+	async fn withdrawVote(context: &Context, vote_token: String, category: Option<submit_handler::VoteCategory>) -> FieldResult<Vec<submit_handler::VoteCategory>> {
+		submit_handler::withdrawVote_impl(context, vote_token, category).await
+	}
+
 	/// paper
 	async fn submitPaperVote(context: &Context, content: PaperSubmitGQL) -> FieldResult<bool> {
 		submit_handler::submitPaperVote_impl(context, &content).await
@@ -62,6 +62,11 @@ pub struct WorkSubmitRest {
 	pub meta: SubmitMetadata
 }
 
+#[derive(juniper::GraphQLObject, Clone, Serialize, Deserialize)]
+pub struct WorkSubmitRestQuery {
+	pub works: Vec<WorkSubmitQuery>
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct CPSubmitRest {
 	pub cps: Vec<CPSubmit>,
@@ -114,7 +119,9 @@ pub struct DojinSubmitQuery {
 	pub title: String,
 	pub author: String,
 	pub reason: String,
-	pub image_url: Option<String>
+	pub image_url: Option<String>,
+	/// 作品id，相同URL的作品共用同一个id
+	pub work_id: Option<String>
 }
 
 #[derive(juniper::GraphQLObject, Clone, Serialize, Deserialize)]
@@ -239,14 +246,27 @@ pub struct WorkSubmit {
 	/// 作品名
 	pub id: String,
 	/// 理由
-	pub reason: Option<String>
+	pub reason: Option<String>,
+	/// 本命
+	pub first: Option<bool>,
+}
+
+#[derive(juniper::GraphQLObject, Clone, Serialize, Deserialize)]
+#[graphql(description="Single work submit")]
+pub struct WorkSubmitQuery {
+	/// 作品名
+	pub id: String,
+	/// 理由
+	pub reason: Option<String>,
+	/// 本命
+	pub first: Option<bool>,
 }
 
 #[derive(juniper::GraphQLInputObject, Clone)]
 #[graphql(description="Work submit")]
 pub struct WorkSubmitGQL {
 	pub vote_token: String,
-	pub work: Vec<WorkSubmit>
+	pub works: Vec<WorkSubmit>
 }
 
 #[derive(juniper::GraphQLInputObject, Clone, Serialize, Deserialize)]
@@ -282,6 +302,31 @@ pub struct QuerySubmitRest {
 	pub vote_id: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct SaveDraftRest {
+	pub vote_id: String,
+	pub category: VoteCategory,
+	pub content: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueryDraftRest {
+	pub vote_id: String,
+	pub category: VoteCategory
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WithdrawRest {
+	pub vote_id: String,
+	pub category: Option<VoteCategory>,
+	pub withdrawn_by: String
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WithdrawResponse {
+	pub withdrawn: Vec<VoteCategory>
+}
+
 #[derive(juniper::GraphQLInputObject, Clone)]
 #[graphql(description="Dojin submit")]
 pub struct DojinSubmitGQL {
@@ -302,6 +347,100 @@ pub struct VotingStatus {
 	pub papers: bool,
 	/// 同人作品是否提交
 	pub dojin: bool,
+	/// 作品是否完成
+	pub works: bool,
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(description="每小时提交数")]
+pub struct HourlySubmitCount {
+	/// 整点时间（UTC）
+	pub hour: String,
+	pub count: i32,
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(description="投票统计")]
+pub struct VotingStatistics {
+	/// 提交过任意类别的投票人数
+	pub num_user: i32,
+	/// 提交问卷的人数
+	pub num_finished_paper: i32,
+	/// 人物、音乐、CP和问卷均已提交的人数
+	pub num_finished_voting: i32,
+	pub num_character: i32,
+	pub num_cp: i32,
+	pub num_music: i32,
+	pub num_dojin: i32,
+	pub num_work: i32,
+	/// 按小时统计的提交次数
+	pub hourly_submits: Vec<HourlySubmitCount>,
+}
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteCategory {
+	Character,
+	Music,
+	Work,
+	CP,
+	Paper,
+	Dojin
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(description="草稿")]
+pub struct Draft {
+	pub category: VoteCategory,
+	/// 草稿的JSON字符串，没有草稿时为null
+	pub content: Option<String>,
+	pub saved_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+#[derive(juniper::GraphQLEnum, Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirstRule {
+	/// 必须选择一个本命
+	Required,
+	/// 最多选择一个本命
+	Optional
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(description="单个类别的投票规则")]
+pub struct CategoryRules {
+	/// 最少投票数
+	pub min_count: i32,
+	/// 最多投票数
+	pub max_count: i32,
+	/// 本命规则
+	pub first: FirstRule,
+	/// 理由最大长度（字符数）
+	pub max_reason_length: i32,
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(description="CP投票规则")]
+pub struct CPRules {
+	/// 最少投票数
+	pub min_count: i32,
+	/// 最多投票数
+	pub max_count: i32,
+	/// 本命规则
+	pub first: FirstRule,
+	/// 理由最大长度（字符数）
+	pub max_reason_length: i32,
+	/// 是否允许三人CP
+	pub allow_three_person: bool,
+}
+
+#[derive(juniper::GraphQLObject, Clone, Debug, Serialize, Deserialize)]
+#[graphql(description="本届投票规则")]
+pub struct VotingRules {
+	pub character: CategoryRules,
+	pub music: CategoryRules,
+	pub work: CategoryRules,
+	pub cp: CPRules,
 }
 
 pub fn generate_submit_metadata(vote_id: &str, context: &Context) -> SubmitMetadata {
@@ -378,6 +517,22 @@ pub async fn submitCPVote_impl(context: &Context, content: &CPSubmitGQL) -> Fiel
 	}
 }
 
+pub async fn submitWorkVote_impl(context: &Context, content: &WorkSubmitGQL) -> FieldResult<bool> {
+	let mut options = VerificationOptions::default();
+	options.allowed_audiences = Some(HashSet::from_strings(&["vote"]));
+	let result = context.public_key.public_key().verify_token::<VoteTokenClaim>(&content.vote_token, Some(options));
+	if let Ok(claim) = result {
+		let submit_json = WorkSubmitRest {
+			meta: generate_submit_metadata(&claim.custom.vote_id.ok_or(ServiceError::new_jwt_error(SERVICE_NAME, None))?, context),
+			works: content.works.clone(),
+		};
+		let post_result: EmptyJSON = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/work/", SUBMIT_HANDLER), submit_json).await?;
+		Ok(true)
+	} else {
+		return Err(ServiceError::new_jwt_error(SERVICE_NAME, None).into_field_error());
+	}
+}
+
 pub async fn submitPaperVote_impl(context: &Context, content: &PaperSubmitGQL) -> FieldResult<bool> {
 	let mut options = VerificationOptions::default();
 	options.allowed_audiences = Some(HashSet::from_strings(&["vote"]));
@@ -441,6 +596,21 @@ pub async fn getSubmitMusicVote_impl(context: &Context, vote_token: String) -> F
 	}
 }
 
+pub async fn getSubmitWorkVote_impl(context: &Context, vote_token: String) -> FieldResult<WorkSubmitRestQuery> {
+	let mut options = VerificationOptions::default();
+	options.allowed_audiences = Some(HashSet::from_strings(&["vote"]));
+	let result = context.public_key.public_key().verify_token::<VoteTokenClaim>(&vote_token, Some(options));
+	if let Ok(claim) = result {
+		let query_json = QuerySubmitRest {
+			vote_id: claim.custom.vote_id.ok_or(ServiceError::new_jwt_error(SERVICE_NAME, None))?
+		};
+		let post_result: WorkSubmitRestQuery = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/get-work/", SUBMIT_HANDLER), query_json).await?;
+		Ok(post_result)
+	} else {
+		return Err(ServiceError::new_jwt_error(SERVICE_NAME, None).into_field_error());
+	}
+}
+
 pub async fn getSubmitCPVote_impl(context: &Context, vote_token: String) -> FieldResult<CPSubmitRestQuery> {
 	let mut options = VerificationOptions::default();
 	options.allowed_audiences = Some(HashSet::from_strings(&["vote"]));
@@ -501,3 +671,63 @@ pub async fn getVotingStatus_impl(context: &Context, vote_token: String) -> Fiel
 		return Err(ServiceError::new_jwt_error(SERVICE_NAME, None).into_field_error());
 	}
 }
+
+pub async fn saveDraft_impl(context: &Context, vote_token: String, category: VoteCategory, content: String) -> FieldResult<bool> {
+	let mut options = VerificationOptions::default();
+	options.allowed_audiences = Some(HashSet::from_strings(&["vote"]));
+	let result = context.public_key.public_key().verify_token::<VoteTokenClaim>(&vote_token, Some(options));
+	if let Ok(claim) = result {
+		let query_json = SaveDraftRest {
+			vote_id: claim.custom.vote_id.ok_or(ServiceError::new_jwt_error(SERVICE_NAME, None))?,
+			category,
+			content
+		};
+		let post_result: EmptyJSON = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/save-draft/", SUBMIT_HANDLER), query_json).await?;
+		Ok(true)
+	} else {
+		return Err(ServiceError::new_jwt_error(SERVICE_NAME, None).into_field_error());
+	}
+}
+
+pub async fn withdrawVote_impl(context: &Context, vote_token: String, category: Option<VoteCategory>) -> FieldResult<Vec<VoteCategory>> {
+	let mut options = VerificationOptions::default();
+	options.allowed_audiences = Some(HashSet::from_strings(&["vote"]));
+	let result = context.public_key.public_key().verify_token::<VoteTokenClaim>(&vote_token, Some(options));
+	if let Ok(claim) = result {
+		let query_json = WithdrawRest {
+			vote_id: claim.custom.vote_id.ok_or(ServiceError::new_jwt_error(SERVICE_NAME, None))?,
+			category,
+			withdrawn_by: "voter".into()
+		};
+		let post_result: WithdrawResponse = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/withdraw/", SUBMIT_HANDLER), query_json).await?;
+		Ok(post_result.withdrawn)
+	} else {
+		return Err(ServiceError::new_jwt_error(SERVICE_NAME, None).into_field_error());
+	}
+}
+
+pub async fn getDraft_impl(context: &Context, vote_token: String, category: VoteCategory) -> FieldResult<Draft> {
+	let mut options = VerificationOptions::default();
+	options.allowed_audiences = Some(HashSet::from_strings(&["vote"]));
+	let result = context.public_key.public_key().verify_token::<VoteTokenClaim>(&vote_token, Some(options));
+	if let Ok(claim) = result {
+		let query_json = QueryDraftRest {
+			vote_id: claim.custom.vote_id.ok_or(ServiceError::new_jwt_error(SERVICE_NAME, None))?,
+			category
+		};
+		let post_result: Draft = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/get-draft/", SUBMIT_HANDLER), query_json).await?;
+		Ok(post_result)
+	} else {
+		return Err(ServiceError::new_jwt_error(SERVICE_NAME, None).into_field_error());
+	}
+}
+
+pub async fn getVotingStatistics_impl(context: &Context) -> FieldResult<VotingStatistics> {
+	let post_result: VotingStatistics = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/voting-statistics/", SUBMIT_HANDLER), EmptyJSON::new()).await?;
+	Ok(post_result)
+}
+
+pub async fn getVotingRules_impl(context: &Context) -> FieldResult<VotingRules> {
+	let post_result: VotingRules = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/voting-rules/", SUBMIT_HANDLER), EmptyJSON::new()).await?;
+	Ok(post_result)
+}
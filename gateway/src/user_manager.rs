@@ -70,6 +70,7 @@ pub struct Voter {
 	pub phone: Option<String>,
 	pub email: Option<String>,
 	pub thbwiki: bool,
+	pub qq: bool,
 	pub patchyvideo: bool,
 	pub created_at: chrono::DateTime<chrono::Utc>,
 }
@@ -160,6 +161,22 @@ pub async fn request_phone_code(context: &Context, phone: String) -> FieldResult
 }
 
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SendPasswordResetCodeRequest {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub meta: UserEventMeta
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResetPasswordInputs {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub verify_code: String,
+    pub new_password: String,
+    pub meta: UserEventMeta
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct UpdateEmailInputs {
 	pub user_token: String,
@@ -211,6 +228,19 @@ pub struct RemoveVoterRequest {
     pub meta: UserEventMeta
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UnbindQQInputs {
+	pub user_token: String,
+    pub meta: UserEventMeta
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LinkPatchyVideoInputs {
+	pub user_token: String,
+    pub patchyvideo_token: String,
+    pub meta: UserEventMeta
+}
+
 pub async fn update_email(context: &Context, user_token: String, email: String, verify_code: String) -> FieldResult<bool> {
 	let email = email.to_ascii_lowercase();
 	let submit_json = UpdateEmailInputs {
@@ -289,3 +319,55 @@ pub async fn remove_voter(context: &Context, user_token: String, old_password: O
 	Ok(true)
 }
 
+pub async fn unbind_qq(context: &Context, user_token: String) -> FieldResult<bool> {
+	let submit_json = UnbindQQInputs {
+		user_token: user_token,
+		meta: UserEventMeta {
+			user_ip: context.user_ip.clone(),
+			additional_fingureprint: context.additional_fingureprint.clone()
+		}
+	};
+	let t: EmptyJSON = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/unbind-qq", USER_MANAGER), submit_json).await?;
+	Ok(true)
+}
+
+pub async fn link_patchyvideo(context: &Context, user_token: String, patchyvideo_token: String) -> FieldResult<bool> {
+	let submit_json = LinkPatchyVideoInputs {
+		user_token: user_token,
+		patchyvideo_token: patchyvideo_token,
+		meta: UserEventMeta {
+			user_ip: context.user_ip.clone(),
+			additional_fingureprint: context.additional_fingureprint.clone()
+		}
+	};
+	let t: EmptyJSON = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/link-patchyvideo", USER_MANAGER), submit_json).await?;
+	Ok(true)
+}
+
+pub async fn request_password_reset_code(context: &Context, email: Option<String>, phone: Option<String>) -> FieldResult<bool> {
+	let submit_json = SendPasswordResetCodeRequest {
+		email: email.map(|f| f.to_ascii_lowercase()),
+		phone: phone,
+		meta: UserEventMeta {
+			user_ip: context.user_ip.clone(),
+			additional_fingureprint: context.additional_fingureprint.clone()
+		}
+	};
+	let t: EmptyJSON = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/send-password-reset-code", USER_MANAGER), submit_json).await?;
+	Ok(true)
+}
+
+pub async fn reset_password(context: &Context, email: Option<String>, phone: Option<String>, verify_code: String, new_password: String) -> FieldResult<bool> {
+	let submit_json = ResetPasswordInputs {
+		email: email.map(|f| f.to_ascii_lowercase()),
+		phone: phone,
+		verify_code: verify_code,
+		new_password: new_password,
+		meta: UserEventMeta {
+			user_ip: context.user_ip.clone(),
+			additional_fingureprint: context.additional_fingureprint.clone()
+		}
+	};
+	let t: EmptyJSON = json_request_gateway(SERVICE_NAME, &format!("http://{}/v1/reset-password", USER_MANAGER), submit_json).await?;
+	Ok(true)
+}
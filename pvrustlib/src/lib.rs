@@ -6,6 +6,8 @@ use serde::de::DeserializeOwned;
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
 
+pub mod rate_limit;
+
 #[derive(Error, Debug, Clone)]
 pub enum ServiceError {
 	#[error("Error")]
@@ -20,7 +22,10 @@ pub struct ServiceErrorResponse {
     pub error_message: Option<String>,
     pub human_readable_message: Option<String>,
     pub upstream_response_json: Option<serde_json::Value>,
-    pub upstream_response_string: Option<String>
+    pub upstream_response_string: Option<String>,
+    /// 被限流时距离可以重试的秒数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<i64>
 }
 
 impl ServiceErrorResponse {
@@ -53,7 +58,8 @@ impl ServiceError {
             error_message: None,
             human_readable_message: None,
             upstream_response_json: None,
-            upstream_response_string: None
+            upstream_response_string: None,
+            retry_after: None
         };
         ServiceError::Error { resp: resp }
     }
@@ -65,7 +71,8 @@ impl ServiceError {
             error_message: errmsg.clone(),
             human_readable_message: None,
             upstream_response_json: None,
-            upstream_response_string: None
+            upstream_response_string: None,
+            retry_after: None
         };
         ServiceError::Error { resp: resp }
     }
@@ -77,7 +84,8 @@ impl ServiceError {
             error_message: errmsg.clone(),
             human_readable_message: None,
             upstream_response_json: None,
-            upstream_response_string: None
+            upstream_response_string: None,
+            retry_after: None
         };
         ServiceError::Error { resp: resp }
     }
@@ -89,7 +97,8 @@ impl ServiceError {
             error_message: errmsg.clone(),
             human_readable_message: Some("无效凭证或不在投票日期".into()),
             upstream_response_json: None,
-            upstream_response_string: None
+            upstream_response_string: None,
+            retry_after: None
         };
         ServiceError::Error { resp: resp }
     }
@@ -101,7 +110,8 @@ impl ServiceError {
             error_message: Some(errmsg),
             human_readable_message: None,
             upstream_response_json: None,
-            upstream_response_string: None
+            upstream_response_string: None,
+            retry_after: None
         };
         ServiceError::Error { resp: resp }
     }
@@ -113,7 +123,22 @@ impl ServiceError {
             error_message: None,
             human_readable_message: Some(human_readable_message),
             upstream_response_json: None,
-            upstream_response_string: None
+            upstream_response_string: None,
+            retry_after: None
+        };
+        ServiceError::Error { resp: resp }
+    }
+    pub fn new_rate_limited(service: &str, retry_after_ms: i64) -> Self {
+        let retry_after = (retry_after_ms + 999) / 1000;
+        let resp = ServiceErrorResponse {
+            service: service.to_string(),
+            url: None,
+            error_kind: "REQUEST_TOO_FREQUENT".to_string(),
+            error_message: None,
+            human_readable_message: Some(format!("请求过于频繁，请{}秒后重试", retry_after)),
+            upstream_response_json: None,
+            upstream_response_string: None,
+            retry_after: Some(retry_after)
         };
         ServiceError::Error { resp: resp }
     }
@@ -125,7 +150,8 @@ impl ServiceError {
             error_message: resource_name,
             human_readable_message: None,
             upstream_response_json: None,
-            upstream_response_string: None
+            upstream_response_string: None,
+            retry_after: None
         };
         ServiceError::Error { resp: resp }
     }
@@ -143,7 +169,11 @@ impl ResponseError for ServiceError {
         let resp = match self {
             ServiceError::Error { resp } => resp,
         };
-		HttpResponse::build(status_code).json(resp)
+		let mut builder = HttpResponse::build(status_code);
+		if let Some(retry_after) = resp.retry_after {
+			builder.insert_header((actix_web::http::header::RETRY_AFTER, retry_after.to_string()));
+		}
+		builder.json(resp)
 	}
 }
 
@@ -157,6 +187,7 @@ impl<S: ScalarValue> IntoFieldError<S> for ServiceError {
                 let error_message = resp.error_message;
                 let human_readable_message = resp.human_readable_message;
                 let upstream_response_string = resp.upstream_response_string;
+                let retry_after = resp.retry_after.map(|f| f as i32);
                 if let Some(upstream_response_json) = &resp.upstream_response_json {
                     println!("{:?}", upstream_response_json);
                 }
@@ -168,7 +199,8 @@ impl<S: ScalarValue> IntoFieldError<S> for ServiceError {
                         "error_kind": error_kind,
                         "error_message": error_message,
                         "human_readable_message": human_readable_message,
-                        "upstream_response_string": upstream_response_string
+                        "upstream_response_string": upstream_response_string,
+                        "retry_after": retry_after
                     }),
 				)
 			}
@@ -0,0 +1,66 @@
+
+use chrono::Utc;
+
+use crate::ServiceError;
+
+/// 令牌桶，桶中令牌数和上次更新时间保存在同一个hash中，在脚本内一次完成补充和扣除
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local capacity = tonumber(ARGV[1])
+local refill_per_ms = tonumber(ARGV[2])
+local now = tonumber(ARGV[3])
+local cost = tonumber(ARGV[4])
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(bucket[1])
+local ts = tonumber(bucket[2])
+if tokens == nil or ts == nil then
+	tokens = capacity
+	ts = now
+end
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * refill_per_ms)
+local allowed = 0
+local retry_after_ms = 0
+if tokens >= cost then
+	tokens = tokens - cost
+	allowed = 1
+else
+	retry_after_ms = math.ceil((cost - tokens) / refill_per_ms)
+end
+redis.call('HMSET', KEYS[1], 'tokens', tostring(tokens), 'ts', tostring(now))
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity / refill_per_ms) + 1000)
+return {allowed, math.floor(tokens), retry_after_ms}
+"#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitStatus {
+	/// 本次请求是否被允许
+	pub allowed: bool,
+	/// 桶中剩余令牌数
+	pub remaining: i64,
+	/// 被拒绝时，距离下一个令牌可用的毫秒数
+	pub retry_after_ms: i64
+}
+
+/// 从`uid`对应的令牌桶中取出一个令牌。桶容量为`capacity`，每`window_seconds`秒补满
+pub async fn acquire_token(service: &str, uid: &impl std::fmt::Display, capacity: i64, window_seconds: i64, conn: &mut redis::aio::Connection) -> Result<RateLimitStatus, ServiceError> {
+	let key = format!("rate-limit-{}", uid);
+	let refill_per_ms = capacity as f64 / (window_seconds * 1000) as f64;
+	let (allowed, remaining, retry_after_ms): (i64, i64, i64) = redis::Script::new(TOKEN_BUCKET_SCRIPT)
+		.key(key)
+		.arg(capacity)
+		.arg(refill_per_ms)
+		.arg(Utc::now().timestamp_millis())
+		.arg(1)
+		.invoke_async(conn)
+		.await
+		.map_err(|e| ServiceError::from_dyn_error(service, Box::new(e)))?;
+	Ok(RateLimitStatus { allowed: allowed == 1, remaining, retry_after_ms })
+}
+
+/// Rate limiting using token bucket, returns REQUEST_TOO_FREQUENT with retry_after when the bucket is empty
+pub async fn rate_limit(service: &str, uid: &impl std::fmt::Display, capacity: i64, window_seconds: i64, conn: &mut redis::aio::Connection) -> Result<RateLimitStatus, ServiceError> {
+	let status = acquire_token(service, uid, capacity, window_seconds, conn).await?;
+	if !status.allowed {
+		return Err(ServiceError::new_rate_limited(service, status.retry_after_ms));
+	}
+	Ok(status)
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+
+use serde_derive::{Serialize, Deserialize};
+
+use crate::{common::SERVICE_NAME, service_error::ServiceError};
+
+/// 管理接口的令牌，对应config.toml中的[admin]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AdminConfig {
+	/// 令牌到管理员名字，未配置时所有管理接口都不可用
+	#[serde(default)]
+	pub tokens: HashMap<String, String>
+}
+
+impl AdminConfig {
+	/// 返回令牌对应的管理员名字
+	pub fn authenticate(&self, admin_token: &str) -> Result<String, ServiceError> {
+		match self.tokens.get(admin_token) {
+			Some(name) if !admin_token.is_empty() => Ok(name.clone()),
+			_ => Err(ServiceError::new_error_kind(SERVICE_NAME, "ADMIN_TOKEN_INVALID"))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_configured_tokens_authenticate() {
+		let config: AdminConfig = toml::from_str("[tokens]\nsecret-token = \"alice\"\n").unwrap();
+		assert_eq!(config.authenticate("secret-token").unwrap(), "alice");
+		let error_kind = match config.authenticate("other-token") {
+			Err(ServiceError::Error { resp }) => resp.error_kind,
+			Ok(_) => panic!("unknown token authenticated")
+		};
+		assert_eq!(error_kind, "ADMIN_TOKEN_INVALID");
+		assert!(config.authenticate("").is_err());
+		assert!(AdminConfig::default().authenticate("secret-token").is_err());
+	}
+}
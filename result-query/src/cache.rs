@@ -0,0 +1,121 @@
+use bson::{doc, Document, oid::ObjectId};
+use mongodb::{Collection, options::{FindOneAndUpdateOptions, ReturnDocument}};
+
+use crate::{common::SERVICE_NAME, context::AppContext, models::CacheBumpResponse, query, service_error::ServiceError};
+
+/// 某届缓存的当前版本，从未清除过时为0
+pub async fn current_generation(ctx: &AppContext, vote_year: i32) -> Result<i64, ServiceError> {
+	let current = ctx.cache_generations_coll.find_one(doc! { "_id": vote_year }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(current.map_or(0, |f| f.generation))
+}
+
+/// 使某届的全部缓存失效，并删除旧版本的缓存文档
+///
+/// votes重新生成后调用，之后的查询会按新版本重新计算并写入缓存
+pub async fn bump_generation(ctx: &AppContext, vote_year: i32) -> Result<CacheBumpResponse, ServiceError> {
+	let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+	let update = doc! {
+		"$inc": { "generation": 1i64 },
+		"$set": { "bumped_at": bson::DateTime::now() }
+	};
+	let bumped = ctx.cache_generations_coll.find_one_and_update(doc! { "_id": vote_year }, update, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let generation = bumped.map_or(1, |f| f.generation);
+	let stale = stale_generations(vote_year, generation);
+	let colls = [
+		ctx.chars_entry_cache_coll.name(),
+		ctx.chars_global_cache_coll.name(),
+		ctx.musics_entry_cache_coll.name(),
+		ctx.musics_global_cache_coll.name(),
+		ctx.cps_entry_cache_coll.name(),
+		ctx.cps_global_cache_coll.name(),
+		ctx.dojins_entry_cache_coll.name(),
+		ctx.dojins_global_cache_coll.name(),
+		ctx.global_stats.name(),
+		ctx.completion_rates.name(),
+		ctx.paper_result.name(),
+		ctx.covote_chars.name(),
+		ctx.covote_musics.name(),
+	];
+	let mut purged = 0;
+	for coll in colls {
+		let result = ctx.db.collection::<Document>(coll).delete_many(stale.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		purged += result.deleted_count;
+	}
+	Ok(CacheBumpResponse {
+		vote_year,
+		generation,
+		purged
+	})
+}
+
+/// 某届中不属于generation的缓存
+///
+/// 加入版本之前写入的缓存没有generation字段，同样会被匹配
+pub fn stale_generations(vote_year: i32, generation: i64) -> Document {
+	doc! {
+		"vote_year": vote_year,
+		"generation": { "$ne": generation }
+	}
+}
+
+/// 同一版本中比batch更早写入的缓存
+///
+/// 并发的缓存未命中会各自写入一批，写入中断时也会留下没有global的条目。
+/// 只删除更早的batch，不会误删其他请求正在写入的更新的batch
+pub fn superseded_batches(key: &str, vote_year: i32, generation: i64, batch: ObjectId) -> Document {
+	doc! {
+		"key": key,
+		"vote_year": vote_year,
+		"generation": generation,
+		"$or": [
+			{ "batch": { "$lt": batch } },
+			{ "batch": null }
+		]
+	}
+}
+
+/// 写入新的一批缓存后删除同一查询被取代的旧批次
+pub async fn purge_superseded_batches<E, G>(entry_coll: &Collection<E>, global_coll: &Collection<G>, key: &str, vote_year: i32, generation: i64, batch: ObjectId) -> Result<(), ServiceError> {
+	let filter = superseded_batches(key, vote_year, generation, batch);
+	// 先删global，读取方不会找到已经没有条目的global
+	global_coll.delete_many(filter.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	entry_coll.delete_many(filter, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(())
+}
+
+/// 预先计算不带筛选条件的常用查询，写入当前版本的缓存
+pub async fn warmup(ctx: &AppContext, vote_start: bson::DateTime, vote_year: i32) -> Result<(), ServiceError> {
+	query::chars_ranking(ctx, None, vote_start, vote_year).await?;
+	query::musics_ranking(ctx, None, vote_start, vote_year).await?;
+	query::cps_ranking(ctx, None, vote_start, vote_year).await?;
+	query::dojins_ranking(ctx, None, vote_start, vote_year).await?;
+	query::global_stats(ctx, vote_start, vote_year, None).await?;
+	query::completion_rates(ctx, None, vote_start, vote_year).await?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn stale_generations_keep_only_current_generation() {
+		let filter = stale_generations(2022, 3);
+		assert_eq!(filter.get_i32("vote_year").unwrap(), 2022);
+		assert_eq!(filter.get_document("generation").unwrap(), &doc! { "$ne": 3i64 });
+	}
+
+	#[test]
+	fn superseded_batches_are_older_batches_of_same_query() {
+		let older = ObjectId::new();
+		let batch = ObjectId::new();
+		assert!(older < batch);
+		let filter = superseded_batches("q", 2022, 3, batch);
+		assert_eq!(filter.get_str("key").unwrap(), "q");
+		assert_eq!(filter.get_i32("vote_year").unwrap(), 2022);
+		assert_eq!(filter.get_i64("generation").unwrap(), 3);
+		let alternatives = filter.get_array("$or").unwrap();
+		assert_eq!(alternatives[0].as_document().unwrap(), &doc! { "batch": { "$lt": batch } });
+		assert_eq!(alternatives[1].as_document().unwrap(), &doc! { "batch": null });
+	}
+}
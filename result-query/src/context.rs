@@ -4,7 +4,8 @@ use std::cell::Cell;
 use bson::Document;
 use mongodb::{Collection, Database};
 
-use crate::models::{CachedRankingEntry, CachedRankingGlobal, CachedCPRankingEntry, PartialVoteItemEntry, GlobalStats, CompletionRate, CachedQuestionEntry, CachedCovote, FinalRanking};
+use crate::admin::AdminConfig;
+use crate::models::{CachedRankingEntry, CachedRankingGlobal, CachedCPRankingEntry, CachedDojinRankingEntry, PartialVoteItemEntry, GlobalStats, CompletionRate, CachedQuestionEntry, CachedCovote, FinalRanking, LiveCounter, LiveGlobal, LiveAppliedVote, LiveSnapshot, LiveSyncState, CacheGeneration};
 
 
 #[derive(Clone, Debug)]
@@ -18,6 +19,8 @@ pub struct AppContext {
     pub musics_global_cache_coll: Collection<CachedRankingGlobal>,
     pub cps_entry_cache_coll: Collection<CachedCPRankingEntry>,
     pub cps_global_cache_coll: Collection<CachedRankingGlobal>,
+    pub dojins_entry_cache_coll: Collection<CachedDojinRankingEntry>,
+    pub dojins_global_cache_coll: Collection<CachedRankingGlobal>,
     pub all_chars: Collection<PartialVoteItemEntry>,
     pub all_musics: Collection<PartialVoteItemEntry>,
     pub global_stats: Collection<GlobalStats>,
@@ -27,4 +30,12 @@ pub struct AppContext {
     pub covote_chars: Collection<CachedCovote>,
     pub final_ranking_music: Collection<FinalRanking>,
     pub final_ranking_char: Collection<FinalRanking>,
+    pub live_counters_coll: Collection<LiveCounter>,
+    pub live_globals_coll: Collection<LiveGlobal>,
+    pub live_applied_coll: Collection<LiveAppliedVote>,
+    pub live_snapshots_coll: Collection<LiveSnapshot>,
+    pub live_sync_state_coll: Collection<LiveSyncState>,
+    pub cache_generations_coll: Collection<CacheGeneration>,
+    /// 管理接口令牌
+    pub admin: AdminConfig,
 }
@@ -5,7 +5,7 @@ use actix_web::{App, HttpMessage, HttpRequest, HttpServer, Responder, web};
 use bson::oid::ObjectId;
 
 
-use crate::{models::{self}, context::AppContext, service_error::ServiceError, query, common::SERVICE_NAME};
+use crate::{models::{self}, context::AppContext, service_error::ServiceError, query, live, cache, common::SERVICE_NAME};
 
 
 pub async fn chars_rank(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::RankingQueryRequest>) -> Result<web::Json<models::RankingQueryResponse>, ServiceError> {
@@ -77,6 +77,15 @@ pub async fn cps_rank(ctx: web::Data<AppContext>, request: HttpRequest, body: ac
 	Ok(web::Json(resp))
 }
 
+pub async fn dojins_rank(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::RankingQueryRequest>) -> Result<web::Json<models::DojinRankingQueryResponse>, ServiceError> {
+	if body.query.as_ref().map(|f| f.len()).unwrap_or_default() > 1000 {
+		// query too long
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "QUERY_TOO_LONG", "查询过长".into()));
+	};
+	let resp = query::dojins_ranking(&ctx, body.query.clone(), bson::DateTime::from_chrono(body.vote_start), body.vote_year).await?;
+	Ok(web::Json(resp))
+}
+
 pub async fn cps_reasons(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::ReasonsRequest>) -> Result<web::Json<models::ReasonsResponse>, ServiceError> {
 	if body.query.as_ref().map(|f| f.len()).unwrap_or_default() > 1000 {
 		// query too long
@@ -196,3 +205,17 @@ pub async fn cps_single(ctx: web::Data<AppContext>, request: HttpRequest, body:
 	let resp = query::cps_single(&ctx, body.query.clone(), bson::DateTime::from_chrono(body.vote_start), body.vote_year, body.rank).await?;
 	Ok(web::Json(resp))
 }
+
+pub async fn live_rank(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::LiveRankingRequest>) -> Result<web::Json<models::LiveRankingResponse>, ServiceError> {
+	let resp = match body.snapshot.clone() {
+		Some(name) => live::snapshot(&ctx, body.vote_year, body.kind, name).await?,
+		None => live::ranking(&ctx, body.vote_year, body.kind).await?
+	};
+	Ok(web::Json(resp))
+}
+
+pub async fn cache_bump(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::CacheBumpRequest>) -> Result<web::Json<models::CacheBumpResponse>, ServiceError> {
+	ctx.admin.authenticate(&body.admin_token)?;
+	let resp = cache::bump_generation(&ctx, body.vote_year).await?;
+	Ok(web::Json(resp))
+}
@@ -0,0 +1,450 @@
+use std::collections::{HashMap, HashSet};
+
+use bson::{doc, Document};
+use chrono::Utc;
+use futures::stream::TryStreamExt;
+use itertools::Itertools;
+use mongodb::options::{ReplaceOptions, UpdateOptions};
+
+use crate::{common::SERVICE_NAME, context::AppContext, models::{CPItem, LiveAppliedVote, LiveContribution, LiveKindContribution, LivePublishResponse, LiveRankingEntry, LiveRankingKind, LiveRankingResponse, LiveSnapshot, LiveSyncResponse, LiveSyncState, RankingGlobal, VotingTrendItem}, query::PartialVote, service_error::ServiceError};
+
+const LIVE_KINDS: [LiveRankingKind; 3] = [LiveRankingKind::Chars, LiveRankingKind::Musics, LiveRankingKind::Cps];
+
+fn hours_since(created_at: bson::DateTime, vote_start: bson::DateTime) -> i32 {
+	(created_at.to_chrono() - vote_start.to_chrono()).num_hours() as i32
+}
+
+/// 与votes中的cps_str一致
+fn cp_name(cp: &CPItem) -> String {
+	let mut members = vec![cp.a.as_str(), cp.b.as_str()];
+	if let Some(c) = &cp.c {
+		members.push(c.as_str());
+	}
+	members.sort();
+	members.join("x")
+}
+
+/// 一张投票计入实时计数的内容，与chars_ranking等的计数方式一致
+pub fn contribution(pv: &PartialVote, vote_start: bson::DateTime) -> LiveContribution {
+	let chars = match (&pv.chars, &pv.chars_meta) {
+		(Some(chars), Some(meta)) if !chars.is_empty() => Some(LiveKindContribution {
+			items: chars.iter().map(|f| f.name.clone()).collect(),
+			first: pv.chars_first.as_ref().and_then(|f| f.first().cloned()),
+			hrs: hours_since(meta.created_at, vote_start)
+		}),
+		_ => None
+	};
+	let musics = match (&pv.musics, &pv.musics_meta) {
+		(Some(musics), Some(meta)) if !musics.is_empty() => Some(LiveKindContribution {
+			items: musics.iter().map(|f| f.name.clone()).collect(),
+			first: pv.musics_first.as_ref().and_then(|f| f.first().cloned()),
+			hrs: hours_since(meta.created_at, vote_start)
+		}),
+		_ => None
+	};
+	let cps = match (&pv.cps, &pv.cps_meta) {
+		(Some(cps), Some(meta)) if !cps.is_empty() => Some(LiveKindContribution {
+			items: cps.iter().map(cp_name).collect(),
+			first: pv.cps_first.as_ref().and_then(|f| f.first()).map(cp_name),
+			hrs: hours_since(meta.created_at, vote_start)
+		}),
+		_ => None
+	};
+	LiveContribution {
+		is_male: pv.q11011.opt.first().map_or(false, |f| f == "1101101"),
+		chars,
+		musics,
+		cps
+	}
+}
+
+#[derive(Debug, Default)]
+struct CounterDelta {
+	vote_count: i32,
+	first_vote_count: i32,
+	male_vote_count: i32,
+	female_vote_count: i32,
+	trend: HashMap<i32, i32>,
+	trend_first: HashMap<i32, i32>
+}
+
+#[derive(Debug, Default)]
+struct GlobalDelta {
+	total_votes: i32,
+	total_first: i32,
+	total_male: i32,
+	total_female: i32
+}
+
+/// 一次同步中所有计数的变化，最后一次性写入
+#[derive(Debug, Default)]
+struct LiveDeltas {
+	counters: HashMap<(LiveRankingKind, String), CounterDelta>,
+	globals: HashMap<LiveRankingKind, GlobalDelta>
+}
+
+impl LiveDeltas {
+	/// sign为1时计入，为-1时扣除
+	fn add(&mut self, contribution: &LiveContribution, sign: i32) {
+		let kinds = [
+			(LiveRankingKind::Chars, &contribution.chars),
+			(LiveRankingKind::Musics, &contribution.musics),
+			(LiveRankingKind::Cps, &contribution.cps)
+		];
+		for (kind, votes) in kinds {
+			let votes = match votes {
+				Some(votes) => votes,
+				None => continue
+			};
+			let global = self.globals.entry(kind).or_default();
+			global.total_votes += sign;
+			if contribution.is_male {
+				global.total_male += sign;
+			} else {
+				global.total_female += sign;
+			}
+			if let Some(first) = &votes.first {
+				global.total_first += sign;
+				let counter = self.counters.entry((kind, first.clone())).or_default();
+				counter.first_vote_count += sign;
+				*counter.trend_first.entry(votes.hrs).or_default() += sign;
+			}
+			for item in votes.items.iter() {
+				let counter = self.counters.entry((kind, item.clone())).or_default();
+				counter.vote_count += sign;
+				if contribution.is_male {
+					counter.male_vote_count += sign;
+				} else {
+					counter.female_vote_count += sign;
+				}
+				*counter.trend.entry(votes.hrs).or_default() += sign;
+			}
+		}
+	}
+
+	async fn write(self, ctx: &AppContext, vote_year: i32) -> Result<(), ServiceError> {
+		let upsert = || UpdateOptions::builder().upsert(true).build();
+		for ((kind, name), delta) in self.counters {
+			let mut inc = Document::new();
+			for (key, value) in [("vote_count", delta.vote_count), ("first_vote_count", delta.first_vote_count), ("male_vote_count", delta.male_vote_count), ("female_vote_count", delta.female_vote_count)] {
+				if value != 0 {
+					inc.insert(key, value);
+				}
+			}
+			for (hrs, value) in delta.trend.iter().filter(|(_, v)| **v != 0) {
+				inc.insert(format!("trend.{}", hrs), *value);
+			}
+			for (hrs, value) in delta.trend_first.iter().filter(|(_, v)| **v != 0) {
+				inc.insert(format!("trend_first.{}", hrs), *value);
+			}
+			if inc.is_empty() {
+				continue;
+			}
+			let update = doc! {
+				"$inc": inc,
+				"$setOnInsert": { "vote_year": vote_year, "kind": kind.name(), "name": &name }
+			};
+			ctx.live_counters_coll.update_one(doc! { "_id": format!("{}-{}-{}", vote_year, kind.name(), name) }, update, upsert()).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		}
+		for (kind, delta) in self.globals {
+			let update = doc! {
+				"$inc": {
+					"total_votes": delta.total_votes,
+					"total_first": delta.total_first,
+					"total_male": delta.total_male,
+					"total_female": delta.total_female
+				},
+				"$setOnInsert": { "vote_year": vote_year, "kind": kind.name() }
+			};
+			ctx.live_globals_coll.update_one(doc! { "_id": format!("{}-{}", vote_year, kind.name()) }, update, upsert()).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		}
+		Ok(())
+	}
+}
+
+/// 同步和发布共用的锁，已被占用时返回SYNC_IN_PROGRESS
+fn try_lock(ctx: &AppContext, vote_year: i32, ttl: usize) -> Result<redlock::Lock<'_>, ServiceError> {
+	let lockid = format!("lock-live-{}", vote_year);
+	ctx.lock.lock(lockid.as_bytes(), ttl).ok_or_else(|| ServiceError::new_human_readable(SERVICE_NAME, "SYNC_IN_PROGRESS", "实时计数正在同步或发布".into()))
+}
+
+/// 比较已计入的投票和votes中的投票，返回计数变化、新增或变化的投票和已被删除的投票
+fn diff_contributions(applied: &HashMap<String, LiveContribution>, current: Vec<(String, LiveContribution)>) -> (LiveDeltas, Vec<(String, LiveContribution)>, Vec<String>) {
+	let mut deltas = LiveDeltas::default();
+	let mut seen = HashSet::new();
+	let mut changed = vec![];
+	for (vote_id, new) in current {
+		seen.insert(vote_id.clone());
+		match applied.get(&vote_id) {
+			Some(old) if *old == new => continue,
+			Some(old) => deltas.add(old, -1),
+			None => {}
+		};
+		deltas.add(&new, 1);
+		changed.push((vote_id, new));
+	}
+	let removed: Vec<String> = applied.keys().filter(|f| !seen.contains(*f)).sorted().cloned().collect();
+	for vote_id in removed.iter() {
+		deltas.add(&applied[vote_id], -1);
+	}
+	(deltas, changed, removed)
+}
+
+/// 将votes中新增、变化和被删除的投票计入实时计数
+///
+/// 每次同步仍需读取votes，但只写入变化的部分，排名查询不再读取votes。
+/// 写入前标记同步进行中，上一次同步中途失败时标记仍在，本次自动清空后重新计算，重试不会重复计数
+pub async fn sync(ctx: &AppContext, vote_start: bson::DateTime, vote_year: i32, rebuild: bool) -> Result<LiveSyncResponse, ServiceError> {
+	let lock = try_lock(ctx, vote_year, 10 * 60 * 1000)?;
+	let result = sync_locked(ctx, vote_start, vote_year, rebuild).await;
+	ctx.lock.unlock(&lock);
+	result
+}
+
+async fn set_sync_in_progress(ctx: &AppContext, vote_year: i32, in_progress: bool) -> Result<(), ServiceError> {
+	let state = LiveSyncState { vote_year, in_progress, updated_at: bson::DateTime::now() };
+	let options = ReplaceOptions::builder().upsert(true).build();
+	ctx.live_sync_state_coll.replace_one(doc! { "_id": vote_year }, state, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(())
+}
+
+async fn sync_locked(ctx: &AppContext, vote_start: bson::DateTime, vote_year: i32, rebuild: bool) -> Result<LiveSyncResponse, ServiceError> {
+	let state = ctx.live_sync_state_coll.find_one(doc! { "_id": vote_year }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let interrupted = state.map_or(false, |f| f.in_progress);
+	if interrupted {
+		println!("live: previous sync for {} was interrupted, rebuilding", vote_year);
+	}
+	set_sync_in_progress(ctx, vote_year, true).await?;
+	if rebuild || interrupted {
+		for coll in [ctx.live_counters_coll.name(), ctx.live_globals_coll.name(), ctx.live_applied_coll.name()] {
+			ctx.db.collection::<Document>(coll).delete_many(doc! { "vote_year": vote_year }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		}
+	}
+	let mut applied: HashMap<String, LiveContribution> = HashMap::new();
+	let mut applied_cursor = ctx.live_applied_coll.find(doc! { "vote_year": vote_year }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	while let Some(item) = applied_cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		applied.insert(item.vote_id, item.contribution);
+	}
+
+	let mut current = vec![];
+	let mut votes_cursor = ctx.votes_coll.find(doc! { "vote_year": vote_year }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	// 读取中断时不能把剩下的投票当作已删除
+	while let Some(vote) = votes_cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		// 只有finalize生成的投票带有vote_id
+		let vote_id = match vote.get_str("vote_id") {
+			Ok(vote_id) => vote_id.to_string(),
+			Err(_) => continue
+		};
+		let pv: PartialVote = match bson::from_document(vote) {
+			Ok(pv) => pv,
+			Err(_) => continue
+		};
+		current.push((vote_id, contribution(&pv, vote_start)));
+	}
+	let (deltas, changed, removed) = diff_contributions(&applied, current);
+
+	deltas.write(ctx, vote_year).await?;
+	for (vote_id, contribution) in changed.iter() {
+		let item = LiveAppliedVote { id: format!("{}-{}", vote_year, vote_id), vote_year, vote_id: vote_id.clone(), contribution: contribution.clone() };
+		let options = ReplaceOptions::builder().upsert(true).build();
+		ctx.live_applied_coll.replace_one(doc! { "_id": &item.id }, item, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	}
+	for vote_id in removed.iter() {
+		ctx.live_applied_coll.delete_one(doc! { "_id": format!("{}-{}", vote_year, vote_id) }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	}
+	set_sync_in_progress(ctx, vote_year, false).await?;
+	Ok(LiveSyncResponse {
+		changed_votes: changed.len() as i32,
+		removed_votes: removed.len() as i32
+	})
+}
+
+fn to_trend(bins: &std::collections::BTreeMap<String, i32>) -> Vec<VotingTrendItem> {
+	bins.iter()
+		.filter(|(_, cnt)| **cnt != 0)
+		.filter_map(|(hrs, cnt)| hrs.parse().ok().map(|hrs| VotingTrendItem { hrs, cnt: *cnt }))
+		.sorted_by_key(|f| f.hrs)
+		.collect()
+}
+
+/// 从实时计数生成排名，只读取计数，不读取votes
+pub async fn ranking(ctx: &AppContext, vote_year: i32, kind: LiveRankingKind) -> Result<LiveRankingResponse, ServiceError> {
+	let global = ctx.live_globals_coll.find_one(doc! { "_id": format!("{}-{}", vote_year, kind.name()) }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let (total_votes, total_first) = global.as_ref().map_or((0, 0), |f| (f.total_votes, f.total_first));
+	let mut counters = vec![];
+	let mut cursor = ctx.live_counters_coll.find(doc! { "vote_year": vote_year, "kind": kind.name(), "vote_count": { "$gt": 0 } }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	while let Some(counter) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		counters.push(counter);
+	}
+	counters.sort_by(|a, b| b.vote_count.cmp(&a.vote_count).then(b.first_vote_count.cmp(&a.first_vote_count)).then(a.name.cmp(&b.name)));
+
+	let mut entries = Vec::with_capacity(counters.len());
+	let mut display_rank = 1;
+	let mut last_votes = 0;
+	for (i, counter) in counters.iter().enumerate() {
+		let rank = i as i32 + 1;
+		if last_votes != counter.vote_count {
+			display_rank = rank;
+		}
+		last_votes = counter.vote_count;
+		entries.push(LiveRankingEntry {
+			rank,
+			display_rank,
+			name: counter.name.clone(),
+			vote_count: counter.vote_count,
+			first_vote_count: counter.first_vote_count,
+			vote_percentage: counter.vote_count as f64 / total_votes.max(1) as f64,
+			first_percentage: counter.first_vote_count as f64 / total_first.max(1) as f64,
+			male_vote_count: counter.male_vote_count,
+			female_vote_count: counter.female_vote_count,
+			male_percentage_per_char: counter.male_vote_count as f64 / counter.vote_count as f64,
+			female_percentage_per_char: counter.female_vote_count as f64 / counter.vote_count as f64,
+			trend: to_trend(&counter.trend),
+			trend_first: to_trend(&counter.trend_first)
+		});
+	}
+
+	let num_items = entries.len();
+	let median = match num_items {
+		0 => 0f64,
+		n if n % 2 == 0 => 0.5f64 * (entries[n / 2 - 1].vote_count + entries[n / 2].vote_count) as f64,
+		n => entries[n / 2].vote_count as f64
+	};
+	let global = RankingGlobal {
+		total_unique_items: num_items as _,
+		total_first,
+		total_votes,
+		average_votes_per_item: if num_items == 0 { 0f64 } else { total_votes as f64 / num_items as f64 },
+		median_votes_per_item: median
+	};
+	Ok(LiveRankingResponse {
+		kind,
+		vote_year,
+		snapshot: None,
+		generated_at: Utc::now(),
+		entries,
+		global
+	})
+}
+
+pub async fn snapshot(ctx: &AppContext, vote_year: i32, kind: LiveRankingKind, name: String) -> Result<LiveRankingResponse, ServiceError> {
+	let snapshot = ctx.live_snapshots_coll.find_one(doc! { "vote_year": vote_year, "kind": kind.name(), "name": &name }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	snapshot.map(|f| f.ranking).ok_or_else(|| ServiceError::new_not_found(SERVICE_NAME, Some(name)))
+}
+
+/// 冻结当前所有类别的实时排名，快照名同一年内不能重复
+pub async fn publish(ctx: &AppContext, vote_year: i32, name: String) -> Result<LivePublishResponse, ServiceError> {
+	if name.is_empty() || name.len() > 100 {
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_SNAPSHOT_NAME", "无效的快照名".into()));
+	}
+	// 与同步使用同一把锁，保证快照中的各类别来自同一次同步
+	let lock = try_lock(ctx, vote_year, 60 * 1000)?;
+	let result = publish_locked(ctx, vote_year, name).await;
+	ctx.lock.unlock(&lock);
+	result
+}
+
+async fn publish_locked(ctx: &AppContext, vote_year: i32, name: String) -> Result<LivePublishResponse, ServiceError> {
+	let existing = ctx.live_snapshots_coll.count_documents(doc! { "vote_year": vote_year, "name": &name }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	if existing != 0 {
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "SNAPSHOT_EXISTS", format!("快照{}已存在", name)));
+	}
+	let published_at = Utc::now();
+	let mut snapshots = vec![];
+	for kind in LIVE_KINDS {
+		let mut ranking = ranking(ctx, vote_year, kind).await?;
+		ranking.snapshot = Some(name.clone());
+		snapshots.push(LiveSnapshot {
+			name: name.clone(),
+			vote_year,
+			kind,
+			published_at,
+			ranking
+		});
+	}
+	ctx.live_snapshots_coll.insert_many(snapshots, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(LivePublishResponse {
+		name,
+		published_at
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn chars(items: &[&str], first: Option<&str>, hrs: i32, is_male: bool) -> LiveContribution {
+		LiveContribution {
+			is_male,
+			chars: Some(LiveKindContribution {
+				items: items.iter().map(|f| f.to_string()).collect(),
+				first: first.map(|f| f.to_string()),
+				hrs
+			}),
+			musics: None,
+			cps: None
+		}
+	}
+
+	fn counter<'a>(deltas: &'a LiveDeltas, name: &str) -> &'a CounterDelta {
+		&deltas.counters[&(LiveRankingKind::Chars, name.to_string())]
+	}
+
+	#[test]
+	fn deltas_add_and_subtract_contributions() {
+		let mut deltas = LiveDeltas::default();
+		deltas.add(&chars(&["博丽灵梦", "雾雨魔理沙"], Some("博丽灵梦"), 3, true), 1);
+		deltas.add(&chars(&["博丽灵梦"], None, 5, false), 1);
+		let reimu = counter(&deltas, "博丽灵梦");
+		assert_eq!((reimu.vote_count, reimu.first_vote_count, reimu.male_vote_count, reimu.female_vote_count), (2, 1, 1, 1));
+		assert_eq!(reimu.trend[&3], 1);
+		assert_eq!(reimu.trend[&5], 1);
+		assert_eq!(reimu.trend_first[&3], 1);
+		let global = &deltas.globals[&LiveRankingKind::Chars];
+		assert_eq!((global.total_votes, global.total_first, global.total_male, global.total_female), (2, 1, 1, 1));
+		assert!(!deltas.globals.contains_key(&LiveRankingKind::Musics));
+
+		deltas.add(&chars(&["博丽灵梦", "雾雨魔理沙"], Some("博丽灵梦"), 3, true), -1);
+		let reimu = counter(&deltas, "博丽灵梦");
+		assert_eq!((reimu.vote_count, reimu.first_vote_count, reimu.male_vote_count), (1, 0, 0));
+		assert_eq!(reimu.trend[&3], 0);
+		assert_eq!(counter(&deltas, "雾雨魔理沙").vote_count, 0);
+		assert_eq!(deltas.globals[&LiveRankingKind::Chars].total_votes, 1);
+	}
+
+	#[test]
+	fn diff_counts_added_changed_and_removed_votes() {
+		let mut applied = HashMap::new();
+		applied.insert("unchanged".to_string(), chars(&["博丽灵梦"], None, 1, true));
+		applied.insert("changed".to_string(), chars(&["雾雨魔理沙"], None, 1, true));
+		applied.insert("removed".to_string(), chars(&["东风谷早苗"], Some("东风谷早苗"), 1, false));
+		let current = vec![
+			("unchanged".to_string(), chars(&["博丽灵梦"], None, 1, true)),
+			("changed".to_string(), chars(&["博丽灵梦"], None, 2, true)),
+			("added".to_string(), chars(&["雾雨魔理沙"], Some("雾雨魔理沙"), 4, false))
+		];
+		let (deltas, changed, removed) = diff_contributions(&applied, current);
+		let changed: Vec<&str> = changed.iter().map(|(f, _)| f.as_str()).collect();
+		assert_eq!(changed, vec!["changed", "added"]);
+		assert_eq!(removed, vec!["removed".to_string()]);
+
+		// changed: 魔理沙 -> 灵梦；added: +魔理沙；removed: -早苗
+		assert_eq!(counter(&deltas, "博丽灵梦").vote_count, 1);
+		assert_eq!(counter(&deltas, "博丽灵梦").trend[&2], 1);
+		let marisa = counter(&deltas, "雾雨魔理沙");
+		assert_eq!((marisa.vote_count, marisa.first_vote_count, marisa.male_vote_count, marisa.female_vote_count), (0, 1, -1, 1));
+		let sanae = counter(&deltas, "东风谷早苗");
+		assert_eq!((sanae.vote_count, sanae.first_vote_count), (-1, -1));
+		let global = &deltas.globals[&LiveRankingKind::Chars];
+		assert_eq!((global.total_votes, global.total_first, global.total_male, global.total_female), (0, 0, 0, 0));
+	}
+
+	#[test]
+	fn diff_of_identical_votes_is_empty() {
+		let mut applied = HashMap::new();
+		applied.insert("a".to_string(), chars(&["博丽灵梦"], None, 1, true));
+		let (deltas, changed, removed) = diff_contributions(&applied, vec![("a".to_string(), chars(&["博丽灵梦"], None, 1, true))]);
+		assert!(deltas.counters.is_empty() && deltas.globals.is_empty());
+		assert!(changed.is_empty() && removed.is_empty());
+	}
+}
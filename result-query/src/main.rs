@@ -8,6 +8,7 @@ extern crate phf;
 
 use actix_web::{App, HttpRequest, HttpServer, Responder, web::{self, Data}};
 use mongodb::{options::ClientOptions, Client};
+use serde_derive::Deserialize;
 
 mod service_error;
 mod models;
@@ -17,6 +18,17 @@ mod comm;
 mod context;
 mod query;
 mod common;
+mod live;
+mod cache;
+mod admin;
+
+/// ../keys/config.toml中result-query使用的部分
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    /// 管理接口令牌，未配置时管理接口不可用
+    #[serde(default)]
+    pub admin: admin::AdminConfig,
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
@@ -29,6 +41,12 @@ async fn main() -> std::io::Result<()> {
 
     let redlock = redlock::RedLock::new(vec![comm::REDIS_ADDRESS]);
 
+    // 与submit-handler共用配置文件，没有配置文件时不启用管理接口
+    let config: Config = match std::fs::read_to_string("../keys/config.toml") {
+        Ok(content) => toml::from_str(&content).expect("Config must be a valid toml file"),
+        Err(_) => Config::default()
+    };
+
     let ctx = context::AppContext {
         db: db.clone(),
         votes_coll: db.collection("votes"),
@@ -39,6 +57,8 @@ async fn main() -> std::io::Result<()> {
         musics_global_cache_coll: db.collection("cache_musics_global"),
         cps_entry_cache_coll: db.collection("cache_cps_entry"),
         cps_global_cache_coll: db.collection("cache_cps_global"),
+        dojins_entry_cache_coll: db.collection("cache_dojins_entry"),
+        dojins_global_cache_coll: db.collection("cache_dojins_global"),
         all_chars: db.collection("chars"),
         all_musics: db.collection("musics"),
         global_stats: db.collection("global_stats"),
@@ -48,12 +68,53 @@ async fn main() -> std::io::Result<()> {
         covote_musics: db.collection("covote_musics"),
         final_ranking_char: db.collection("final_ranking_char"),
         final_ranking_music: db.collection("final_ranking_music"),
+        live_counters_coll: db.collection("live_counters"),
+        live_globals_coll: db.collection("live_globals"),
+        live_applied_coll: db.collection("live_applied"),
+        live_snapshots_coll: db.collection("live_snapshots"),
+        live_sync_state_coll: db.collection("live_sync_state"),
+        cache_generations_coll: db.collection("cache_generations"),
+        admin: config.admin,
     };
+    if let Some(command) = std::env::args().nth(1) {
+        match command.as_str() {
+            "warmup" => {
+                let usage = "Usage: warmup <vote_year> <vote_start in RFC 3339>";
+                let vote_year: i32 = std::env::args().nth(2).and_then(|f| f.parse().ok()).expect(usage);
+                let vote_start = std::env::args().nth(3).and_then(|f| chrono::DateTime::parse_from_rfc3339(&f).ok()).expect(usage);
+                cache::warmup(&ctx, bson::DateTime::from_chrono(vote_start), vote_year).await.expect("Warmup failed");
+            },
+            "cache-bump" => {
+                let usage = "Usage: cache-bump <vote_year>";
+                let vote_year: i32 = std::env::args().nth(2).and_then(|f| f.parse().ok()).expect(usage);
+                let resp = cache::bump_generation(&ctx, vote_year).await.expect("Cache bump failed");
+                println!("cache: generation {}, purged {} documents", resp.generation, resp.purged);
+            },
+            "live-sync" => {
+                let usage = "Usage: live-sync <vote_year> <vote_start in RFC 3339> [--rebuild]";
+                let vote_year: i32 = std::env::args().nth(2).and_then(|f| f.parse().ok()).expect(usage);
+                let vote_start = std::env::args().nth(3).and_then(|f| chrono::DateTime::parse_from_rfc3339(&f).ok()).expect(usage);
+                let rebuild = std::env::args().nth(4).as_deref() == Some("--rebuild");
+                let resp = live::sync(&ctx, bson::DateTime::from_chrono(vote_start), vote_year, rebuild).await.expect("Live sync failed");
+                println!("live: {} changed votes, {} removed votes", resp.changed_votes, resp.removed_votes);
+            },
+            "live-publish" => {
+                let usage = "Usage: live-publish <vote_year> <snapshot name>";
+                let vote_year: i32 = std::env::args().nth(2).and_then(|f| f.parse().ok()).expect(usage);
+                let name = std::env::args().nth(3).expect(usage);
+                let resp = live::publish(&ctx, vote_year, name).await.expect("Live publish failed");
+                println!("live: published snapshot {} at {}", resp.name, resp.published_at);
+            },
+            _ => panic!("Unknown command {}", command)
+        }
+        return Ok(());
+    }
     HttpServer::new(move || {
         App::new().app_data(Data::new(ctx.clone()))
             .route("/v1/chars-rank/", web::post().to(handlers::chars_rank))
             .route("/v1/musics-rank/", web::post().to(handlers::musics_rank))
             .route("/v1/cps-rank/", web::post().to(handlers::cps_rank))
+            .route("/v1/dojins-rank/", web::post().to(handlers::dojins_rank))
             .route("/v1/chars-reasons/", web::post().to(handlers::chars_reasons))
             .route("/v1/musics-reasons/", web::post().to(handlers::musics_reasons))
             .route("/v1/cps-reasons/", web::post().to(handlers::cps_reasons))
@@ -69,6 +130,8 @@ async fn main() -> std::io::Result<()> {
             .route("/v1/chars-single/", web::post().to(handlers::chars_single))
             .route("/v1/musics-single/", web::post().to(handlers::musics_single))
             .route("/v1/cps-single/", web::post().to(handlers::cps_single))
+            .route("/v1/live-rank/", web::post().to(handlers::live_rank))
+            .route("/v1/cache-bump/", web::post().to(handlers::cache_bump))
     })
     .bind("0.0.0.0:80")?
     .run()
@@ -1,5 +1,5 @@
 
-use std::{collections::HashSet, hash::Hash};
+use std::{collections::{BTreeMap, HashSet}, hash::Hash};
 use chrono::{DateTime, Utc};
 use derivative::Derivative;
 use serde_derive::{Serialize, Deserialize};
@@ -199,6 +199,46 @@ pub struct CPRankingEntry {
 	pub num_reasons: i32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DojinTypeCount {
+	/// 作品类型
+	pub dojin_type: String,
+	/// 以该类型提名的次数
+	pub count: i32
+}
+
+/// 用于同人作品
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DojinRankingEntry {
+	/// 排名
+	pub rank: i32,
+	/// 展示排名
+	pub display_rank: i32,
+	/// 作品id
+	pub work_id: String,
+	/// 作品链接
+	pub url: String,
+	/// 作品名
+	pub title: String,
+	/// 作者
+	pub author: String,
+	/// 封面
+	pub image_url: Option<String>,
+	/// 提名数
+	pub nomination_count: i32,
+	/// 提名占比
+	/// =提名了该作品的人数/提名了同人作品的总人数
+	pub nomination_percentage: f64,
+	/// 各类型提名数
+	pub type_breakdown: Vec<DojinTypeCount>,
+	/// 首次提名时间
+	pub first_nominated_at: Option<DateTime<Utc>>,
+	/// 理由
+	pub reasons: Vec<String>,
+	/// 理由数量
+	pub num_reasons: i32,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RankingGlobal {
 	/// 角色数/音乐数
@@ -269,6 +309,12 @@ pub struct CPRankingQueryResponse {
 pub struct CachedRankingEntry {
 	pub key: String,
 	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
+	/// 同一次写入的条目与global使用相同的batch
+	#[serde(default)]
+	pub batch: Option<bson::oid::ObjectId>,
 	pub entry: RankingEntry
 }
 
@@ -276,13 +322,44 @@ pub struct CachedRankingEntry {
 pub struct CachedCPRankingEntry {
 	pub key: String,
 	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
+	/// 同一次写入的条目与global使用相同的batch
+	#[serde(default)]
+	pub batch: Option<bson::oid::ObjectId>,
 	pub entry: CPRankingEntry
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DojinRankingQueryResponse {
+	pub entries: Vec<DojinRankingEntry>,
+	pub global: RankingGlobal
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedDojinRankingEntry {
+	pub key: String,
+	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
+	/// 同一次写入的条目与global使用相同的batch
+	#[serde(default)]
+	pub batch: Option<bson::oid::ObjectId>,
+	pub entry: DojinRankingEntry
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CachedRankingGlobal {
 	pub key: String,
 	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
+	/// 同一次写入的条目与global使用相同的batch
+	#[serde(default)]
+	pub batch: Option<bson::oid::ObjectId>,
 	pub global: RankingGlobal
 }
 
@@ -328,6 +405,9 @@ pub struct CompletionRateRequest {
 pub struct GlobalStats {
 	pub key: String,
 	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
 	pub num_vote: i32,
 	pub num_char: i32,
 	pub num_music: i32,
@@ -350,6 +430,9 @@ pub struct CompletionRateItem {
 pub struct CompletionRate {
 	pub key: String,
 	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
 	pub items: Vec<CompletionRateItem>
 }
 
@@ -378,6 +461,9 @@ pub struct CachedQuestionItem {
 pub struct CachedQuestionEntry {
 	pub key: String,
 	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
 	pub entry: CachedQuestionItem,
 	pub trend: Vec<VotingTrendItem>
 }
@@ -426,6 +512,9 @@ pub struct CovoteItem {
 pub struct CachedCovote {
 	pub key: String,
 	pub vote_year: i32,
+	/// 缓存版本，见cache::current_generation
+	#[serde(default)]
+	pub generation: i64,
 	pub first_k: i32, // only top k participate in covote calculation
 	pub items: Vec<CovoteItem>
 }
@@ -455,3 +544,202 @@ pub struct SingleRankQuery {
 	pub vote_year: i32,
 	pub rank: i32
 }
+
+/// 实时结果的类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LiveRankingKind {
+	Chars,
+	Musics,
+	Cps
+}
+
+impl LiveRankingKind {
+	pub fn name(&self) -> &'static str {
+		match self {
+			LiveRankingKind::Chars => "chars",
+			LiveRankingKind::Musics => "musics",
+			LiveRankingKind::Cps => "cps",
+		}
+	}
+}
+
+/// 单个角色/音乐/CP的实时计数，CP的名字为成员排序后用x连接
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveCounter {
+	/// {vote_year}-{kind}-{name}
+	#[serde(rename = "_id")]
+	pub id: String,
+	pub vote_year: i32,
+	pub kind: LiveRankingKind,
+	pub name: String,
+	#[serde(default)]
+	pub vote_count: i32,
+	#[serde(default)]
+	pub first_vote_count: i32,
+	#[serde(default)]
+	pub male_vote_count: i32,
+	#[serde(default)]
+	pub female_vote_count: i32,
+	/// 投票开始后的小时数 -> 票数
+	#[serde(default)]
+	pub trend: BTreeMap<String, i32>,
+	#[serde(default)]
+	pub trend_first: BTreeMap<String, i32>
+}
+
+/// 每个类别的实时总数
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveGlobal {
+	/// {vote_year}-{kind}
+	#[serde(rename = "_id")]
+	pub id: String,
+	pub vote_year: i32,
+	pub kind: LiveRankingKind,
+	#[serde(default)]
+	pub total_votes: i32,
+	#[serde(default)]
+	pub total_first: i32,
+	#[serde(default)]
+	pub total_male: i32,
+	#[serde(default)]
+	pub total_female: i32
+}
+
+/// 一张投票在某个类别中计入的内容
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LiveKindContribution {
+	pub items: Vec<String>,
+	pub first: Option<String>,
+	/// 投票开始后的小时数
+	pub hrs: i32
+}
+
+/// 一张投票计入实时计数的内容，用于在投票更新或被移除时扣除
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LiveContribution {
+	pub is_male: bool,
+	pub chars: Option<LiveKindContribution>,
+	pub musics: Option<LiveKindContribution>,
+	pub cps: Option<LiveKindContribution>
+}
+
+/// 已计入实时计数的投票
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveAppliedVote {
+	/// {vote_year}-{vote_id}
+	#[serde(rename = "_id")]
+	pub id: String,
+	pub vote_year: i32,
+	pub vote_id: String,
+	pub contribution: LiveContribution
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveRankingEntry {
+	/// 排名
+	pub rank: i32,
+	/// 展示排名
+	pub display_rank: i32,
+	/// 角色名/音乐名/CP
+	pub name: String,
+	/// 票数
+	pub vote_count: i32,
+	/// 本命票数
+	pub first_vote_count: i32,
+	/// 票数占比
+	pub vote_percentage: f64,
+	/// 本命占比
+	pub first_percentage: f64,
+	/// 男性票数
+	pub male_vote_count: i32,
+	/// 女性票数
+	pub female_vote_count: i32,
+	/// 男性占比（本角色）
+	pub male_percentage_per_char: f64,
+	/// 女性占比（本角色）
+	pub female_percentage_per_char: f64,
+	/// 时间线
+	pub trend: Vec<VotingTrendItem>,
+	/// 本命时间线
+	pub trend_first: Vec<VotingTrendItem>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveRankingResponse {
+	pub kind: LiveRankingKind,
+	pub vote_year: i32,
+	/// 读取快照时为快照名
+	pub snapshot: Option<String>,
+	/// 计算时间
+	pub generated_at: DateTime<Utc>,
+	pub entries: Vec<LiveRankingEntry>,
+	pub global: RankingGlobal
+}
+
+/// 发布的快照，发布后不再改变
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveSnapshot {
+	pub name: String,
+	pub vote_year: i32,
+	pub kind: LiveRankingKind,
+	pub published_at: DateTime<Utc>,
+	pub ranking: LiveRankingResponse
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LiveRankingRequest {
+	pub vote_year: i32,
+	pub kind: LiveRankingKind,
+	/// 为空时返回实时结果
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
+	pub snapshot: Option<String>
+}
+
+/// 每届实时计数的同步状态，in_progress为true时计数可能只写入了一部分
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LiveSyncState {
+	#[serde(rename = "_id")]
+	pub vote_year: i32,
+	pub in_progress: bool,
+	pub updated_at: bson::DateTime
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LiveSyncResponse {
+	/// 新增或内容有变化的投票数
+	pub changed_votes: i32,
+	/// 已不在votes中而被扣除的投票数
+	pub removed_votes: i32
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LivePublishResponse {
+	pub name: String,
+	pub published_at: DateTime<Utc>
+}
+
+/// 每届结果缓存的当前版本，缓存文档的generation与之不同时视为失效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheGeneration {
+	#[serde(rename = "_id")]
+	pub vote_year: i32,
+	pub generation: i64,
+	pub bumped_at: bson::DateTime
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CacheBumpRequest {
+	pub admin_token: String,
+	pub vote_year: i32
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CacheBumpResponse {
+	pub vote_year: i32,
+	/// 新的缓存版本
+	pub generation: i64,
+	/// 删除的旧缓存文档数
+	pub purged: u64
+}
@@ -1,6 +1,6 @@
 use std::{collections::{HashMap, HashSet}, cmp::Ordering};
 
-use bson::{Document, doc};
+use bson::{Document, doc, oid::ObjectId};
 use chrono::Utc;
 use itertools::Itertools;
 use mongodb::{Collection, options::FindOptions};
@@ -8,7 +8,7 @@ use futures::stream::{StreamExt, TryStreamExt};
 use serde_derive::{Serialize, Deserialize};
 
 
-use crate::{parser, common::SERVICE_NAME, context::AppContext, models::{self, SubmitMetadata, RankingEntry, VotingTrendItem, RankingQueryResponse, RankingGlobal, CachedRankingEntry, CachedRankingGlobal, CPItem, CPRankingQueryResponse, CPRankingEntry, CachedCPRankingEntry, GlobalStats, CompletionRate, CompletionRateItem, SinglePaperItem, CachedQuestionItem, CachedQuestionAnswerItem, CachedQuestionEntry, QueryQuestionnaireResponse, CovoteItem, CachedCovote}, service_error::ServiceError};
+use crate::{parser, cache, common::SERVICE_NAME, context::AppContext, models::{self, SubmitMetadata, RankingEntry, VotingTrendItem, RankingQueryResponse, RankingGlobal, CachedRankingEntry, CachedRankingGlobal, CPItem, CPRankingQueryResponse, CPRankingEntry, CachedCPRankingEntry, DojinRankingEntry, DojinRankingQueryResponse, DojinTypeCount, CachedDojinRankingEntry, GlobalStats, CompletionRate, CompletionRateItem, SinglePaperItem, CachedQuestionItem, CachedQuestionAnswerItem, CachedQuestionEntry, QueryQuestionnaireResponse, CovoteItem, CachedCovote}, service_error::ServiceError};
 
 use phf::phf_map;
 
@@ -23,18 +23,31 @@ static KIND_MAPPING: phf::Map<&'static str, &'static str> = phf_map! {
 };
 
 #[derive(Clone, Serialize, Deserialize)]
-struct PartialVoteCharEntry {
+pub(crate) struct PartialVoteCharEntry {
 	pub name: String,
 	pub reason: Option<String>
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-struct PartialVotePaperEntry {
+pub(crate) struct PartialVoteDojinEntry {
+	pub dojin_type: String,
+	pub url: String,
+	pub title: String,
+	pub author: String,
+	pub reason: String,
+	pub image_url: Option<String>,
+	/// 作品登记之前的提交没有work_id，使用URL代替
+	#[serde(default)]
+	pub work_id: Option<String>
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct PartialVotePaperEntry {
 	pub opt: Vec<String>
 }
 
 #[derive(Clone, Serialize, Deserialize)]
-struct PartialVote {
+pub(crate) struct PartialVote {
 	pub q11011: PartialVotePaperEntry,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	#[serde(default)]
@@ -68,6 +81,9 @@ struct PartialVote {
 	pub doujins: Option<Vec<Document>>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	#[serde(default)]
+	pub doujins_meta: Option<SubmitMetadata>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	#[serde(default)]
 	pub paper_meta: Option<SubmitMetadata>,
 }
 
@@ -94,12 +110,15 @@ pub async fn chars_reasons(ctx: &AppContext, query: Option<String>, vote_start:
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.chars_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let opt = FindOptions::builder().skip(Some((std::cmp::max(rank, 1) - 1) as u64)).limit(1).build();
 		let mut cached_entries = ctx.chars_entry_cache_coll.find(cache_query, Some(opt)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		let mut entries = Vec::with_capacity(300);
@@ -138,12 +157,22 @@ pub async fn chars_trend(ctx: &AppContext, query: Option<String>, vote_start: bs
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
 		"vote_year": vote_year,
+		"generation": generation,
 		"entry.name": name
 	};
-	let cached_entry = ctx.chars_entry_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	// 只读取与global一同写入的那一批
+	let cached_global = ctx.chars_global_cache_coll.find_one(doc! { "key": cache_key.clone(), "vote_year": vote_year, "generation": generation }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let cached_entry = match cached_global {
+		Some(cached_global) => {
+			cache_query.insert("batch", cached_global.batch);
+			ctx.chars_entry_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?
+		}
+		None => None
+	};
 	if let Some(cached_entry) = cached_entry {
 		let resp = models::TrendResponse {
 			trend: cached_entry.entry.trend,
@@ -171,12 +200,15 @@ pub async fn chars_single(ctx: &AppContext, query: Option<String>, vote_start: b
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.chars_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let opt = FindOptions::builder().skip(Some((std::cmp::max(rank, 1) - 1) as u64)).limit(1).build();
 		let mut cached_entries = ctx.chars_entry_cache_coll.find(cache_query, Some(opt)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		if let Some(Ok(entry)) = cached_entries.next().await {
@@ -202,12 +234,15 @@ pub async fn chars_ranking(ctx: &AppContext, query: Option<String>, vote_start:
 	let lockid = format!("lock-chars_ranking-{}", cache_key);
 	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.chars_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let mut cached_entries = ctx.chars_entry_cache_coll.find(cache_query, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		let mut entries = Vec::with_capacity(300);
 		while let Some(Ok(entry)) = cached_entries.next().await {
@@ -474,12 +509,16 @@ pub async fn chars_ranking(ctx: &AppContext, query: Option<String>, vote_start:
 	};
 
 	// build cache
+	// global最后写入，读取时只认与global相同batch的条目
+	let batch = ObjectId::new();
 	let cached_entries = chars_result
 		.iter()
 		.map(|f| {
 			CachedRankingEntry {
 				key: cache_key.clone(),
 				vote_year,
+				generation,
+				batch: Some(batch),
 				entry: f.clone()
 			}
 		})
@@ -487,11 +526,14 @@ pub async fn chars_ranking(ctx: &AppContext, query: Option<String>, vote_start:
 	let cached_global = CachedRankingGlobal {
 		key: cache_key.clone(),
 		vote_year,
+		generation,
+		batch: Some(batch),
 		global: global.clone()
 	};
 	if cached_entries.len() != 0 {
 		ctx.chars_entry_cache_coll.insert_many(cached_entries, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		ctx.chars_global_cache_coll.insert_one(cached_global, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		cache::purge_superseded_batches(&ctx.chars_entry_cache_coll, &ctx.chars_global_cache_coll, &cache_key, vote_year, generation, batch).await?;
 	}
 	// build response
 	let resp = RankingQueryResponse {
@@ -513,12 +555,15 @@ pub async fn musics_reasons(ctx: &AppContext, query: Option<String>, vote_start:
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.musics_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let opt = FindOptions::builder().skip(Some((std::cmp::max(rank, 1) - 1) as u64)).limit(1).build();
 		let mut cached_entries = ctx.musics_entry_cache_coll.find(cache_query, Some(opt)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		let mut entries = Vec::with_capacity(300);
@@ -557,12 +602,22 @@ pub async fn musics_trend(ctx: &AppContext, query: Option<String>, vote_start: b
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
 		"vote_year": vote_year,
+		"generation": generation,
 		"entry.name": name
 	};
-	let cached_entry = ctx.musics_entry_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	// 只读取与global一同写入的那一批
+	let cached_global = ctx.musics_global_cache_coll.find_one(doc! { "key": cache_key.clone(), "vote_year": vote_year, "generation": generation }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let cached_entry = match cached_global {
+		Some(cached_global) => {
+			cache_query.insert("batch", cached_global.batch);
+			ctx.musics_entry_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?
+		}
+		None => None
+	};
 	if let Some(cached_entry) = cached_entry {
 		let resp = models::TrendResponse {
 			trend: cached_entry.entry.trend,
@@ -594,12 +649,15 @@ pub async fn musics_ranking(ctx: &AppContext, query: Option<String>, vote_start:
 	let lockid = format!("lock-musics_ranking-{}", cache_key);
 	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.musics_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let mut cached_entries = ctx.musics_entry_cache_coll.find(cache_query, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		let mut entries = Vec::with_capacity(300);
 		while let Some(Ok(entry)) = cached_entries.next().await {
@@ -866,12 +924,16 @@ pub async fn musics_ranking(ctx: &AppContext, query: Option<String>, vote_start:
 	};
 
 	// build cache
+	// global最后写入，读取时只认与global相同batch的条目
+	let batch = ObjectId::new();
 	let cached_entries = musics_result
 		.iter()
 		.map(|f| {
 			CachedRankingEntry {
 				key: cache_key.clone(),
 				vote_year,
+				generation,
+				batch: Some(batch),
 				entry: f.clone()
 			}
 		})
@@ -879,11 +941,14 @@ pub async fn musics_ranking(ctx: &AppContext, query: Option<String>, vote_start:
 	let cached_global = CachedRankingGlobal {
 		key: cache_key.clone(),
 		vote_year,
+		generation,
+		batch: Some(batch),
 		global: global.clone()
 	};
 	if cached_entries.len() != 0 {
 		ctx.musics_entry_cache_coll.insert_many(cached_entries, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		ctx.musics_global_cache_coll.insert_one(cached_global, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		cache::purge_superseded_batches(&ctx.musics_entry_cache_coll, &ctx.musics_global_cache_coll, &cache_key, vote_year, generation, batch).await?;
 	}
 	// build response
 	let resp = RankingQueryResponse {
@@ -905,12 +970,15 @@ pub async fn musics_single(ctx: &AppContext, query: Option<String>, vote_start:
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.musics_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let opt = FindOptions::builder().skip(Some((std::cmp::max(rank, 1) - 1) as u64)).limit(1).build();
 		let mut cached_entries = ctx.musics_entry_cache_coll.find(cache_query, Some(opt)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		if let Some(Ok(entry)) = cached_entries.next().await {
@@ -932,12 +1000,15 @@ pub async fn cps_reasons(ctx: &AppContext, query: Option<String>, vote_start: bs
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.cps_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let opt = FindOptions::builder().skip(Some((std::cmp::max(rank, 1) - 1) as u64)).limit(1).build();
 		let mut cached_entries = ctx.cps_entry_cache_coll.find(cache_query, Some(opt)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		let mut entries = Vec::with_capacity(300);
@@ -979,12 +1050,15 @@ pub async fn cps_ranking(ctx: &AppContext, query: Option<String>, vote_start: bs
 	let lockid = format!("lock-cps_ranking-{}", cache_key);
 	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.cps_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let mut cached_entries = ctx.cps_entry_cache_coll.find(cache_query, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		let mut entries = Vec::with_capacity(1000);
 		while let Some(Ok(entry)) = cached_entries.next().await {
@@ -1216,12 +1290,16 @@ pub async fn cps_ranking(ctx: &AppContext, query: Option<String>, vote_start: bs
 	};
 
 	// build cache
+	// global最后写入，读取时只认与global相同batch的条目
+	let batch = ObjectId::new();
 	let cached_entries = cps_result
 		.iter()
 		.map(|f| {
 			CachedCPRankingEntry {
 				key: cache_key.clone(),
 				vote_year,
+				generation,
+				batch: Some(batch),
 				entry: f.clone()
 			}
 		})
@@ -1229,11 +1307,14 @@ pub async fn cps_ranking(ctx: &AppContext, query: Option<String>, vote_start: bs
 	let cached_global = CachedRankingGlobal {
 		key: cache_key.clone(),
 		vote_year,
+		generation,
+		batch: Some(batch),
 		global: global.clone()
 	};
 	if cached_entries.len() != 0 {
 		ctx.cps_entry_cache_coll.insert_many(cached_entries, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		ctx.cps_global_cache_coll.insert_one(cached_global, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		cache::purge_superseded_batches(&ctx.cps_entry_cache_coll, &ctx.cps_global_cache_coll, &cache_key, vote_year, generation, batch).await?;
 	}
 	// build response
 	let resp = CPRankingQueryResponse {
@@ -1255,12 +1336,15 @@ pub async fn cps_single(ctx: &AppContext, query: Option<String>, vote_start: bso
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.cps_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
 		let opt = FindOptions::builder().skip(Some((std::cmp::max(rank, 1) - 1) as u64)).limit(1).build();
 		let mut cached_entries = ctx.cps_entry_cache_coll.find(cache_query, Some(opt)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 		if let Some(Ok(entry)) = cached_entries.next().await {
@@ -1282,12 +1366,22 @@ pub async fn cps_trend(ctx: &AppContext, query: Option<String>, vote_start: bson
 		}
 	};
 	// find in cache
-	let cache_query = doc! {
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
 		"key": cache_key.clone(),
 		"vote_year": vote_year,
+		"generation": generation,
 		"entry.rank": rank
 	};
-	let cached_entry = ctx.cps_entry_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	// 只读取与global一同写入的那一批
+	let cached_global = ctx.cps_global_cache_coll.find_one(doc! { "key": cache_key.clone(), "vote_year": vote_year, "generation": generation }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let cached_entry = match cached_global {
+		Some(cached_global) => {
+			cache_query.insert("batch", cached_global.batch);
+			ctx.cps_entry_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?
+		}
+		None => None
+	};
 	if let Some(cached_entry) = cached_entry {
 		let resp = models::TrendResponse {
 			trend: cached_entry.entry.trend,
@@ -1303,6 +1397,198 @@ pub async fn cps_trend(ctx: &AppContext, query: Option<String>, vote_start: bson
 	}
 }
 
+/// 同人作品提名统计
+#[derive(Default)]
+struct DojinNominations {
+	/// 作品id -> 最早的一次提名
+	first_nominations: HashMap<String, (Option<bson::DateTime>, PartialVoteDojinEntry)>,
+	reasons: HashMap<String, Vec<String>>,
+	per_work_nomination_count: HashMap<String, i32>,
+	per_work_type_count: HashMap<String, HashMap<String, i32>>,
+	total_voters: i32,
+	total_nominations: i32
+}
+
+impl DojinNominations {
+	/// 同一投票人多次提名同一作品只计一次
+	fn add_vote(&mut self, pv: &PartialVote) {
+		let dojins = match &pv.doujins {
+			Some(dojins) if dojins.len() != 0 => dojins,
+			_ => return
+		};
+		let created_at = pv.doujins_meta.as_ref().map(|f| f.created_at);
+		self.total_voters += 1;
+		let mut visited = HashSet::new();
+		for dojin in dojins {
+			let dojin: PartialVoteDojinEntry = match bson::from_document(dojin.clone()) {
+				Ok(dojin) => dojin,
+				Err(_) => continue
+			};
+			let work_id = dojin.work_id.clone().unwrap_or_else(|| dojin.url.clone());
+			if !visited.insert(work_id.clone()) {
+				continue;
+			}
+			self.total_nominations += 1;
+			*self.per_work_nomination_count.entry(work_id.clone()).or_default() += 1;
+			*self.per_work_type_count.entry(work_id.clone()).or_default().entry(dojin.dojin_type.clone()).or_default() += 1;
+			if dojin.reason.len() != 0 {
+				self.reasons.entry(work_id.clone()).or_default().push(dojin.reason.clone());
+			}
+			let earlier = match self.first_nominations.get(&work_id) {
+				Some((Some(existing), _)) => created_at.map_or(false, |f| f < *existing),
+				Some((None, _)) => created_at.is_some(),
+				None => true
+			};
+			if earlier {
+				self.first_nominations.insert(work_id, (created_at, dojin));
+			}
+		}
+	}
+
+	/// 排好名次的条目和总体统计
+	fn finish(mut self) -> (Vec<DojinRankingEntry>, RankingGlobal) {
+		let total_voters = self.total_voters;
+		let total_nominations = self.total_nominations;
+		let mut dojins_result = Vec::with_capacity(self.first_nominations.len());
+		for (work_id, (created_at, dojin)) in self.first_nominations {
+			let nomination_count = *self.per_work_nomination_count.get(&work_id).unwrap_or(&0);
+			let type_breakdown = self.per_work_type_count.remove(&work_id).unwrap_or_default()
+				.into_iter()
+				.map(|(dojin_type, count)| DojinTypeCount { dojin_type, count })
+				.sorted_by(|a, b| b.count.cmp(&a.count).then_with(|| a.dojin_type.cmp(&b.dojin_type)))
+				.collect::<Vec<_>>();
+			let reasons = self.reasons.remove(&work_id).unwrap_or_default();
+			dojins_result.push(DojinRankingEntry {
+				rank: 0,
+				display_rank: 0,
+				work_id,
+				url: dojin.url,
+				title: dojin.title,
+				author: dojin.author,
+				image_url: dojin.image_url,
+				nomination_count,
+				nomination_percentage: nomination_count as f64 / total_voters as f64,
+				type_breakdown,
+				first_nominated_at: created_at.map(|f| f.to_chrono()),
+				num_reasons: reasons.len() as i32,
+				reasons
+			});
+		}
+		// 提名数相同时先被提名的在前
+		dojins_result.sort_by(|a, b| b.nomination_count.cmp(&a.nomination_count).then_with(|| a.first_nominated_at.cmp(&b.first_nominated_at)).then_with(|| a.work_id.cmp(&b.work_id)));
+		let mut rank = 1;
+		let mut display_rank = 1;
+		let mut last_votes = -1;
+		for entry in dojins_result.iter_mut() {
+			if last_votes != entry.nomination_count {
+				display_rank = rank;
+			}
+			entry.rank = rank;
+			entry.display_rank = display_rank;
+			rank += 1;
+			last_votes = entry.nomination_count;
+		}
+		let num_dojin = dojins_result.len();
+		let avg = if num_dojin == 0 { 0f64 } else { total_nominations as f64 / num_dojin as f64 };
+		let median = if num_dojin == 0 {
+			0f64
+		} else if num_dojin % 2 == 0 {
+			0.5f64 * (dojins_result[num_dojin / 2 - 1].nomination_count as f64 + dojins_result[num_dojin / 2].nomination_count as f64)
+		} else {
+			dojins_result[num_dojin / 2].nomination_count as f64
+		};
+		let global = RankingGlobal {
+			total_unique_items: num_dojin as _,
+			total_first: 0,
+			total_votes: total_voters,
+			average_votes_per_item: avg,
+			median_votes_per_item: median,
+		};
+		(dojins_result, global)
+	}
+}
+
+pub async fn dojins_ranking(ctx: &AppContext, query: Option<String>, vote_start: bson::DateTime, vote_year: i32) ->  Result<DojinRankingQueryResponse, ServiceError> {
+	let (filter, cache_key) = process_query(query)?;
+	let filter = if let Some(filter) = filter {
+		doc! {
+			"$and": [filter, {"vote_year": vote_year}]
+		}
+	} else {
+		doc! {
+			"vote_year": vote_year
+		}
+	};
+	// lock query
+	let lockid = format!("lock-dojins_ranking-{}", cache_key);
+	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
+	// find in cache
+	let generation = cache::current_generation(ctx, vote_year).await?;
+	let mut cache_query = doc! {
+		"key": cache_key.clone(),
+		"vote_year": vote_year,
+		"generation": generation
+	};
+	let cached_global = ctx.dojins_global_cache_coll.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	if let Some(cached_global) = cached_global {
+		cache_query.insert("batch", cached_global.batch);
+		let options = FindOptions::builder().sort(doc! { "entry.rank": 1 }).build();
+		let mut cached_entries = ctx.dojins_entry_cache_coll.find(cache_query, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let mut entries = Vec::with_capacity(300);
+		while let Some(Ok(entry)) = cached_entries.next().await {
+			entries.push(entry.entry);
+		}
+		// build response
+		let resp = DojinRankingQueryResponse {
+			entries: entries,
+			global: cached_global.global
+		};
+		return Ok(resp);
+	};
+	// else
+	let mut votes_cursor = ctx.votes_coll.find(filter, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let mut nominations = DojinNominations::default();
+	while let Some(vote) = votes_cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		let pv: PartialVote = bson::from_document(vote).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		nominations.add_vote(&pv);
+	}
+	let (dojins_result, global) = nominations.finish();
+
+	// build cache
+	// global最后写入，读取时只认与global相同batch的条目
+	let batch = ObjectId::new();
+	let cached_entries = dojins_result
+		.iter()
+		.map(|f| {
+			CachedDojinRankingEntry {
+				key: cache_key.clone(),
+				vote_year,
+				generation,
+				batch: Some(batch),
+				entry: f.clone()
+			}
+		})
+		.collect::<Vec<_>>();
+	let cached_global = CachedRankingGlobal {
+		key: cache_key.clone(),
+		vote_year,
+		generation,
+		batch: Some(batch),
+		global: global.clone()
+	};
+	if cached_entries.len() != 0 {
+		ctx.dojins_entry_cache_coll.insert_many(cached_entries, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		ctx.dojins_global_cache_coll.insert_one(cached_global, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		cache::purge_superseded_batches(&ctx.dojins_entry_cache_coll, &ctx.dojins_global_cache_coll, &cache_key, vote_year, generation, batch).await?;
+	}
+	// build response
+	let resp = DojinRankingQueryResponse {
+		entries: dojins_result,
+		global
+	};
+	Ok(resp)
+}
+
 pub async fn global_stats(ctx: &AppContext, vote_start: bson::DateTime, vote_year: i32, query: Option<String>) -> Result<models::GlobalStats, ServiceError> {
 	let (filter, cache_key) = process_query(query)?;
 	let filter = if let Some(filter) = filter {
@@ -1314,9 +1600,11 @@ pub async fn global_stats(ctx: &AppContext, vote_start: bson::DateTime, vote_yea
 			"vote_year": vote_year
 		}
 	};
+	let generation = cache::current_generation(ctx, vote_year).await?;
 	let cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.global_stats.find_one(cache_query, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
@@ -1327,6 +1615,7 @@ pub async fn global_stats(ctx: &AppContext, vote_start: bson::DateTime, vote_yea
 	let mut gs = GlobalStats::default();
 	gs.key = cache_key;
 	gs.vote_year = vote_year;
+	gs.generation = generation;
 	while let Some(Ok(vote)) = votes_cursor.next().await {
 		let pv: PartialVote = bson::from_document(vote).unwrap();
 		gs.num_vote += 1;
@@ -1377,9 +1666,11 @@ pub async fn completion_rates(ctx: &AppContext, query: Option<String>, vote_star
 	let lockid = format!("lock-completion_rates-{}", cache_key);
 	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
 	// find in cache
+	let generation = cache::current_generation(ctx, vote_year).await?;
 	let cache_query = doc! {
 		"key": cache_key.clone(),
-		"vote_year": vote_year
+		"vote_year": vote_year,
+		"generation": generation
 	};
 	let cached_global = ctx.completion_rates.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	if let Some(cached_global) = cached_global {
@@ -1390,6 +1681,7 @@ pub async fn completion_rates(ctx: &AppContext, query: Option<String>, vote_star
 	let mut ret = CompletionRate {
 		key: cache_key,
 		vote_year,
+		generation,
 		items: vec![],
 	};
 	let mut total_votes = 0i32;
@@ -1443,9 +1735,11 @@ pub async fn paper_result(ctx: &AppContext, query: Option<String>, vote_start: b
 	let lockid = format!("lock-paper_result-{}", cache_key);
 	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
 	// find in cache
+	let generation = cache::current_generation(ctx, vote_year).await?;
 	let cache_query = doc! {
 		"key": cache_key.clone(),
 		"vote_year": vote_year,
+		"generation": generation,
 		"entry.question_id": {
 			"$in": questions_of_interest
 		}
@@ -1532,6 +1826,7 @@ pub async fn paper_result(ctx: &AppContext, query: Option<String>, vote_start: b
 				CachedQuestionEntry {
 					key: cache_key.clone(),
 					vote_year: vote_year,
+					generation,
 					entry: f.clone(),
 					trend: {
 						if let Some(trend) = hrs_bins.get(&f.question_id) {
@@ -1572,9 +1867,11 @@ pub async fn papers_trend(ctx: &AppContext, query: Option<String>, vote_start: b
 		}
 	};
 	// find in cache
+	let generation = cache::current_generation(ctx, vote_year).await?;
 	let cache_query = doc! {
 		"key": cache_key.clone(),
 		"vote_year": vote_year,
+		"generation": generation,
 		"entry.question_id": {
 			"$in": vec![question_id]
 		}
@@ -1611,9 +1908,11 @@ pub async fn chars_covote(ctx: &AppContext, query: Option<String>, vote_start: b
 	let lockid = format!("lock-chars_covote-{}", cache_key);
 	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
 	// find in cache
+	let generation = cache::current_generation(ctx, vote_year).await?;
 	let cache_query = doc! {
 		"key": cache_key.clone(),
 		"vote_year": vote_year,
+		"generation": generation,
 		"first_k": top_k
 	};
 	if let Some(cached) = ctx.covote_chars.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
@@ -1721,6 +2020,7 @@ pub async fn chars_covote(ctx: &AppContext, query: Option<String>, vote_start: b
 	let cached = CachedCovote {
 		key: cache_key,
 		vote_year: vote_year,
+		generation,
 		first_k: top_k as i32,
 		items: result.clone()
 	};
@@ -1744,9 +2044,11 @@ pub async fn musics_covote(ctx: &AppContext, query: Option<String>, vote_start:
 	let lockid = format!("lock-musics_covote-{}", cache_key);
 	let guard = ctx.lock.acquire_async(lockid.as_bytes(), 60 * 1000).await;
 	// find in cache
+	let generation = cache::current_generation(ctx, vote_year).await?;
 	let cache_query = doc! {
 		"key": cache_key.clone(),
 		"vote_year": vote_year,
+		"generation": generation,
 		"first_k": top_k
 	};
 	if let Some(cached) = ctx.covote_musics.find_one(cache_query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
@@ -1854,9 +2156,74 @@ pub async fn musics_covote(ctx: &AppContext, query: Option<String>, vote_start:
 	let cached = CachedCovote {
 		key: cache_key,
 		vote_year: vote_year,
+		generation,
 		first_k: top_k as i32,
 		items: result.clone()
 	};
 	ctx.covote_musics.insert_one(cached, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
 	Ok(models::CovoteResponse { items: result })
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn dojin_vote(created_at_millis: i64, dojins: Vec<Document>) -> PartialVote {
+		bson::from_document(doc! {
+			"q11011": { "opt": ["1101101"] },
+			"doujins": dojins,
+			"doujins_meta": {
+				"vote_id": "thvote-2022-email-1",
+				"created_at": bson::DateTime::from_millis(created_at_millis),
+				"user_ip": "127.0.0.1",
+				"additional_fingreprint": null
+			}
+		}).unwrap()
+	}
+
+	fn dojin(work_id: &str, dojin_type: &str, reason: &str) -> Document {
+		doc! {
+			"dojin_type": dojin_type,
+			"url": format!("https://example.com/{}", work_id),
+			"title": work_id,
+			"author": "author",
+			"reason": reason,
+			"image_url": null,
+			"work_id": work_id
+		}
+	}
+
+	#[test]
+	fn dojin_nominations_are_aggregated_per_work() {
+		let mut nominations = DojinNominations::default();
+		nominations.add_vote(&dojin_vote(3000, vec![dojin("a", "music", "好听"), dojin("b", "video", "")]));
+		// 同一投票人重复提名只计一次
+		nominations.add_vote(&dojin_vote(1000, vec![dojin("a", "video", "喜欢"), dojin("a", "video", "重复")]));
+		nominations.add_vote(&dojin_vote(2000, vec![dojin("b", "video", "不错")]));
+		nominations.add_vote(&dojin_vote(500, vec![]));
+		let (entries, global) = nominations.finish();
+		assert_eq!(global.total_votes, 3);
+		assert_eq!(global.total_unique_items, 2);
+		assert_eq!(global.average_votes_per_item, 2.0);
+		assert_eq!(entries.len(), 2);
+
+		// 提名数相同时先被提名的在前
+		let a = &entries[0];
+		assert_eq!(a.work_id, "a");
+		assert_eq!((a.rank, a.display_rank), (1, 1));
+		assert_eq!(a.nomination_count, 2);
+		assert_eq!(a.reasons, vec!["好听".to_string(), "喜欢".to_string()]);
+		assert_eq!(a.num_reasons, 2);
+		assert_eq!(a.first_nominated_at, Some(bson::DateTime::from_millis(1000).to_chrono()));
+		let types: Vec<_> = a.type_breakdown.iter().map(|f| (f.dojin_type.as_str(), f.count)).collect();
+		assert_eq!(types, vec![("music", 1), ("video", 1)]);
+
+		let b = &entries[1];
+		assert_eq!(b.work_id, "b");
+		assert_eq!((b.rank, b.display_rank), (2, 1));
+		assert_eq!(b.reasons, vec!["不错".to_string()]);
+		assert_eq!(b.first_nominated_at, Some(bson::DateTime::from_millis(2000).to_chrono()));
+		let types: Vec<_> = b.type_breakdown.iter().map(|f| (f.dojin_type.as_str(), f.count)).collect();
+		assert_eq!(types, vec![("video", 2)]);
+	}
+}
@@ -0,0 +1,38 @@
+use std::collections::HashMap;
+
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize};
+
+use crate::common::SERVICE_NAME;
+
+/// 管理接口的令牌，对应config.toml中的[admin]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct AdminConfig {
+	/// 令牌到管理员名字，未配置时所有管理接口都不可用
+	#[serde(default)]
+	pub tokens: HashMap<String, String>
+}
+
+impl AdminConfig {
+	/// 返回令牌对应的管理员名字
+	pub fn authenticate(&self, admin_token: &str) -> Result<String, ServiceError> {
+		match self.tokens.get(admin_token) {
+			Some(name) if !admin_token.is_empty() => Ok(name.clone()),
+			_ => Err(ServiceError::new_error_kind(SERVICE_NAME, "ADMIN_TOKEN_INVALID"))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn only_configured_tokens_authenticate() {
+		let config: AdminConfig = toml::from_str("[tokens]\nsecret-token = \"alice\"\n").unwrap();
+		assert_eq!(config.authenticate("secret-token").unwrap(), "alice");
+		assert!(config.authenticate("other-token").is_err());
+		assert!(config.authenticate("").is_err());
+		assert!(AdminConfig::default().authenticate("secret-token").is_err());
+	}
+}
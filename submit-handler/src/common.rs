@@ -1,7 +1,4 @@
 
-use chrono::Utc;
-use pvrustlib::ServiceError;
-use redis::AsyncCommands;
 
 pub static SERVICE_NAME: &'static str = "submit-handler";
 
@@ -11,34 +8,23 @@ pub const MONGODB_URL: &str = "mongodb://localhost:27017/";
 #[cfg(not(debug_assertions))]
 pub const MONGODB_URL: &str = "mongodb://mongo:27017/";
 
+/// 可投票角色/音乐列表的刷新间隔
+pub const CATALOG_REFRESH_INTERVAL_IN_SECONDS: u64 = 300;
 
-pub const RATE_LIMIT_WINDOW_SIZE_IN_SECONDS: i64 = 60;
-pub const RATE_LIMIT_MAX_REQUETS: i64 = 30;
-
-/// Rate limiting using token bucket
-pub async fn rate_limit(uid: &impl std::fmt::Display, conn: &mut redis::aio::Connection) -> Result<(), ServiceError> {
-	let cur_time = Utc::now().timestamp_millis();
-	let id = format!("rate-limit-{}-last-reset", uid);
-	let id_ctr = format!("rate-limit-{}-tokens", uid);
-	let last_time: Option<i64> = conn.get(&id).await.map_err(|e| ServiceError::from_dyn_error(SERVICE_NAME, Box::new(e)))?;
-	let (last_time, tokens_remaining) = if let Some(last_time) = last_time {
-		let remain: Option<i64> = conn.get(&id_ctr).await.map_err(|e| ServiceError::from_dyn_error(SERVICE_NAME, Box::new(e)))?;
-		(last_time, remain.unwrap())
-	} else {
-		conn.set(id.clone(), cur_time).await.map_err(|e| ServiceError::from_dyn_error(SERVICE_NAME, Box::new(e)))?;
-		conn.set(id_ctr.clone(), RATE_LIMIT_MAX_REQUETS).await.map_err(|e| ServiceError::from_dyn_error(SERVICE_NAME, Box::new(e)))?;
-		(cur_time, RATE_LIMIT_MAX_REQUETS)
-	};
-	if cur_time - last_time > RATE_LIMIT_WINDOW_SIZE_IN_SECONDS * 1000 {
-		// reset bucket
-		conn.set(id.clone(), cur_time).await.map_err(|e| ServiceError::from_dyn_error(SERVICE_NAME, Box::new(e)))?;
-		conn.set(id_ctr.clone(), RATE_LIMIT_MAX_REQUETS).await.map_err(|e| ServiceError::from_dyn_error(SERVICE_NAME, Box::new(e)))?;
-	} else {
-		if tokens_remaining <= 0 {
-			return Err(ServiceError::new_error_kind(SERVICE_NAME, "REQUEST_TOO_FREQUENT").into());
-		}
-	}
-	conn.decr(id_ctr.clone(), 1).await.map_err(|e| ServiceError::from_dyn_error(SERVICE_NAME, Box::new(e)))?;
-	Ok(())
-}
+/// 投票统计缓存时间
+pub const STATISTICS_CACHE_TTL_IN_SECONDS: usize = 10;
 
+/// 草稿保存时间，每次保存后重新计算
+pub const DRAFT_TTL_IN_SECONDS: usize = 14 * 24 * 3600;
+
+/// 单个草稿的最大长度
+pub const DRAFT_MAX_SIZE_IN_BYTES: usize = 64 * 1024;
+
+/// 草稿保存频率限制
+pub const DRAFT_RATE_LIMIT_WINDOW_IN_SECONDS: i64 = 60;
+pub const DRAFT_RATE_LIMIT_MAX_REQUESTS: i64 = 30;
+
+/// 提交锁的过期时间，防止进程崩溃后锁无法释放
+///
+/// 持锁期间只有数据库读写，需要联网的同人作品信息获取在加锁之前完成
+pub const SUBMIT_LOCK_TTL: std::time::Duration = std::time::Duration::from_secs(10);
@@ -0,0 +1,282 @@
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize};
+use url::Url;
+
+use crate::{comm::SERVICE_SCRAPER_ADDRESS, common::SERVICE_NAME};
+
+/// 同人作品类型，与gateway中的DojinType一致
+pub const DOJIN_TYPES: [&str; 7] = ["Music", "Video", "Drawing", "Software", "Article", "Craft", "Other"];
+
+/// 所有网站通用的追踪参数，另外还会去掉utm_开头的参数
+const COMMON_TRACKING_PARAMETERS: [&str; 2] = ["fbclid", "gclid"];
+
+/// 可以获取作品信息的网站
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DojinSite {
+	Bilibili,
+	BilibiliArticle,
+	Pixiv,
+	PixivNovel,
+	Twitter,
+	Youtube,
+	Acfun,
+	AcfunArticle,
+	NicoSeiga,
+	NicoVideo,
+	Thbwiki,
+	PatchyVideo,
+	Weibo,
+	Dizzylab,
+	Steam,
+	Dlsite,
+	Melonbooks,
+	Tieba
+}
+
+impl DojinSite {
+	pub fn detect(url: &Url) -> Option<DojinSite> {
+		let host = url.host_str().unwrap_or_default();
+		let path = url.path();
+		let site = match host {
+			"www.bilibili.com" | "bilibili.com" | "m.bilibili.com" if path.starts_with("/read/") => DojinSite::BilibiliArticle,
+			"www.bilibili.com" | "bilibili.com" | "m.bilibili.com" => DojinSite::Bilibili,
+			"www.pixiv.net" | "pixiv.net" if path.starts_with("/novel/") => DojinSite::PixivNovel,
+			"www.pixiv.net" | "pixiv.net" => DojinSite::Pixiv,
+			"twitter.com" | "mobile.twitter.com" | "x.com" => DojinSite::Twitter,
+			"www.youtube.com" | "youtube.com" | "m.youtube.com" => DojinSite::Youtube,
+			"www.acfun.cn" | "acfun.cn" | "m.acfun.cn" if path.starts_with("/a/") => DojinSite::AcfunArticle,
+			"www.acfun.cn" | "acfun.cn" | "m.acfun.cn" => DojinSite::Acfun,
+			"seiga.nicovideo.jp" => DojinSite::NicoSeiga,
+			"www.nicovideo.jp" | "nicovideo.jp" | "sp.nicovideo.jp" => DojinSite::NicoVideo,
+			"thwiki.cc" | "www.thwiki.cc" => DojinSite::Thbwiki,
+			"thvideo.tv" | "patchyvideo.com" | "www.patchyvideo.com" => DojinSite::PatchyVideo,
+			"weibo.com" | "m.weibo.cn" => DojinSite::Weibo,
+			"www.dizzylab.net" | "dizzylab.net" => DojinSite::Dizzylab,
+			"store.steampowered.com" => DojinSite::Steam,
+			"www.dlsite.com" | "dlsite.com" => DojinSite::Dlsite,
+			"www.melonbooks.co.jp" | "melonbooks.co.jp" => DojinSite::Melonbooks,
+			"tieba.baidu.com" => DojinSite::Tieba,
+			_ => return None
+		};
+		Some(site)
+	}
+
+	/// 该网站分享链接中的追踪参数
+	///
+	/// 像t、s、from这样的参数在其他网站上可能是内容id，只在已知的网站上去掉
+	fn tracking_parameters(&self) -> &'static [&'static str] {
+		match self {
+			DojinSite::Bilibili | DojinSite::BilibiliArticle => &[
+				"spm_id_from", "vd_source", "share_source", "share_medium", "share_plat", "share_session_id", "share_tag", "share_from",
+				"bbid", "ts", "from", "seid", "unique_k", "timestamp"
+			],
+			DojinSite::Youtube => &["si", "feature"],
+			DojinSite::Twitter => &["s", "t", "ref_src"],
+			DojinSite::NicoVideo | DojinSite::NicoSeiga => &["ref"],
+			_ => &[]
+		}
+	}
+
+	/// 该网站上可能出现的作品类型，None表示不限
+	pub fn allowed_types(&self) -> Option<&'static [&'static str]> {
+		match self {
+			DojinSite::Bilibili | DojinSite::Youtube | DojinSite::Acfun | DojinSite::NicoVideo | DojinSite::PatchyVideo => Some(&["Music", "Video"]),
+			DojinSite::BilibiliArticle | DojinSite::AcfunArticle | DojinSite::PixivNovel => Some(&["Article"]),
+			DojinSite::Pixiv | DojinSite::NicoSeiga => Some(&["Drawing", "Craft"]),
+			DojinSite::Dizzylab => Some(&["Music"]),
+			DojinSite::Steam => Some(&["Software"]),
+			DojinSite::Twitter | DojinSite::Weibo | DojinSite::Tieba | DojinSite::Thbwiki | DojinSite::Dlsite | DojinSite::Melonbooks => None
+		}
+	}
+}
+
+/// 统一作品链接：仅允许http(s)，去掉追踪参数和无用的片段，展开可以离线展开的短链接
+pub fn normalize_url(url: &str) -> Result<Url, ServiceError> {
+	let mut url = Url::parse(url.trim()).map_err(|_| ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("无效的URL：{}", url)))?;
+	if url.scheme() != "http" && url.scheme() != "https" {
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("无效的URL：{}", url)));
+	}
+	url.set_scheme("https").ok();
+	if url.host_str() == Some("youtu.be") {
+		let video_id = url.path().trim_start_matches('/').to_string();
+		url = Url::parse(&format!("https://www.youtube.com/watch?v={}", video_id)).unwrap();
+	}
+	let site_parameters = DojinSite::detect(&url).map_or(&[][..], |f| f.tracking_parameters());
+	let query: Vec<(String, String)> = url.query_pairs()
+		.filter(|(k, _)| !k.starts_with("utm_") && !COMMON_TRACKING_PARAMETERS.contains(&k.as_ref()) && !site_parameters.contains(&k.as_ref()))
+		.map(|(k, v)| (k.into_owned(), v.into_owned()))
+		.collect();
+	if query.is_empty() {
+		url.set_query(None);
+	} else {
+		url.query_pairs_mut().clear().extend_pairs(query);
+	}
+	// PatchyVideo使用hash路由，其他网站的片段只是页面内位置
+	if DojinSite::detect(&url) != Some(DojinSite::PatchyVideo) {
+		url.set_fragment(None);
+	}
+	Ok(url)
+}
+
+/// 作品信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DojinMetadata {
+	pub title: String,
+	pub authors: Vec<String>,
+	pub cover: Option<String>
+}
+
+/// 获取作品信息
+#[async_trait]
+pub trait DojinMetadataResolver: std::fmt::Debug + Send + Sync {
+	/// 展开需要联网才能解析的短链接（如b23.tv）
+	async fn expand_short_link(&self, url: &Url) -> Result<Url, ServiceError> {
+		Ok(url.clone())
+	}
+	/// 作品不存在时返回Ok(None)
+	async fn resolve(&self, url: &Url) -> Result<Option<DojinMetadata>, ServiceError>;
+}
+
+#[derive(Serialize)]
+struct ScraperRequest {
+	url: String
+}
+
+#[derive(Deserialize)]
+struct ScraperResponseData {
+	title: String,
+	#[serde(default)]
+	cover: Option<String>,
+	#[serde(default)]
+	author_name: Option<Vec<String>>
+}
+
+#[derive(Deserialize)]
+struct ScraperResponse {
+	status: String,
+	#[serde(default)]
+	msg: String,
+	#[serde(default)]
+	data: Option<ScraperResponseData>
+}
+
+/// 短链接展开和scraper的单次请求超时
+const RESOLVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(8);
+
+/// 使用scraper服务的/api获取作品信息
+#[derive(Debug)]
+pub struct ScraperMetadataResolver {
+	client: reqwest::Client
+}
+
+impl ScraperMetadataResolver {
+	pub fn new() -> Self {
+		Self {
+			client: reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).timeout(RESOLVE_TIMEOUT).build().unwrap()
+		}
+	}
+}
+
+const SHORT_LINK_HOSTS: [&str; 5] = ["b23.tv", "bili22.cn", "bili23.cn", "bili33.cn", "bili2233.cn"];
+
+#[async_trait]
+impl DojinMetadataResolver for ScraperMetadataResolver {
+	async fn expand_short_link(&self, url: &Url) -> Result<Url, ServiceError> {
+		if !SHORT_LINK_HOSTS.contains(&url.host_str().unwrap_or_default()) {
+			return Ok(url.clone());
+		}
+		let response = self.client.head(url.as_str()).send().await.map_err(|e| ServiceError::new_network_error(SERVICE_NAME, url.as_str(), Some(format!("{:?}", e))))?;
+		let location = response.headers().get(reqwest::header::LOCATION).and_then(|f| f.to_str().ok());
+		match location {
+			Some(location) => normalize_url(location),
+			None => Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("无法解析短链接：{}", url)))
+		}
+	}
+	async fn resolve(&self, url: &Url) -> Result<Option<DojinMetadata>, ServiceError> {
+		let api = format!("{}/api", SERVICE_SCRAPER_ADDRESS);
+		let response = self.client.post(&api).json(&ScraperRequest { url: url.to_string() }).send().await.map_err(|e| ServiceError::new_network_error(SERVICE_NAME, &api, Some(format!("{:?}", e))))?;
+		let resp: ScraperResponse = response.json().await.map_err(|e| ServiceError::new_json_error(SERVICE_NAME, &api, Some(format!("{:?}", e))))?;
+		match (resp.status.as_str(), resp.data) {
+			("ok", Some(data)) => Ok(Some(DojinMetadata {
+				title: data.title,
+				authors: data.author_name.unwrap_or_default(),
+				cover: data.cover.filter(|f| !f.is_empty())
+			})),
+			("err", _) => Ok(None),
+			_ => Err(ServiceError::new(SERVICE_NAME, format!("scraper failed for {}: {}", url, resp.msg)))
+		}
+	}
+}
+
+/// 固定返回预设结果，用于测试
+#[derive(Debug, Default)]
+pub struct StubMetadataResolver {
+	pub entries: HashMap<String, DojinMetadata>
+}
+
+#[async_trait]
+impl DojinMetadataResolver for StubMetadataResolver {
+	async fn resolve(&self, url: &Url) -> Result<Option<DojinMetadata>, ServiceError> {
+		Ok(self.entries.get(url.as_str()).cloned())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn strips_tracking_parameters() {
+		let url = normalize_url("http://www.bilibili.com/video/BV1xx411c7mD?spm_id_from=333.999&vd_source=abc&p=2#reply").unwrap();
+		assert_eq!(url.as_str(), "https://www.bilibili.com/video/BV1xx411c7mD?p=2");
+	}
+
+	#[test]
+	fn strips_site_parameters_only_on_that_site() {
+		let url = normalize_url("https://twitter.com/user/status/123?s=20&t=abc").unwrap();
+		assert_eq!(url.as_str(), "https://twitter.com/user/status/123");
+		// 其他网站上的t和s是内容id
+		let url = normalize_url("https://forum.example.com/viewtopic.php?t=123&utm_source=x&fbclid=y").unwrap();
+		assert_eq!(url.as_str(), "https://forum.example.com/viewtopic.php?t=123");
+		let url = normalize_url("https://blog.example.com/?s=%E4%B8%9C%E6%96%B9&from=home&ref=nav").unwrap();
+		assert_eq!(url.as_str(), "https://blog.example.com/?s=%E4%B8%9C%E6%96%B9&from=home&ref=nav");
+	}
+
+	#[test]
+	fn expands_youtube_short_links() {
+		let url = normalize_url("https://youtu.be/dQw4w9WgXcQ?si=tracking").unwrap();
+		assert_eq!(url.as_str(), "https://www.youtube.com/watch?v=dQw4w9WgXcQ");
+	}
+
+	#[test]
+	fn keeps_patchyvideo_hash_route() {
+		let url = normalize_url("https://thvideo.tv/#/video?id=abc").unwrap();
+		assert_eq!(url.fragment(), Some("/video?id=abc"));
+	}
+
+	#[test]
+	fn rejects_non_http_urls() {
+		assert!(normalize_url("javascript:alert(1)").is_err());
+		assert!(normalize_url("not a url").is_err());
+	}
+
+	#[test]
+	fn site_type_combinations() {
+		let steam = DojinSite::detect(&normalize_url("https://store.steampowered.com/app/1234").unwrap()).unwrap();
+		assert_eq!(steam.allowed_types(), Some(&["Software"][..]));
+		assert_eq!(DojinSite::detect(&normalize_url("https://example.com/a").unwrap()), None);
+	}
+
+	#[actix_rt::test]
+	async fn stub_resolver_returns_entries() {
+		let mut resolver = StubMetadataResolver::default();
+		resolver.entries.insert("https://www.pixiv.net/artworks/1".into(), DojinMetadata { title: "t".into(), authors: vec!["a".into()], cover: None });
+		let url = normalize_url("https://www.pixiv.net/artworks/1?utm_source=x").unwrap();
+		assert_eq!(resolver.resolve(&url).await.unwrap().unwrap().title, "t");
+		assert!(resolver.resolve(&normalize_url("https://www.pixiv.net/artworks/2").unwrap()).await.unwrap().is_none());
+	}
+}
@@ -0,0 +1,314 @@
+use std::collections::BTreeMap;
+
+use bson::{doc, oid::ObjectId, Document};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, IndexModel, options::ReplaceOptions};
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::{common::SERVICE_NAME, models::*, paper_validator::PaperAnswer, services::SubmitServiceV1};
+
+/// 人物、音乐或作品，与result-query中的PartialVoteCharEntry一致
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FinalVoteEntry {
+	pub name: String,
+	pub reason: Option<String>
+}
+
+/// 与result-query中的CPItem一致，成员按名字排序
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FinalCPItem {
+	pub a: String,
+	pub b: String,
+	pub c: Option<String>,
+	pub active: Option<String>,
+	pub reason: Option<String>
+}
+
+/// submits_v1_final.votes中的一条，每个投票人每年一条，字段与result-query中的PartialVote一致
+#[derive(Debug, Clone, Serialize)]
+pub struct FinalVote {
+	pub vote_id: String,
+	pub vote_year: i32,
+	/// 写入本条记录的finalize批次
+	pub finalize_run: ObjectId,
+	/// fraud-scan的可疑分数，供降低权重使用
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub suspicion_score: Option<f64>,
+	/// 问卷回答，展开成qXXXXX.opt/ans
+	#[serde(flatten)]
+	pub papers: BTreeMap<String, PaperAnswer>,
+	pub paper_meta: SubmitMetadata,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub chars: Option<Vec<FinalVoteEntry>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub chars_first: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub chars_meta: Option<SubmitMetadata>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub musics: Option<Vec<FinalVoteEntry>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub musics_first: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub musics_meta: Option<SubmitMetadata>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub cps: Option<Vec<FinalCPItem>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub cps_first: Option<Vec<FinalCPItem>>,
+	/// 成员按名字排序后用x连接，供result-query的cp:[...]查询使用
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub cps_str: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub cps_meta: Option<SubmitMetadata>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub works: Option<Vec<FinalVoteEntry>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub works_first: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub works_meta: Option<SubmitMetadata>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub doujins: Option<Vec<DojinSubmit>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub doujins_meta: Option<SubmitMetadata>
+}
+
+/// 拆分成投票项和本命
+fn split_first<'a>(items: impl Iterator<Item = (&'a String, &'a Option<String>, &'a Option<bool>)>) -> (Vec<FinalVoteEntry>, Vec<String>) {
+	let mut entries = vec![];
+	let mut first = vec![];
+	for (id, reason, is_first) in items {
+		if is_first.unwrap_or_default() {
+			first.push(id.clone());
+		}
+		entries.push(FinalVoteEntry { name: id.clone(), reason: reason.clone() });
+	}
+	(entries, first)
+}
+
+fn canonical_cp(cp: &CPSubmit) -> (FinalCPItem, String) {
+	let canonical = cp.canonical();
+	let item = FinalCPItem {
+		a: canonical.members[0].to_string(),
+		b: canonical.members[1].to_string(),
+		c: canonical.members.get(2).map(|f| f.to_string()),
+		active: cp.active.clone(),
+		reason: cp.reason.clone()
+	};
+	(item, canonical.members.join("x"))
+}
+
+/// vote_year年的投票人id前缀，格式：thvote-{YYYY}-{uid}，见Voter::generate_vote_id
+pub(crate) fn vote_id_prefix(vote_year: u32) -> String {
+	format!("thvote-{}-", vote_year)
+}
+
+/// vote_year年未撤回的问卷，current_*中同时保存着往年的提交
+fn papers_filter(vote_year: u32) -> Document {
+	doc! {
+		"meta.vote_id": { "$regex": format!("^{}", vote_id_prefix(vote_year)) },
+		"meta.withdrawal": null
+	}
+}
+
+impl FinalVote {
+	/// result-query要求每条记录都有q11011，问卷不属于vote_year、无法解析或没有回答q11011时返回None
+	pub fn new(vote_year: u32, finalize_run: ObjectId, paper: PaperSubmitRest) -> Option<FinalVote> {
+		if !paper.meta.vote_id.starts_with(&vote_id_prefix(vote_year)) {
+			return None;
+		}
+		let papers: BTreeMap<String, PaperAnswer> = serde_json::from_str(&paper.papers_json).ok()?;
+		if papers.get("q11011").map_or(true, |f| f.opt.is_empty()) {
+			return None;
+		}
+		Some(FinalVote {
+			vote_id: paper.meta.vote_id.clone(),
+			vote_year: vote_year as i32,
+			finalize_run,
+			suspicion_score: None,
+			papers,
+			paper_meta: paper.meta,
+			chars: None,
+			chars_first: None,
+			chars_meta: None,
+			musics: None,
+			musics_first: None,
+			musics_meta: None,
+			cps: None,
+			cps_first: None,
+			cps_str: None,
+			cps_meta: None,
+			works: None,
+			works_first: None,
+			works_meta: None,
+			doujins: None,
+			doujins_meta: None
+		})
+	}
+
+	pub fn set_characters(&mut self, submit: CharacterSubmitRest) {
+		let (entries, first) = split_first(submit.characters.iter().map(|f| (&f.id, &f.reason, &f.first)));
+		self.chars = Some(entries);
+		self.chars_first = Some(first);
+		self.chars_meta = Some(submit.meta);
+	}
+
+	pub fn set_musics(&mut self, submit: MusicSubmitRest) {
+		let (entries, first) = split_first(submit.music.iter().map(|f| (&f.id, &f.reason, &f.first)));
+		self.musics = Some(entries);
+		self.musics_first = Some(first);
+		self.musics_meta = Some(submit.meta);
+	}
+
+	pub fn set_works(&mut self, submit: WorkSubmitRest) {
+		let (entries, first) = split_first(submit.works.iter().map(|f| (&f.id, &f.reason, &f.first)));
+		self.works = Some(entries);
+		self.works_first = Some(first);
+		self.works_meta = Some(submit.meta);
+	}
+
+	pub fn set_cps(&mut self, submit: CPSubmitRest) {
+		let mut cps = vec![];
+		let mut cps_first = vec![];
+		let mut cps_str = vec![];
+		for cp in submit.cps.iter() {
+			let (item, key) = canonical_cp(cp);
+			if cp.first.unwrap_or_default() {
+				cps_first.push(item.clone());
+			}
+			cps.push(item);
+			cps_str.push(key);
+		}
+		self.cps = Some(cps);
+		self.cps_first = Some(cps_first);
+		self.cps_str = Some(cps_str);
+		self.cps_meta = Some(submit.meta);
+	}
+
+	pub fn set_dojins(&mut self, submit: DojinSubmitRest) {
+		self.doujins = Some(submit.dojins);
+		self.doujins_meta = Some(submit.meta);
+	}
+}
+
+/// 读取未撤回的当前提交，保留用户信息
+async fn find_valid<T>(current: &Collection<T>, vote_id: &str) -> Result<Option<T>, ServiceError>
+	where T: DeserializeOwned + Unpin + Send + Sync {
+	current.find_one(doc! { "meta.vote_id": vote_id, "meta.withdrawal": null }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))
+}
+
+/// 从current_*生成vote_year年的votes，每个完成问卷的投票人一条，排除fraud-scan判定为作弊的投票人
+///
+/// 可以重复运行：每次运行覆盖已有记录，并删除本年中不再有效（如已撤回问卷或被排除）的记录
+pub async fn finalize_votes(service: &SubmitServiceV1, votes: &Collection<Document>, vote_year: u32) -> Result<(), ServiceError> {
+	let index = IndexModel::builder().keys(doc! { "vote_year": 1, "vote_id": 1 }).build();
+	votes.create_index(index, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let finalize_run = ObjectId::new();
+	let reports = service.fraud.load_reports().await?;
+	let mut cursor = service.paper_coll.find(papers_filter(vote_year), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let mut finalized = 0;
+	let mut skipped = 0;
+	let mut excluded = 0;
+	while let Some(paper) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		let vote_id = paper.meta.vote_id.clone();
+		let report = reports.get(&vote_id);
+		if report.map_or(false, |f| service.fraud.is_excluded(f)) {
+			excluded += 1;
+			continue;
+		}
+		let mut vote = match FinalVote::new(vote_year, finalize_run, paper) {
+			Some(vote) => vote,
+			None => {
+				skipped += 1;
+				continue;
+			}
+		};
+		vote.suspicion_score = report.map(|f| f.score);
+		if let Some(submit) = find_valid(&service.character_coll, &vote_id).await? {
+			vote.set_characters(submit);
+		}
+		if let Some(submit) = find_valid(&service.music_coll, &vote_id).await? {
+			vote.set_musics(submit);
+		}
+		if let Some(submit) = find_valid(&service.cp_coll, &vote_id).await? {
+			vote.set_cps(submit);
+		}
+		if let Some(submit) = find_valid(&service.work_coll, &vote_id).await? {
+			vote.set_works(submit);
+		}
+		if let Some(submit) = find_valid(&service.dojin_coll, &vote_id).await? {
+			vote.set_dojins(submit);
+		}
+		let vote = bson::to_document(&vote).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let options = ReplaceOptions::builder().upsert(true).build();
+		votes.replace_one(doc! { "vote_year": vote_year as i32, "vote_id": &vote_id }, vote, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		finalized += 1;
+	}
+	let removed = votes.delete_many(doc! { "vote_year": vote_year as i32, "finalize_run": { "$ne": finalize_run } }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	println!("votes: finalized {} voters, skipped {} papers from other years or without q11011, excluded {} voters, removed {} stale votes", finalized, skipped, excluded, removed.deleted_count);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn meta() -> SubmitMetadata {
+		SubmitMetadata { vote_id: "thvote-2022-1".into(), ..SubmitMetadata::new() }
+	}
+
+	fn paper(papers_json: &str) -> PaperSubmitRest {
+		PaperSubmitRest { papers_json: papers_json.into(), meta: meta() }
+	}
+
+	#[test]
+	fn flattens_paper_answers() {
+		let vote = FinalVote::new(2022, ObjectId::new(), paper(r#"{"q11011":{"opt":["1101101"]},"q22011":{"ans":"无"}}"#)).unwrap();
+		let doc = bson::to_document(&vote).unwrap();
+		assert_eq!(doc.get_document("q11011").unwrap().get_array("opt").unwrap()[0].as_str(), Some("1101101"));
+		assert_eq!(doc.get_document("q22011").unwrap().get_str("ans").unwrap(), "无");
+		assert_eq!(doc.get_i32("vote_year").unwrap(), 2022);
+		assert!(!doc.contains_key("chars"));
+	}
+
+	#[test]
+	fn only_finalizes_papers_of_vote_year() {
+		let papers = vec![
+			PaperSubmitRest { papers_json: r#"{"q11011":{"opt":["1101101"]}}"#.into(), meta: SubmitMetadata { vote_id: "thvote-2021-email-1".into(), ..SubmitMetadata::new() } },
+			PaperSubmitRest { papers_json: r#"{"q11011":{"opt":["1101102"]}}"#.into(), meta: SubmitMetadata { vote_id: "thvote-2022-email-1".into(), ..SubmitMetadata::new() } },
+			// 前缀必须完整匹配年份
+			PaperSubmitRest { papers_json: r#"{"q11011":{"opt":["1101102"]}}"#.into(), meta: SubmitMetadata { vote_id: "thvote-20221-email-1".into(), ..SubmitMetadata::new() } }
+		];
+		let finalized: Vec<FinalVote> = papers.into_iter().filter_map(|f| FinalVote::new(2022, ObjectId::new(), f)).collect();
+		assert_eq!(finalized.len(), 1);
+		assert_eq!(finalized[0].vote_id, "thvote-2022-email-1");
+		assert_eq!(papers_filter(2022).get_document("meta.vote_id").unwrap().get_str("$regex").unwrap(), "^thvote-2022-");
+	}
+
+	#[test]
+	fn requires_q11011() {
+		assert!(FinalVote::new(2022, ObjectId::new(), paper(r#"{"q22011":{"ans":"无"}}"#)).is_none());
+		assert!(FinalVote::new(2022, ObjectId::new(), paper("not json")).is_none());
+	}
+
+	#[test]
+	fn converts_first_and_canonicalizes_cps() {
+		let mut vote = FinalVote::new(2022, ObjectId::new(), paper(r#"{"q11011":{"opt":["1101102"]}}"#)).unwrap();
+		vote.set_characters(CharacterSubmitRest {
+			characters: vec![
+				CharacterSubmit { id: "博丽灵梦".into(), reason: None, first: Some(false) },
+				CharacterSubmit { id: "东风谷早苗".into(), reason: Some("r".into()), first: Some(true) }
+			],
+			meta: meta()
+		});
+		vote.set_cps(CPSubmitRest {
+			cps: vec![CPSubmit { id_a: "雾雨魔理沙".into(), id_b: "博丽灵梦".into(), id_c: None, active: Some("雾雨魔理沙".into()), first: Some(true), reason: None }],
+			meta: meta()
+		});
+		assert_eq!(vote.chars_first, Some(vec!["东风谷早苗".to_string()]));
+		assert_eq!(vote.chars.as_ref().unwrap().len(), 2);
+		let cp = &vote.cps.as_ref().unwrap()[0];
+		assert_eq!((cp.a.as_str(), cp.b.as_str()), ("博丽灵梦", "雾雨魔理沙"));
+		assert_eq!(vote.cps_first.as_ref().unwrap()[0], *cp);
+		assert_eq!(vote.cps_str, Some(vec!["博丽灵梦x雾雨魔理沙".to_string()]));
+	}
+}
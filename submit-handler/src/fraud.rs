@@ -0,0 +1,647 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use bson::{doc, oid::ObjectId, Document};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, options::{FindOptions, ReplaceOptions, UpdateOptions}};
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+
+use crate::{common::SERVICE_NAME, finalize::vote_id_prefix, models::SubmitWithMeta, services::SubmitServiceV1, throttle::subnet_of};
+
+/// 常见的一次性邮箱域名
+const DISPOSABLE_EMAIL_DOMAINS: [&str; 12] = [
+	"mailinator.com", "10minutemail.com", "guerrillamail.com", "sharklasers.com", "yopmail.com", "temp-mail.org",
+	"trashmail.com", "getnada.com", "dispostable.com", "maildrop.cc", "mohmal.com", "linshiyouxiang.net"
+];
+
+/// 作弊检测配置，对应config.toml中的[fraud]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FraudConfig {
+	/// 同一IP下的投票人数达到该值时标记
+	#[serde(default = "default_accounts_per_ip")]
+	pub accounts_per_ip: usize,
+	/// 同一网段下的投票人数达到该值时标记
+	#[serde(default = "default_accounts_per_subnet")]
+	pub accounts_per_subnet: usize,
+	/// 同一浏览器指纹下的投票人数达到该值时标记
+	#[serde(default = "default_accounts_per_fingerprint")]
+	pub accounts_per_fingerprint: usize,
+	/// 在identical_list_window_minutes分钟内提交相同人物列表的投票人数达到该值时标记
+	#[serde(default = "default_identical_list_burst")]
+	pub identical_list_burst: usize,
+	#[serde(default = "default_identical_list_window_minutes")]
+	pub identical_list_window_minutes: i64,
+	/// 不短于该字数的理由被多个投票人使用时标记
+	#[serde(default = "default_min_copied_reason_length")]
+	pub min_copied_reason_length: usize,
+	/// 注册后该分钟数内就投票时标记
+	#[serde(default = "default_new_account_minutes")]
+	pub new_account_minutes: i64,
+	/// 内置列表之外的一次性邮箱域名
+	#[serde(default)]
+	pub disposable_email_domains: Vec<String>,
+	/// 未经人工审核且分数不低于该值的投票在finalize时排除，未配置时只排除审核结果为exclude的投票
+	#[serde(default)]
+	pub exclude_score: Option<f64>
+}
+
+fn default_accounts_per_ip() -> usize {
+	5
+}
+
+fn default_accounts_per_subnet() -> usize {
+	30
+}
+
+fn default_accounts_per_fingerprint() -> usize {
+	3
+}
+
+fn default_identical_list_burst() -> usize {
+	5
+}
+
+fn default_identical_list_window_minutes() -> i64 {
+	30
+}
+
+fn default_min_copied_reason_length() -> usize {
+	10
+}
+
+fn default_new_account_minutes() -> i64 {
+	10
+}
+
+impl Default for FraudConfig {
+	fn default() -> Self {
+		Self {
+			accounts_per_ip: default_accounts_per_ip(),
+			accounts_per_subnet: default_accounts_per_subnet(),
+			accounts_per_fingerprint: default_accounts_per_fingerprint(),
+			identical_list_burst: default_identical_list_burst(),
+			identical_list_window_minutes: default_identical_list_window_minutes(),
+			min_copied_reason_length: default_min_copied_reason_length(),
+			new_account_minutes: default_new_account_minutes(),
+			disposable_email_domains: vec![],
+			exclude_score: None
+		}
+	}
+}
+
+/// 可疑信号
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SuspicionSignal {
+	/// 同一IP下有多个投票人
+	SharedIp { ip: String, accounts: usize },
+	/// 同一网段下有多个投票人
+	SharedSubnet { subnet: String, accounts: usize },
+	/// 同一浏览器指纹下有多个投票人
+	SharedFingerprint { fingerprint: String, accounts: usize },
+	/// 短时间内多个投票人提交了相同的人物列表
+	IdenticalCharacterBurst { accounts: usize },
+	/// 理由与其他投票人完全相同
+	CopiedReason { reason: String, accounts: usize },
+	/// 注册后很快就投票
+	NewAccount { minutes: i64 },
+	/// 使用一次性邮箱
+	DisposableEmail { domain: String },
+	/// 提交曾被限流
+	Throttled { attempts: u64 }
+}
+
+impl SuspicionSignal {
+	fn kind(&self) -> &'static str {
+		match self {
+			SuspicionSignal::SharedIp { .. } => "shared_ip",
+			SuspicionSignal::SharedSubnet { .. } => "shared_subnet",
+			SuspicionSignal::SharedFingerprint { .. } => "shared_fingerprint",
+			SuspicionSignal::IdenticalCharacterBurst { .. } => "identical_character_burst",
+			SuspicionSignal::CopiedReason { .. } => "copied_reason",
+			SuspicionSignal::NewAccount { .. } => "new_account",
+			SuspicionSignal::DisposableEmail { .. } => "disposable_email",
+			SuspicionSignal::Throttled { .. } => "throttled"
+		}
+	}
+
+	fn weight(&self) -> f64 {
+		match self {
+			SuspicionSignal::SharedIp { .. } => 2.0,
+			SuspicionSignal::SharedSubnet { .. } => 1.0,
+			SuspicionSignal::SharedFingerprint { .. } => 2.0,
+			SuspicionSignal::IdenticalCharacterBurst { .. } => 3.0,
+			SuspicionSignal::CopiedReason { .. } => 2.0,
+			SuspicionSignal::NewAccount { .. } => 1.0,
+			SuspicionSignal::DisposableEmail { .. } => 2.0,
+			SuspicionSignal::Throttled { .. } => 1.0
+		}
+	}
+}
+
+/// 每种信号只计一次
+pub fn score(signals: &[SuspicionSignal]) -> f64 {
+	let mut counted = HashSet::new();
+	signals.iter().filter(|f| counted.insert(f.kind())).map(|f| f.weight()).sum()
+}
+
+/// 一个投票人的提交和账号信息，时间均为毫秒时间戳
+#[derive(Debug, Clone, Default)]
+pub struct VoterActivity {
+	/// 提交、注册和登录时使用的IP
+	pub ips: BTreeSet<String>,
+	/// 提交、注册和登录时的额外指纹
+	pub fingerprints: BTreeSet<String>,
+	/// 排序后的人物列表和提交时间
+	pub characters: Option<(String, i64)>,
+	pub reasons: BTreeSet<String>,
+	pub first_submit_at: Option<i64>,
+	pub account_created_at: Option<i64>,
+	pub email: Option<String>,
+	pub throttled_attempts: u64
+}
+
+/// 对所有投票人计算可疑信号，没有信号的投票人不出现在结果中
+pub fn detect(config: &FraudConfig, activities: &HashMap<String, VoterActivity>) -> BTreeMap<String, Vec<SuspicionSignal>> {
+	let mut signals: BTreeMap<String, Vec<SuspicionSignal>> = BTreeMap::new();
+
+	let mut by_ip: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+	let mut by_subnet: BTreeMap<String, BTreeSet<&str>> = BTreeMap::new();
+	let mut by_fingerprint: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+	let mut by_reason: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+	let mut by_characters: BTreeMap<&str, Vec<(i64, &str)>> = BTreeMap::new();
+	for (vote_id, activity) in activities.iter() {
+		for ip in activity.ips.iter() {
+			by_ip.entry(ip.as_str()).or_default().insert(vote_id.as_str());
+			if let Some(subnet) = subnet_of(ip) {
+				by_subnet.entry(subnet).or_default().insert(vote_id.as_str());
+			}
+		}
+		for fingerprint in activity.fingerprints.iter() {
+			by_fingerprint.entry(fingerprint.as_str()).or_default().insert(vote_id.as_str());
+		}
+		for reason in activity.reasons.iter().filter(|f| f.chars().count() >= config.min_copied_reason_length) {
+			by_reason.entry(reason.as_str()).or_default().insert(vote_id.as_str());
+		}
+		if let Some((characters, created_at)) = activity.characters.as_ref().filter(|(f, _)| !f.is_empty()) {
+			by_characters.entry(characters.as_str()).or_default().push((*created_at, vote_id.as_str()));
+		}
+	}
+
+	for (ip, voters) in by_ip.iter().filter(|(_, v)| v.len() >= config.accounts_per_ip) {
+		for vote_id in voters.iter() {
+			signals.entry(vote_id.to_string()).or_default().push(SuspicionSignal::SharedIp { ip: ip.to_string(), accounts: voters.len() });
+		}
+	}
+	for (subnet, voters) in by_subnet.iter().filter(|(_, v)| v.len() >= config.accounts_per_subnet) {
+		for vote_id in voters.iter() {
+			signals.entry(vote_id.to_string()).or_default().push(SuspicionSignal::SharedSubnet { subnet: subnet.clone(), accounts: voters.len() });
+		}
+	}
+	for (fingerprint, voters) in by_fingerprint.iter().filter(|(_, v)| v.len() >= config.accounts_per_fingerprint) {
+		for vote_id in voters.iter() {
+			signals.entry(vote_id.to_string()).or_default().push(SuspicionSignal::SharedFingerprint { fingerprint: fingerprint.to_string(), accounts: voters.len() });
+		}
+	}
+	for (reason, voters) in by_reason.iter().filter(|(_, v)| v.len() >= 2) {
+		for vote_id in voters.iter() {
+			signals.entry(vote_id.to_string()).or_default().push(SuspicionSignal::CopiedReason { reason: reason.to_string(), accounts: voters.len() });
+		}
+	}
+
+	// 按提交时间滑动窗口，记录每个投票人所在的最大窗口
+	let window = config.identical_list_window_minutes * 60 * 1000;
+	for submits in by_characters.values_mut() {
+		submits.sort();
+		let mut burst = vec![0usize; submits.len()];
+		let mut end = 0;
+		for start in 0..submits.len() {
+			while end < submits.len() && submits[end].0 - submits[start].0 <= window {
+				end += 1;
+			}
+			let count = end - start;
+			if count >= config.identical_list_burst {
+				for b in burst[start..end].iter_mut() {
+					*b = (*b).max(count);
+				}
+			}
+		}
+		for (i, (_, vote_id)) in submits.iter().enumerate().filter(|(i, _)| burst[*i] != 0) {
+			signals.entry(vote_id.to_string()).or_default().push(SuspicionSignal::IdenticalCharacterBurst { accounts: burst[i] });
+		}
+	}
+
+	for (vote_id, activity) in activities.iter() {
+		if let (Some(created_at), Some(first_submit_at)) = (activity.account_created_at, activity.first_submit_at) {
+			let minutes = (first_submit_at - created_at) / 60 / 1000;
+			if first_submit_at >= created_at && minutes < config.new_account_minutes {
+				signals.entry(vote_id.clone()).or_default().push(SuspicionSignal::NewAccount { minutes });
+			}
+		}
+		if let Some(domain) = activity.email.as_ref().and_then(|f| f.rsplit('@').next()).map(|f| f.to_lowercase()) {
+			if DISPOSABLE_EMAIL_DOMAINS.contains(&domain.as_str()) || config.disposable_email_domains.iter().any(|f| f.eq_ignore_ascii_case(&domain)) {
+				signals.entry(vote_id.clone()).or_default().push(SuspicionSignal::DisposableEmail { domain });
+			}
+		}
+		if activity.throttled_attempts != 0 {
+			signals.entry(vote_id.clone()).or_default().push(SuspicionSignal::Throttled { attempts: activity.throttled_attempts });
+		}
+	}
+	signals
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReviewDecision {
+	/// 确认正常，finalize时保留
+	Accept,
+	/// 确认作弊，finalize时排除
+	Exclude
+}
+
+/// 人工审核结果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FraudReview {
+	pub decision: ReviewDecision,
+	/// 审核人
+	pub reviewer: String,
+	pub note: Option<String>,
+	pub reviewed_at: bson::DateTime
+}
+
+/// 每个投票人的可疑报告，保存在fraud_reports中
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SuspicionReport {
+	#[serde(rename = "_id")]
+	pub vote_id: String,
+	pub score: f64,
+	pub signals: Vec<SuspicionSignal>,
+	/// 最近一次更新本报告的扫描
+	pub scan_run: ObjectId,
+	pub scanned_at: bson::DateTime,
+	/// 最近一次审核结果，重新扫描时保留
+	#[serde(default)]
+	pub review: Option<FraudReview>
+}
+
+/// 审核记录，每次审核追加一条，不会修改
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FraudReviewLog {
+	/// 由vote_id和审核时间生成，重复写入同一次审核不会产生多条记录
+	#[serde(rename = "_id")]
+	pub id: String,
+	pub vote_id: String,
+	pub review: FraudReview,
+	/// 本次审核前的结果
+	pub previous_decision: Option<ReviewDecision>,
+	/// 审核时的分数和信号
+	pub score: f64,
+	pub signals: Vec<SuspicionSignal>
+}
+
+/// vote_year年的提交，current_*和history_*中同时保存着往年的提交
+fn year_filter(vote_year: u32) -> Document {
+	doc! { "meta.vote_id": { "$regex": format!("^{}", vote_id_prefix(vote_year)) } }
+}
+
+/// 读取vote_year年未撤回的当前提交，记录IP、提交时间和其他信息
+async fn collect_current<T>(current: &Collection<T>, vote_year: u32, activities: &mut HashMap<String, VoterActivity>, f: impl Fn(&T, &mut VoterActivity)) -> Result<(), ServiceError>
+	where T: DeserializeOwned + SubmitWithMeta + Unpin + Send + Sync {
+	let mut filter = year_filter(vote_year);
+	filter.insert("meta.withdrawal", bson::Bson::Null);
+	let mut cursor = current.find(filter, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	while let Some(submit) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		let meta = submit.meta();
+		let activity = activities.entry(meta.vote_id.clone()).or_default();
+		activity.ips.insert(meta.user_ip.clone());
+		activity.fingerprints.extend(meta.additional_fingreprint.clone());
+		let created_at = meta.created_at.timestamp_millis();
+		activity.first_submit_at = Some(activity.first_submit_at.map_or(created_at, |t| t.min(created_at)));
+		f(&submit, activity);
+	}
+	Ok(())
+}
+
+/// vote_year年的历史提交只补充已有投票人的IP和最早提交时间
+async fn collect_history<T>(history: &Collection<T>, vote_year: u32, activities: &mut HashMap<String, VoterActivity>) -> Result<(), ServiceError>
+	where T: DeserializeOwned + SubmitWithMeta + Unpin + Send + Sync {
+	let mut cursor = history.find(year_filter(vote_year), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	while let Some(submit) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		let meta = submit.meta();
+		if let Some(activity) = activities.get_mut(&meta.vote_id) {
+			activity.ips.insert(meta.user_ip.clone());
+			activity.fingerprints.extend(meta.additional_fingreprint.clone());
+			let created_at = meta.created_at.timestamp_millis();
+			activity.first_submit_at = Some(activity.first_submit_at.map_or(created_at, |t| t.min(created_at)));
+		}
+	}
+	Ok(())
+}
+
+/// vote_year年的vote_id按uid索引，不属于vote_year的vote_id被忽略
+fn uid_to_vote_id<'a>(vote_year: u32, vote_ids: impl Iterator<Item = &'a String>) -> HashMap<String, String> {
+	let prefix = vote_id_prefix(vote_year);
+	vote_ids.filter_map(|f| f.strip_prefix(&prefix).map(|uid| (uid.to_string(), f.clone()))).collect()
+}
+
+/// 对报告做出审核时的审核记录
+fn review_log(report: &SuspicionReport, review: FraudReview) -> FraudReviewLog {
+	FraudReviewLog {
+		id: format!("{}-{}", report.vote_id, review.reviewed_at.timestamp_millis()),
+		vote_id: report.vote_id.clone(),
+		review,
+		previous_decision: report.review.as_ref().map(|f| f.decision),
+		score: report.score,
+		signals: report.signals.clone()
+	}
+}
+
+/// user-manager的voter_logs中与投票人有关的一条记录，返回(uid, IP, 额外指纹)
+///
+/// 记录格式为{ "VoterLogin": { "uid": ..., "requester_ip": ..., ... } }，没有uid的记录（如发送验证码）返回None
+fn voter_log_activity(entry: &Document) -> Option<(String, Option<String>, Option<String>)> {
+	let (_, fields) = entry.iter().find(|(k, _)| k.as_str() != "_id")?;
+	let fields = fields.as_document()?;
+	let uid = fields.get_object_id("uid").ok()?.to_hex();
+	let ip = fields.get_str("requester_ip").ok().filter(|f| !f.is_empty()).map(|f| f.to_string());
+	let fingerprint = fields.get_str("requester_additional_fingerprint").ok().filter(|f| !f.is_empty()).map(|f| f.to_string());
+	Some((uid, ip, fingerprint))
+}
+
+#[derive(Debug, Clone)]
+pub struct FraudReports {
+	pub config: FraudConfig,
+	pub reports_coll: Collection<SuspicionReport>,
+	pub reviews_coll: Collection<FraudReviewLog>
+}
+
+impl FraudReports {
+	/// 扫描vote_year年所有未撤回的提交并更新fraud_reports，`voters`和`voter_logs`为user-manager的voters和voter_logs集合
+	pub async fn scan(&self, service: &SubmitServiceV1, vote_year: u32, voters: &Collection<Document>, voter_logs: &Collection<Document>) -> Result<(), ServiceError> {
+		let mut activities: HashMap<String, VoterActivity> = HashMap::new();
+		collect_current(&service.character_coll, vote_year, &mut activities, |submit, activity| {
+			let mut characters: Vec<&str> = submit.characters.iter().map(|f| f.id.as_str()).collect();
+			characters.sort();
+			activity.characters = Some((characters.join(","), submit.meta.created_at.timestamp_millis()));
+			activity.reasons.extend(submit.characters.iter().filter_map(|f| f.reason.as_ref()).map(|f| f.trim().to_string()));
+		}).await?;
+		collect_current(&service.music_coll, vote_year, &mut activities, |submit, activity| {
+			activity.reasons.extend(submit.music.iter().filter_map(|f| f.reason.as_ref()).map(|f| f.trim().to_string()));
+		}).await?;
+		collect_current(&service.cp_coll, vote_year, &mut activities, |submit, activity| {
+			activity.reasons.extend(submit.cps.iter().filter_map(|f| f.reason.as_ref()).map(|f| f.trim().to_string()));
+		}).await?;
+		collect_current(&service.work_coll, vote_year, &mut activities, |submit, activity| {
+			activity.reasons.extend(submit.works.iter().filter_map(|f| f.reason.as_ref()).map(|f| f.trim().to_string()));
+		}).await?;
+		collect_current(&service.dojin_coll, vote_year, &mut activities, |submit, activity| {
+			activity.reasons.extend(submit.dojins.iter().map(|f| f.reason.trim().to_string()));
+		}).await?;
+		collect_current(&service.paper_coll, vote_year, &mut activities, |_, _| {}).await?;
+		collect_history(&service.character_history_coll, vote_year, &mut activities).await?;
+		collect_history(&service.music_history_coll, vote_year, &mut activities).await?;
+		collect_history(&service.cp_history_coll, vote_year, &mut activities).await?;
+		collect_history(&service.work_history_coll, vote_year, &mut activities).await?;
+		collect_history(&service.dojin_history_coll, vote_year, &mut activities).await?;
+		collect_history(&service.paper_history_coll, vote_year, &mut activities).await?;
+
+		let uid_to_vote_id = uid_to_vote_id(vote_year, activities.keys());
+		let options = FindOptions::builder().projection(doc! { "created_at": 1, "email": 1, "signup_ip": 1 }).build();
+		let mut cursor = voters.find(None, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		while let Some(voter) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+			let uid = match voter.get_object_id("_id") {
+				Ok(uid) => uid.to_hex(),
+				Err(_) => continue
+			};
+			if let Some(activity) = uid_to_vote_id.get(&uid).and_then(|f| activities.get_mut(f)) {
+				activity.account_created_at = voter.get_datetime("created_at").ok().map(|f| f.timestamp_millis());
+				activity.email = voter.get_str("email").ok().map(|f| f.to_string());
+				if let Ok(signup_ip) = voter.get_str("signup_ip") {
+					activity.ips.insert(signup_ip.to_string());
+				}
+			}
+		}
+
+		// 注册、登录和账号操作时的IP和指纹
+		let mut cursor = voter_logs.find(None, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		while let Some(entry) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+			let (uid, ip, fingerprint) = match voter_log_activity(&entry) {
+				Some(activity) => activity,
+				None => continue
+			};
+			if let Some(activity) = uid_to_vote_id.get(&uid).and_then(|f| activities.get_mut(f)) {
+				activity.ips.extend(ip);
+				activity.fingerprints.extend(fingerprint);
+			}
+		}
+
+		let stages = vec![doc! { "$group": { "_id": "$vote_id", "count": { "$sum": 1 } } }];
+		let mut cursor = service.throttle.throttled_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		while let Some(throttled) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+			let count = match throttled.get("count") {
+				Some(bson::Bson::Int32(v)) => *v as u64,
+				Some(bson::Bson::Int64(v)) => *v as u64,
+				_ => 0
+			};
+			if let Some(activity) = throttled.get_str("_id").ok().and_then(|f| activities.get_mut(f)) {
+				activity.throttled_attempts = count;
+			}
+		}
+
+		let scan_run = ObjectId::new();
+		let scanned_at = bson::DateTime::now();
+		let signals = detect(&self.config, &activities);
+		for (vote_id, signals) in signals.iter() {
+			let update = doc! {
+				"$set": {
+					"score": score(signals),
+					"signals": bson::to_bson(signals).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?,
+					"scan_run": scan_run,
+					"scanned_at": scanned_at
+				}
+			};
+			self.reports_coll.update_one(doc! { "_id": vote_id }, update, UpdateOptions::builder().upsert(true).build()).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		}
+		// 本次没有信号的投票人清空信号，保留审核结果
+		let cleared = self.reports_coll.update_many(
+			doc! { "scan_run": { "$ne": scan_run } },
+			doc! { "$set": { "score": 0.0, "signals": [], "scan_run": scan_run, "scanned_at": scanned_at } },
+			None
+		).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		println!("fraud_reports: scanned {} voters, {} suspicious, cleared {}", activities.len(), signals.len(), cleared.modified_count);
+		Ok(())
+	}
+
+	/// 按分数从高到低列出报告
+	pub async fn list(&self, min_score: f64, offset: u64, limit: i64) -> Result<Vec<SuspicionReport>, ServiceError> {
+		let options = FindOptions::builder().sort(doc! { "score": -1, "_id": 1 }).skip(offset).limit(limit).build();
+		let cursor = self.reports_coll.find(doc! { "score": { "$gte": min_score } }, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		cursor.try_collect().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))
+	}
+
+	/// 记录审核结果，并在fraud_reviews中追加审核记录
+	///
+	/// 先更新报告再写审核记录，报告中的审核结果是finalize唯一依据。更新报告时要求审核结果仍是刚读到的版本，
+	/// 两人同时审核时后提交的返回REVIEW_CONFLICT；审核记录按_id覆盖写入，同一次审核重复写入只有一条
+	pub async fn review(&self, vote_id: String, decision: ReviewDecision, reviewer: String, note: Option<String>) -> Result<SuspicionReport, ServiceError> {
+		let mut report = self.reports_coll.find_one(doc! { "_id": &vote_id }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?
+			.ok_or_else(|| ServiceError::new_not_found(SERVICE_NAME, Some(vote_id.clone())))?;
+		let review = FraudReview {
+			decision,
+			reviewer,
+			note,
+			reviewed_at: bson::DateTime::now()
+		};
+		let log = review_log(&report, review.clone());
+		let query = match &report.review {
+			Some(previous) => doc! { "_id": &vote_id, "review.reviewed_at": previous.reviewed_at },
+			None => doc! { "_id": &vote_id, "review": null }
+		};
+		let update = doc! { "$set": { "review": bson::to_bson(&review).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? } };
+		let result = self.reports_coll.update_one(query, update, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		if result.matched_count == 0 {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "REVIEW_CONFLICT", "该报告已被其他人审核，请刷新后重试".into()));
+		}
+		self.reviews_coll.replace_one(doc! { "_id": &log.id }, log, ReplaceOptions::builder().upsert(true).build()).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		report.review = Some(review);
+		Ok(report)
+	}
+
+	/// 审核结果优先，未审核时按exclude_score判断
+	pub fn is_excluded(&self, report: &SuspicionReport) -> bool {
+		match &report.review {
+			Some(review) => review.decision == ReviewDecision::Exclude,
+			None => self.config.exclude_score.map_or(false, |f| report.score >= f)
+		}
+	}
+
+	/// 所有有信号或已审核的报告
+	pub async fn load_reports(&self) -> Result<HashMap<String, SuspicionReport>, ServiceError> {
+		let filter = doc! { "$or": [{ "score": { "$gt": 0.0 } }, { "review": { "$ne": null } }] };
+		let cursor = self.reports_coll.find(filter, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let reports: Vec<SuspicionReport> = cursor.try_collect().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		Ok(reports.into_iter().map(|f| (f.vote_id.clone(), f)).collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const MINUTE: i64 = 60 * 1000;
+
+	fn activity(ip: &str) -> VoterActivity {
+		VoterActivity { ips: vec![ip.to_string()].into_iter().collect(), ..Default::default() }
+	}
+
+	#[test]
+	fn flags_shared_ip_and_subnet() {
+		let config = FraudConfig { accounts_per_ip: 3, accounts_per_subnet: 4, ..Default::default() };
+		let mut activities = HashMap::new();
+		for i in 0..3 {
+			activities.insert(format!("v{}", i), activity("10.0.0.1"));
+		}
+		activities.insert("v3".into(), activity("10.0.0.2"));
+		activities.insert("v4".into(), activity("10.0.1.1"));
+		let signals = detect(&config, &activities);
+		assert_eq!(signals["v0"], vec![
+			SuspicionSignal::SharedIp { ip: "10.0.0.1".into(), accounts: 3 },
+			SuspicionSignal::SharedSubnet { subnet: "10.0.0.0/24".into(), accounts: 4 }
+		]);
+		assert_eq!(signals["v3"], vec![SuspicionSignal::SharedSubnet { subnet: "10.0.0.0/24".into(), accounts: 4 }]);
+		assert!(!signals.contains_key("v4"));
+	}
+
+	#[test]
+	fn flags_identical_character_bursts_within_window() {
+		let config = FraudConfig { identical_list_burst: 3, identical_list_window_minutes: 10, accounts_per_ip: 100, ..Default::default() };
+		let mut activities = HashMap::new();
+		for (i, minute) in [0, 2, 5, 40].iter().enumerate() {
+			let mut a = activity(&format!("10.0.{}.1", i));
+			a.characters = Some(("博丽灵梦,雾雨魔理沙".into(), minute * MINUTE));
+			activities.insert(format!("v{}", i), a);
+		}
+		let signals = detect(&config, &activities);
+		assert_eq!(signals["v0"], vec![SuspicionSignal::IdenticalCharacterBurst { accounts: 3 }]);
+		assert!(!signals.contains_key("v3"));
+	}
+
+	#[test]
+	fn flags_account_signals_and_scores_each_kind_once() {
+		let config = FraudConfig::default();
+		let mut a = activity("10.0.0.1");
+		a.account_created_at = Some(0);
+		a.first_submit_at = Some(3 * MINUTE);
+		a.email = Some("someone@Mailinator.com".into());
+		a.reasons = vec!["这是一段很长的复制粘贴理由啊".to_string(), "另一段也很长的复制粘贴理由啊".to_string()].into_iter().collect();
+		let mut b = activity("10.0.1.1");
+		b.reasons = a.reasons.clone();
+		b.throttled_attempts = 2;
+		let activities: HashMap<String, VoterActivity> = vec![("a".to_string(), a), ("b".to_string(), b)].into_iter().collect();
+		let signals = detect(&config, &activities);
+		assert!(signals["a"].contains(&SuspicionSignal::NewAccount { minutes: 3 }));
+		assert!(signals["a"].contains(&SuspicionSignal::DisposableEmail { domain: "mailinator.com".into() }));
+		assert!(signals["b"].contains(&SuspicionSignal::Throttled { attempts: 2 }));
+		// 两条复制的理由只计一次
+		assert_eq!(score(&signals["b"]), 2.0 + 1.0);
+	}
+
+	#[test]
+	fn flags_shared_fingerprints() {
+		let config = FraudConfig { accounts_per_fingerprint: 2, ..Default::default() };
+		let mut a = activity("10.0.0.1");
+		a.fingerprints.insert("fp-1".into());
+		let mut b = activity("10.0.1.1");
+		b.fingerprints.insert("fp-1".into());
+		let mut c = activity("10.0.2.1");
+		c.fingerprints.insert("fp-2".into());
+		let activities: HashMap<String, VoterActivity> = vec![("a".to_string(), a), ("b".to_string(), b), ("c".to_string(), c)].into_iter().collect();
+		let signals = detect(&config, &activities);
+		assert_eq!(signals["a"], vec![SuspicionSignal::SharedFingerprint { fingerprint: "fp-1".into(), accounts: 2 }]);
+		assert!(!signals.contains_key("c"));
+	}
+
+	#[test]
+	fn reads_ip_and_fingerprint_from_voter_logs() {
+		let uid = ObjectId::new();
+		let login = doc! {
+			"_id": ObjectId::new(),
+			"VoterLogin": { "created_at": bson::DateTime::now(), "uid": uid, "email": null, "phone": null, "requester_ip": "10.0.0.1", "requester_additional_fingerprint": "fp-1" }
+		};
+		assert_eq!(voter_log_activity(&login), Some((uid.to_hex(), Some("10.0.0.1".to_string()), Some("fp-1".to_string()))));
+		let no_fingerprint = doc! { "VoterCreation": { "uid": uid, "requester_ip": null, "requester_additional_fingerprint": "" } };
+		assert_eq!(voter_log_activity(&no_fingerprint), Some((uid.to_hex(), None, None)));
+		// 发送验证码的记录没有uid
+		let send_email = doc! { "SendEmail": { "target_email": "a@example.com", "code": "123456", "requester_ip": "10.0.0.1" } };
+		assert_eq!(voter_log_activity(&send_email), None);
+	}
+
+	#[test]
+	fn maps_uid_to_vote_id_of_vote_year_only() {
+		let vote_ids = vec!["thvote-2021-62a0c0ffee".to_string(), "thvote-2022-62a0c0ffee".to_string(), "thvote-2022-62a0beef".to_string()];
+		let mapping = uid_to_vote_id(2022, vote_ids.iter());
+		assert_eq!(mapping.len(), 2);
+		assert_eq!(mapping["62a0c0ffee"], "thvote-2022-62a0c0ffee");
+		assert_eq!(mapping["62a0beef"], "thvote-2022-62a0beef");
+		assert_eq!(uid_to_vote_id(2021, vote_ids.iter())["62a0c0ffee"], "thvote-2021-62a0c0ffee");
+		assert_eq!(year_filter(2022), doc! { "meta.vote_id": { "$regex": "^thvote-2022-" } });
+	}
+
+	#[test]
+	fn review_log_id_is_stable_per_review() {
+		let report = SuspicionReport {
+			vote_id: "thvote-2022-1".into(),
+			score: 3.0,
+			signals: vec![SuspicionSignal::NewAccount { minutes: 1 }],
+			scan_run: ObjectId::new(),
+			scanned_at: bson::DateTime::now(),
+			review: Some(FraudReview { decision: ReviewDecision::Accept, reviewer: "alice".into(), note: None, reviewed_at: bson::DateTime::from_millis(1000) })
+		};
+		let review = FraudReview { decision: ReviewDecision::Exclude, reviewer: "bob".into(), note: None, reviewed_at: bson::DateTime::from_millis(2000) };
+		let log = review_log(&report, review.clone());
+		assert_eq!(log.id, "thvote-2022-1-2000");
+		assert_eq!(review_log(&report, review).id, log.id);
+		assert_eq!(log.previous_decision, Some(ReviewDecision::Accept));
+		assert_eq!(log.score, 3.0);
+	}
+}
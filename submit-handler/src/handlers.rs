@@ -2,57 +2,78 @@
 use actix_web::{web, HttpRequest};
 use pvrustlib::{EmptyJSON, ServiceError};
 
-use crate::{models, common::{rate_limit, SERVICE_NAME}};
+use crate::{models::{self, VoteCategory}, common::{SERVICE_NAME, SUBMIT_LOCK_TTL}, rules::VotingRules, lock::with_submit_lock};
 
 type SubmitServiceV1Wrapper = web::Data<crate::services::SubmitServiceV1>;
 
 pub async fn submit_character_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::CharacterSubmitRest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	service.check_voting_open(&body.0.meta)?;
 	let mut conn = service.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-	rate_limit(&body.0.meta.vote_id, &mut conn).await?;
+	service.throttle.check(VoteCategory::Character, &body.0.meta, &mut conn).await?;
 	let lockid = format!("lock-submit_character_v1-{}", body.0.meta.vote_id);
-	let guard = service.lock.acquire_async(lockid.as_bytes(), 10 * 1000).await;
-	let sanitized = service.validator.validate_character(body.0, &service.character_coll).await?;
-	service.submit_charcater(sanitized).await?;
+	with_submit_lock(service.lock.as_ref(), &lockid, SUBMIT_LOCK_TTL, async {
+		let sanitized = service.validator.validate_character(body.0, &service.character_coll).await?;
+		service.submit_charcater(sanitized).await
+	}).await?;
 	Ok(web::Json(EmptyJSON::new()))
 }
 
 pub async fn submit_music_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::MusicSubmitRest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	service.check_voting_open(&body.0.meta)?;
 	let mut conn = service.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-	rate_limit(&body.0.meta.vote_id, &mut conn).await?;
+	service.throttle.check(VoteCategory::Music, &body.0.meta, &mut conn).await?;
 	let lockid = format!("lock-submit_music_v1-{}", body.0.meta.vote_id);
-	let guard = service.lock.acquire_async(lockid.as_bytes(), 10 * 1000).await;
-	let sanitized = service.validator.validate_music(body.0, &service.music_coll).await?;
-	service.submit_music(sanitized).await?;
+	with_submit_lock(service.lock.as_ref(), &lockid, SUBMIT_LOCK_TTL, async {
+		let sanitized = service.validator.validate_music(body.0, &service.music_coll).await?;
+		service.submit_music(sanitized).await
+	}).await?;
+	Ok(web::Json(EmptyJSON::new()))
+}
+
+pub async fn submit_work_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::WorkSubmitRest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	service.check_voting_open(&body.0.meta)?;
+	let mut conn = service.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	service.throttle.check(VoteCategory::Work, &body.0.meta, &mut conn).await?;
+	let lockid = format!("lock-submit_work_v1-{}", body.0.meta.vote_id);
+	with_submit_lock(service.lock.as_ref(), &lockid, SUBMIT_LOCK_TTL, async {
+		let sanitized = service.validator.validate_work(body.0, &service.work_coll).await?;
+		service.submit_work(sanitized).await
+	}).await?;
 	Ok(web::Json(EmptyJSON::new()))
 }
 
 pub async fn submit_cp_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::CPSubmitRest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	service.check_voting_open(&body.0.meta)?;
 	let mut conn = service.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-	rate_limit(&body.0.meta.vote_id, &mut conn).await?;
+	service.throttle.check(VoteCategory::CP, &body.0.meta, &mut conn).await?;
 	let lockid = format!("lock-submit_cp_v1-{}", body.0.meta.vote_id);
-	let guard = service.lock.acquire_async(lockid.as_bytes(), 10 * 1000).await;
-	let sanitized = service.validator.validate_cp(body.0, &service.cp_coll).await?;
-	service.submit_cp(sanitized).await?;
+	with_submit_lock(service.lock.as_ref(), &lockid, SUBMIT_LOCK_TTL, async {
+		let sanitized = service.validator.validate_cp(body.0, &service.cp_coll).await?;
+		service.submit_cp(sanitized).await
+	}).await?;
 	Ok(web::Json(EmptyJSON::new()))
 }
 
 pub async fn submit_paper_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::PaperSubmitRest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	service.check_voting_open(&body.0.meta)?;
 	let mut conn = service.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-	rate_limit(&body.0.meta.vote_id, &mut conn).await?;
+	service.throttle.check(VoteCategory::Paper, &body.0.meta, &mut conn).await?;
 	let lockid = format!("lock-submit_paper_v1-{}", body.0.meta.vote_id);
-	let guard = service.lock.acquire_async(lockid.as_bytes(), 10 * 1000).await;
-	let sanitized = service.validator.validate_paper(body.0, &service.paper_coll).await?;
-	service.submit_paper(sanitized).await?;
+	with_submit_lock(service.lock.as_ref(), &lockid, SUBMIT_LOCK_TTL, async {
+		let sanitized = service.validator.validate_paper(body.0, &service.paper_coll).await?;
+		service.submit_paper(sanitized).await
+	}).await?;
 	Ok(web::Json(EmptyJSON::new()))
 }
 
 pub async fn submit_dojin_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::DojinSubmitRest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	service.check_voting_open(&body.0.meta)?;
 	let mut conn = service.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-	rate_limit(&body.0.meta.vote_id, &mut conn).await?;
-	let lockid = format!("lock-submit_character_v1-{}", body.0.meta.vote_id);
-	let guard = service.lock.acquire_async(lockid.as_bytes(), 10 * 1000).await;
+	service.throttle.check(VoteCategory::Dojin, &body.0.meta, &mut conn).await?;
+	let lockid = format!("lock-submit_dojin_v1-{}", body.0.meta.vote_id);
+	// 校验需要联网获取作品信息，且不依赖已有提交，放在锁外进行
 	let sanitized = service.validator.validate_dojin(body.0, &service.dojin_coll).await?;
-	service.submit_dojin(sanitized).await?;
+	with_submit_lock(service.lock.as_ref(), &lockid, SUBMIT_LOCK_TTL, service.submit_dojin(sanitized)).await?;
 	Ok(web::Json(EmptyJSON::new()))
 }
 
@@ -66,6 +87,10 @@ pub async fn get_submit_music_v1(service: SubmitServiceV1Wrapper, body: actix_we
 	Ok(web::Json(service.get_submit_music(body.0.vote_id).await?))
 }
 
+pub async fn get_submit_work_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::QuerySubmitRequest>) -> Result<web::Json<models::WorkSubmitRest>, ServiceError> {
+	Ok(web::Json(service.get_submit_work(body.0.vote_id).await?))
+}
+
 pub async fn get_submit_cp_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::QuerySubmitRequest>) -> Result<web::Json<models::CPSubmitRest>, ServiceError> {
 	let lockid = format!("lock-submit_cp_v1-{}", body.0.vote_id);
 	Ok(web::Json(service.get_submit_cp(body.0.vote_id).await?))
@@ -85,7 +110,49 @@ pub async fn get_voting_status_v1(service: SubmitServiceV1Wrapper, body: actix_w
 	Ok(web::Json(service.get_voting_status(body.0.vote_id).await?))
 }
 
+pub async fn get_voting_rules_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<EmptyJSON>) -> Result<web::Json<VotingRules>, ServiceError> {
+	Ok(web::Json(service.validator.rules.as_ref().clone()))
+}
+
 pub async fn get_voting_statistics_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<EmptyJSON>) -> Result<web::Json<models::VotingStatistics>, ServiceError> {
 	Ok(web::Json(service.get_voting_statistics().await?))
 }
 
+pub async fn save_draft_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::SaveDraftRequest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	service.save_draft(body.0.vote_id, body.0.category, body.0.content).await?;
+	Ok(web::Json(EmptyJSON::new()))
+}
+
+pub async fn get_draft_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::DraftRequest>) -> Result<web::Json<models::Draft>, ServiceError> {
+	Ok(web::Json(service.get_draft(body.0.vote_id, body.0.category).await?))
+}
+
+pub async fn withdraw_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::WithdrawRequest>) -> Result<web::Json<models::WithdrawResponse>, ServiceError> {
+	let withdrawn = service.withdraw(body.0.vote_id, body.0.category, body.0.withdrawn_by, body.0.reason, false).await?;
+	Ok(web::Json(models::WithdrawResponse { withdrawn }))
+}
+
+pub async fn admin_withdraw_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::AdminWithdrawRequest>) -> Result<web::Json<models::WithdrawResponse>, ServiceError> {
+	let admin = service.admin.authenticate(&body.0.admin_token)?;
+	let withdrawn = service.withdraw(body.0.vote_id, body.0.category, admin, body.0.reason, true).await?;
+	Ok(web::Json(models::WithdrawResponse { withdrawn }))
+}
+
+pub async fn get_fraud_reports_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::FraudReportsRequest>) -> Result<web::Json<models::FraudReportsResponse>, ServiceError> {
+	service.admin.authenticate(&body.0.admin_token)?;
+	let reports = service.fraud.list(body.0.min_score, body.0.offset, body.0.limit.unwrap_or(100)).await?;
+	Ok(web::Json(models::FraudReportsResponse { reports }))
+}
+
+pub async fn review_fraud_report_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::FraudReviewRequest>) -> Result<web::Json<crate::fraud::SuspicionReport>, ServiceError> {
+	let reviewer = service.admin.authenticate(&body.0.admin_token)?;
+	Ok(web::Json(service.fraud.review(body.0.vote_id, body.0.decision, reviewer, body.0.note).await?))
+}
+
+pub async fn get_history_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::HistoryRequest>) -> Result<web::Json<models::SubmitHistory>, ServiceError> {
+	Ok(web::Json(service.get_history(body.0.vote_id, body.0.category).await?))
+}
+
+pub async fn get_history_diff_v1(service: SubmitServiceV1Wrapper, body: actix_web::web::Json<models::HistoryDiffRequest>) -> Result<web::Json<models::SubmitDiff>, ServiceError> {
+	Ok(web::Json(service.get_history_diff(body.0.vote_id, body.0.category, body.0.from_attempt, body.0.to_attempt).await?))
+}
\ No newline at end of file
@@ -0,0 +1,171 @@
+
+use std::collections::{BTreeMap, HashMap};
+
+use bson::doc;
+use futures_util::TryStreamExt;
+use mongodb::{Collection, options::FindOptions};
+use pvrustlib::ServiceError;
+use serde::de::DeserializeOwned;
+
+use crate::{models::*, common::SERVICE_NAME};
+
+/// 将各类别的提交统一成可比较的列表
+pub trait HistoryItems: SubmitWithMeta {
+	fn history_items(&self) -> Vec<HistoryItem>;
+}
+
+fn ranked<I: Iterator<Item = (String, bool, Option<String>)>>(items: I) -> Vec<HistoryItem> {
+	items.enumerate().map(|(i, (id, first, content))| HistoryItem { id, rank: i + 1, first, content }).collect()
+}
+
+impl HistoryItems for CharacterSubmitRest {
+	fn history_items(&self) -> Vec<HistoryItem> {
+		ranked(self.characters.iter().map(|c| (c.id.clone(), c.first.unwrap_or_default(), c.reason.clone())))
+	}
+}
+
+impl HistoryItems for MusicSubmitRest {
+	fn history_items(&self) -> Vec<HistoryItem> {
+		ranked(self.music.iter().map(|c| (c.id.clone(), c.first.unwrap_or_default(), c.reason.clone())))
+	}
+}
+
+impl HistoryItems for WorkSubmitRest {
+	fn history_items(&self) -> Vec<HistoryItem> {
+		ranked(self.works.iter().map(|c| (c.id.clone(), c.first.unwrap_or_default(), c.reason.clone())))
+	}
+}
+
+impl HistoryItems for CPSubmitRest {
+	fn history_items(&self) -> Vec<HistoryItem> {
+		ranked(self.cps.iter().map(|c| {
+			let mut members = vec![c.id_a.clone(), c.id_b.clone()];
+			members.extend(c.id_c.clone());
+			members.sort();
+			let content = format!("主动方：{}；理由：{}", c.active.as_deref().unwrap_or(""), c.reason.as_deref().unwrap_or(""));
+			(members.join("x"), c.first.unwrap_or_default(), Some(content))
+		}))
+	}
+}
+
+impl HistoryItems for PaperSubmitRest {
+	fn history_items(&self) -> Vec<HistoryItem> {
+		let answers: BTreeMap<String, serde_json::Value> = serde_json::from_str(&self.papers_json).unwrap_or_default();
+		// 问卷没有排位
+		answers.into_iter().map(|(qid, ans)| HistoryItem { id: qid, rank: 0, first: false, content: Some(ans.to_string()) }).collect()
+	}
+}
+
+impl HistoryItems for DojinSubmitRest {
+	fn history_items(&self) -> Vec<HistoryItem> {
+		ranked(self.dojins.iter().map(|d| (d.url.clone(), false, Some(format!("{}：{}", d.title, d.reason)))))
+	}
+}
+
+/// 读取某一投票人在一个类别下的所有历史版本和当前版本，按提交时间从早到晚排列
+pub async fn load_attempts<T>(current: &Collection<T>, history: &Collection<T>, vote_id: &str) -> Result<Vec<SubmitAttempt>, ServiceError>
+	where T: HistoryItems + DeserializeOwned + Unpin + Send + Sync {
+	let options = FindOptions::builder().sort(doc! { "meta.created_at": 1 }).build();
+	let cursor = history.find(doc! { "meta.vote_id": vote_id }, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let mut submits: Vec<T> = cursor.try_collect().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	if let Some(submit) = current.find_one(doc! { "meta.vote_id": vote_id }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		submits.push(submit);
+	}
+	Ok(submits.into_iter().map(|s| SubmitAttempt { meta: s.meta().clone(), items: s.history_items() }).collect())
+}
+
+/// 比较两次提交
+pub fn diff_attempts(category: VoteCategory, from: &SubmitAttempt, to: &SubmitAttempt) -> SubmitDiff {
+	let from_items: HashMap<&str, &HistoryItem> = from.items.iter().map(|f| (f.id.as_str(), f)).collect();
+	let to_items: HashMap<&str, &HistoryItem> = to.items.iter().map(|f| (f.id.as_str(), f)).collect();
+	let mut diff = SubmitDiff {
+		category,
+		from_attempt: from.meta.attempt.unwrap_or_default(),
+		to_attempt: to.meta.attempt.unwrap_or_default(),
+		added: vec![],
+		removed: vec![],
+		reranked: vec![],
+		first_changed: vec![],
+		content_changed: vec![]
+	};
+	for item in from.items.iter() {
+		if !to_items.contains_key(item.id.as_str()) {
+			diff.removed.push(item.clone());
+		}
+	}
+	for item in to.items.iter() {
+		let old = match from_items.get(item.id.as_str()) {
+			Some(old) => old,
+			None => {
+				diff.added.push(item.clone());
+				continue;
+			}
+		};
+		if old.rank != item.rank {
+			diff.reranked.push(RerankedItem { id: item.id.clone(), from_rank: old.rank, to_rank: item.rank });
+		}
+		if old.first != item.first {
+			diff.first_changed.push(FirstChangedItem { id: item.id.clone(), from_first: old.first, to_first: item.first });
+		}
+		if old.content != item.content {
+			diff.content_changed.push(item.id.clone());
+		}
+	}
+	diff
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn attempt<T: HistoryItems>(attempt: i32, submit: T) -> SubmitAttempt {
+		let mut meta = SubmitMetadata::new();
+		meta.attempt = Some(attempt);
+		SubmitAttempt { meta, items: submit.history_items() }
+	}
+
+	fn character(id: &str, first: bool, reason: &str) -> CharacterSubmit {
+		CharacterSubmit { id: id.into(), first: Some(first), reason: Some(reason.into()) }
+	}
+
+	fn cp(id_a: &str, id_b: &str, active: Option<&str>, first: bool) -> CPSubmit {
+		CPSubmit { id_a: id_a.into(), id_b: id_b.into(), id_c: None, active: active.map(|f| f.to_string()), first: Some(first), reason: None }
+	}
+
+	#[test]
+	fn diff_reports_added_removed_and_reranked_items() {
+		let from = attempt(1, CharacterSubmitRest { characters: vec![character("博丽灵梦", true, "a"), character("雾雨魔理沙", false, "b"), character("琪露诺", false, "c")], meta: SubmitMetadata::new() });
+		let to = attempt(2, CharacterSubmitRest { characters: vec![character("雾雨魔理沙", false, "b"), character("博丽灵梦", true, "a"), character("东风谷早苗", false, "d")], meta: SubmitMetadata::new() });
+		let diff = diff_attempts(VoteCategory::Character, &from, &to);
+		assert_eq!((diff.from_attempt, diff.to_attempt), (1, 2));
+		assert_eq!(diff.added.iter().map(|f| (f.id.as_str(), f.rank)).collect::<Vec<_>>(), vec![("东风谷早苗", 3)]);
+		assert_eq!(diff.removed.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["琪露诺"]);
+		let reranked = diff.reranked.iter().map(|f| (f.id.as_str(), f.from_rank, f.to_rank)).collect::<Vec<_>>();
+		assert_eq!(reranked, vec![("雾雨魔理沙", 2, 1), ("博丽灵梦", 1, 2)]);
+		assert!(diff.first_changed.is_empty());
+		assert!(diff.content_changed.is_empty());
+	}
+
+	#[test]
+	fn diff_reports_first_and_content_changes() {
+		let from = attempt(1, CharacterSubmitRest { characters: vec![character("博丽灵梦", true, "a"), character("雾雨魔理沙", false, "b")], meta: SubmitMetadata::new() });
+		let to = attempt(2, CharacterSubmitRest { characters: vec![character("博丽灵梦", false, "a"), character("雾雨魔理沙", true, "新理由")], meta: SubmitMetadata::new() });
+		let diff = diff_attempts(VoteCategory::Character, &from, &to);
+		assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.reranked.is_empty());
+		let first_changed = diff.first_changed.iter().map(|f| (f.id.as_str(), f.from_first, f.to_first)).collect::<Vec<_>>();
+		assert_eq!(first_changed, vec![("博丽灵梦", true, false), ("雾雨魔理沙", false, true)]);
+		assert_eq!(diff.content_changed, vec!["雾雨魔理沙".to_string()]);
+	}
+
+	#[test]
+	fn cp_items_ignore_member_order() {
+		let from = attempt(1, CPSubmitRest { cps: vec![cp("博丽灵梦", "雾雨魔理沙", Some("博丽灵梦"), true), cp("琪露诺", "大妖精", None, false)], meta: SubmitMetadata::new() });
+		let to = attempt(2, CPSubmitRest { cps: vec![cp("雾雨魔理沙", "博丽灵梦", Some("雾雨魔理沙"), true), cp("东风谷早苗", "博丽灵梦", None, false)], meta: SubmitMetadata::new() });
+		let diff = diff_attempts(VoteCategory::CP, &from, &to);
+		// 交换成员顺序仍是同一个CP，主动方变化算作内容变化
+		assert_eq!(diff.content_changed, vec!["博丽灵梦x雾雨魔理沙".to_string()]);
+		assert!(diff.reranked.is_empty() && diff.first_changed.is_empty());
+		assert_eq!(diff.added.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["东风谷早苗x博丽灵梦"]);
+		assert_eq!(diff.removed.iter().map(|f| f.id.as_str()).collect::<Vec<_>>(), vec!["大妖精x琪露诺"]);
+	}
+}
@@ -0,0 +1,172 @@
+
+use std::{collections::HashMap, future::Future, sync::Mutex, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use pvrustlib::ServiceError;
+
+use crate::common::SERVICE_NAME;
+
+/// 持有者一致时才删除，避免删除已过期后被他人重新获取的锁
+const RELEASE_SCRIPT: &str = r#"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+	return redis.call('DEL', KEYS[1])
+end
+return 0
+"#;
+
+/// 同一投票人的提交互斥
+#[async_trait]
+pub trait SubmitLock: std::fmt::Debug + Send + Sync {
+	/// 尝试获取锁，成功时返回释放锁所需的token，锁已被占用时返回None
+	async fn try_acquire(&self, key: &str, ttl: Duration) -> Result<Option<String>, ServiceError>;
+	async fn release(&self, key: &str, token: &str) -> Result<(), ServiceError>;
+}
+
+fn new_token() -> String {
+	format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>())
+}
+
+/// 使用Redis SET NX PX实现的锁
+#[derive(Debug, Clone)]
+pub struct RedisSubmitLock {
+	pub client: redis::Client
+}
+
+#[async_trait]
+impl SubmitLock for RedisSubmitLock {
+	async fn try_acquire(&self, key: &str, ttl: Duration) -> Result<Option<String>, ServiceError> {
+		let mut conn = self.client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let token = new_token();
+		let result: Option<String> = redis::cmd("SET")
+			.arg(key)
+			.arg(&token)
+			.arg("NX")
+			.arg("PX")
+			.arg(ttl.as_millis() as u64)
+			.query_async(&mut conn)
+			.await
+			.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		Ok(result.map(|_| token))
+	}
+	async fn release(&self, key: &str, token: &str) -> Result<(), ServiceError> {
+		let mut conn = self.client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let _: i64 = redis::Script::new(RELEASE_SCRIPT)
+			.key(key)
+			.arg(token)
+			.invoke_async(&mut conn)
+			.await
+			.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		Ok(())
+	}
+}
+
+/// 进程内的锁，行为与RedisSubmitLock一致，用于测试
+#[derive(Debug, Default)]
+pub struct InMemorySubmitLock {
+	entries: Mutex<HashMap<String, (String, Instant)>>
+}
+
+#[async_trait]
+impl SubmitLock for InMemorySubmitLock {
+	async fn try_acquire(&self, key: &str, ttl: Duration) -> Result<Option<String>, ServiceError> {
+		let mut entries = self.entries.lock().unwrap();
+		let now = Instant::now();
+		if entries.get(key).map_or(false, |(_, expires_at)| *expires_at > now) {
+			return Ok(None);
+		}
+		let token = new_token();
+		entries.insert(key.to_string(), (token.clone(), now + ttl));
+		Ok(Some(token))
+	}
+	async fn release(&self, key: &str, token: &str) -> Result<(), ServiceError> {
+		let mut entries = self.entries.lock().unwrap();
+		if entries.get(key).map_or(false, |(holder, _)| holder == token) {
+			entries.remove(key);
+		}
+		Ok(())
+	}
+}
+
+/// 持有锁期间执行`f`，结束后释放锁。锁已被占用时返回SUBMIT_IN_PROGRESS
+pub async fn with_submit_lock<T, F>(lock: &dyn SubmitLock, key: &str, ttl: Duration, f: F) -> Result<T, ServiceError>
+	where F: Future<Output = Result<T, ServiceError>> {
+	let token = match lock.try_acquire(key, ttl).await? {
+		Some(token) => token,
+		None => return Err(ServiceError::new_human_readable(SERVICE_NAME, "SUBMIT_IN_PROGRESS", "上一次提交仍在处理中，请稍后重试".into()))
+	};
+	let result = f.await;
+	lock.release(key, &token).await?;
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+	use super::*;
+
+	const TTL: Duration = Duration::from_secs(10);
+
+	fn error_kind(e: &ServiceError) -> &str {
+		match e {
+			ServiceError::Error { resp } => &resp.error_kind
+		}
+	}
+
+	#[actix_rt::test]
+	async fn concurrent_submits_for_one_voter_serialize() {
+		let lock = Arc::new(InMemorySubmitLock::default());
+		let holders = Arc::new(AtomicUsize::new(0));
+		let max_holders = Arc::new(AtomicUsize::new(0));
+		let submit = |lock: Arc<InMemorySubmitLock>, holders: Arc<AtomicUsize>, max_holders: Arc<AtomicUsize>| async move {
+			with_submit_lock(lock.as_ref(), "lock-submit_character_v1-voter", TTL, async {
+				let current = holders.fetch_add(1, Ordering::SeqCst) + 1;
+				max_holders.fetch_max(current, Ordering::SeqCst);
+				tokio::time::sleep(Duration::from_millis(50)).await;
+				holders.fetch_sub(1, Ordering::SeqCst);
+				Ok(())
+			}).await
+		};
+		let (a, b) = tokio::join!(
+			submit(lock.clone(), holders.clone(), max_holders.clone()),
+			submit(lock.clone(), holders.clone(), max_holders.clone())
+		);
+		assert_eq!(max_holders.load(Ordering::SeqCst), 1);
+		let rejected: Vec<_> = vec![a, b].into_iter().filter_map(|r| r.err()).collect();
+		assert_eq!(rejected.len(), 1);
+		assert_eq!(error_kind(&rejected[0]), "SUBMIT_IN_PROGRESS");
+		// 锁在前一次提交完成后释放
+		assert!(submit(lock.clone(), holders.clone(), max_holders.clone()).await.is_ok());
+	}
+
+	#[actix_rt::test]
+	async fn lock_is_released_on_error() {
+		let lock = InMemorySubmitLock::default();
+		let failed: Result<(), ServiceError> = with_submit_lock(&lock, "k", TTL, async {
+			Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "".into()))
+		}).await;
+		assert_eq!(error_kind(&failed.unwrap_err()), "INVALID_CONTENT");
+		assert!(with_submit_lock(&lock, "k", TTL, async { Ok(()) }).await.is_ok());
+	}
+
+	#[actix_rt::test]
+	async fn different_voters_do_not_contend() {
+		let lock = InMemorySubmitLock::default();
+		let token = lock.try_acquire("lock-a", TTL).await.unwrap().unwrap();
+		assert!(lock.try_acquire("lock-a", TTL).await.unwrap().is_none());
+		assert!(lock.try_acquire("lock-b", TTL).await.unwrap().is_some());
+		// 其他持有者的token不能释放锁
+		lock.release("lock-a", "not-the-holder").await.unwrap();
+		assert!(lock.try_acquire("lock-a", TTL).await.unwrap().is_none());
+		lock.release("lock-a", &token).await.unwrap();
+		assert!(lock.try_acquire("lock-a", TTL).await.unwrap().is_some());
+	}
+
+	#[actix_rt::test]
+	async fn expired_lock_can_be_taken_over() {
+		let lock = InMemorySubmitLock::default();
+		assert!(lock.try_acquire("k", Duration::from_millis(10)).await.unwrap().is_some());
+		tokio::time::sleep(Duration::from_millis(20)).await;
+		assert!(lock.try_acquire("k", TTL).await.unwrap().is_some());
+	}
+}
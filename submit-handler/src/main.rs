@@ -1,6 +1,7 @@
 
 use actix_web::{web::{self, Data}, App, HttpServer};
 use mongodb::{options::ClientOptions, Client};
+use serde::{Deserialize, Serialize};
 use tokio::runtime::Handle;
 
 mod comm;
@@ -10,17 +11,80 @@ mod handlers;
 mod services;
 mod validator;
 mod paper_validator;
+mod history;
+mod migrate;
+mod rules;
+mod dojin;
+mod throttle;
+mod lock;
+mod finalize;
+mod fraud;
+mod admin;
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Config_vote_date {
+    pub vote_year: u32,
+    pub vote_start: String,
+    pub vote_end: String,
+    /// 投票结束后仍接受提交的秒数
+    #[serde(default)]
+    pub grace_period_seconds: i64,
+    /// 强制关闭投票，只允许查询
+    #[serde(default)]
+    pub voting_closed: bool
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct Config {
+    pub vote_date: Config_vote_date,
+    /// 提交限流，未配置时使用默认值
+    #[serde(default)]
+    pub throttle: throttle::ThrottleConfig,
+    /// 作弊检测，未配置时使用默认值
+    #[serde(default)]
+    pub fraud: fraud::FraudConfig,
+    /// 管理接口令牌，未配置时管理接口不可用
+    #[serde(default)]
+    pub admin: admin::AdminConfig,
+}
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     //std::env::set_var("RUST_LOG", "actix_web=debug");
+    let config: Config = toml::from_str(&std::fs::read_to_string("../keys/config.toml").unwrap()).expect("Config must be a valid toml file");
+
+    let schedule = models::VoteSchedule {
+        vote_year: config.vote_date.vote_year,
+        vote_start: chrono::DateTime::parse_from_rfc3339(&config.vote_date.vote_start).unwrap().with_timezone(&chrono::Utc),
+        vote_end: chrono::DateTime::parse_from_rfc3339(&config.vote_date.vote_end).unwrap().with_timezone(&chrono::Utc),
+        grace_period: chrono::Duration::seconds(config.vote_date.grace_period_seconds),
+        voting_closed: config.vote_date.voting_closed
+    };
+    let paper = paper_validator::PaperDefinition::load(paper_validator::PAPER_DEFINITION_PATH).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    let rules = rules::VotingRules::load(config.vote_date.vote_year).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+
     let client_options = ClientOptions::parse(common::MONGODB_URL).await.unwrap();
     let client = Client::with_options(client_options).unwrap();
     let db = client.database("submits_v1");
+    let catalog_db = client.database("submits_v1_final");
+    let votes_coll = catalog_db.collection::<bson::Document>("votes");
+    let voters_coll = client.database("thvote_users").collection::<bson::Document>("voters");
+    let voter_logs_coll = client.database("thvote_users").collection::<bson::Document>("voter_logs");
 
-    let redlock = redlock::RedLock::new(vec![comm::REDIS_ADDRESS]);
     let redis_client = redis::Client::open(comm::REDIS_ADDRESS).unwrap();
-    let submit_service_v1 = services::SubmitServiceV1::new(db.clone(), redis_client, redlock).await;
+    let submit_lock = std::sync::Arc::new(lock::RedisSubmitLock { client: redis_client.clone() });
+    let dojin_resolver = std::sync::Arc::new(dojin::ScraperMetadataResolver::new());
+    let submit_service_v1 = services::SubmitServiceV1::new(db.clone(), catalog_db, schedule, paper, rules, config.throttle, config.fraud, config.admin, dojin_resolver, redis_client, submit_lock).await;
+
+    if let Some(command) = std::env::args().nth(1) {
+        match command.as_str() {
+            "migrate" => migrate::migrate_raw_collections(&db, &submit_service_v1).await.expect("Migration failed"),
+            "fraud-scan" => submit_service_v1.fraud.scan(&submit_service_v1, config.vote_date.vote_year, &voters_coll, &voter_logs_coll).await.expect("Fraud scan failed"),
+            "finalize" => finalize::finalize_votes(&submit_service_v1, &votes_coll, config.vote_date.vote_year).await.expect("Finalization failed"),
+            _ => panic!("Unknown command {}", command)
+        }
+        return Ok(());
+    }
 
     // Start http server
     HttpServer::new(move || {
@@ -28,15 +92,27 @@ async fn main() -> std::io::Result<()> {
             .app_data(Data::new(submit_service_v1.clone()))
             .route("/v1/character/", web::post().to(handlers::submit_character_v1))
             .route("/v1/music/", web::post().to(handlers::submit_music_v1))
+            .route("/v1/work/", web::post().to(handlers::submit_work_v1))
             .route("/v1/cp/", web::post().to(handlers::submit_cp_v1))
             .route("/v1/paper/", web::post().to(handlers::submit_paper_v1))
             .route("/v1/dojin/", web::post().to(handlers::submit_dojin_v1))
             .route("/v1/get-character/", web::post().to(handlers::get_submit_character_v1))
             .route("/v1/get-music/", web::post().to(handlers::get_submit_music_v1))
+            .route("/v1/get-work/", web::post().to(handlers::get_submit_work_v1))
             .route("/v1/get-cp/", web::post().to(handlers::get_submit_cp_v1))
             .route("/v1/get-paper/", web::post().to(handlers::get_submit_paper_v1))
             .route("/v1/get-dojin/", web::post().to(handlers::get_submit_dojin_v1))
             .route("/v1/voting-status/", web::post().to(handlers::get_voting_status_v1))
+            .route("/v1/voting-rules/", web::post().to(handlers::get_voting_rules_v1))
+            .route("/v1/voting-statistics/", web::post().to(handlers::get_voting_statistics_v1))
+            .route("/v1/save-draft/", web::post().to(handlers::save_draft_v1))
+            .route("/v1/get-draft/", web::post().to(handlers::get_draft_v1))
+            .route("/v1/withdraw/", web::post().to(handlers::withdraw_v1))
+            .route("/v1/admin-withdraw/", web::post().to(handlers::admin_withdraw_v1))
+            .route("/v1/fraud-reports/", web::post().to(handlers::get_fraud_reports_v1))
+            .route("/v1/fraud-review/", web::post().to(handlers::review_fraud_report_v1))
+            .route("/v1/history/", web::post().to(handlers::get_history_v1))
+            .route("/v1/history-diff/", web::post().to(handlers::get_history_diff_v1))
     })
     .bind("0.0.0.0:80")?
     .run()
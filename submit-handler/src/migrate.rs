@@ -0,0 +1,68 @@
+
+use bson::{doc, Document};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, options::{AggregateOptions, UpdateOptions}};
+use pvrustlib::ServiceError;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{common::SERVICE_NAME, models::*, services::{SubmitServiceV1, upsert_history}};
+
+/// 从旧的raw_*集合生成current_*和history_*集合，已有当前提交的投票人会被跳过
+pub async fn migrate_raw_collections(db: &Database, service: &SubmitServiceV1) -> Result<(), ServiceError> {
+	migrate_category(db, VoteCategory::Character, &service.character_coll, &service.character_history_coll, &service.attempt_counter_coll).await?;
+	migrate_category(db, VoteCategory::Music, &service.music_coll, &service.music_history_coll, &service.attempt_counter_coll).await?;
+	migrate_category(db, VoteCategory::Work, &service.work_coll, &service.work_history_coll, &service.attempt_counter_coll).await?;
+	migrate_category(db, VoteCategory::CP, &service.cp_coll, &service.cp_history_coll, &service.attempt_counter_coll).await?;
+	migrate_category(db, VoteCategory::Paper, &service.paper_coll, &service.paper_history_coll, &service.attempt_counter_coll).await?;
+	migrate_category(db, VoteCategory::Dojin, &service.dojin_coll, &service.dojin_history_coll, &service.attempt_counter_coll).await?;
+	Ok(())
+}
+
+async fn migrate_category<T>(db: &Database, category: VoteCategory, current: &Collection<T>, history: &Collection<T>, counters: &Collection<Document>) -> Result<(), ServiceError>
+	where T: Serialize + DeserializeOwned + SubmitWithMeta + Unpin + Send + Sync {
+	let raw_name = format!("raw_{}", category.name());
+	let stages = vec![
+		doc!{"$sort": {"meta.vote_id": 1, "meta.created_at": 1}}
+	];
+	let options = AggregateOptions::builder().allow_disk_use(true).build();
+	let mut cursor = db.collection::<Document>(&raw_name).aggregate(stages, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let mut pending: Vec<T> = vec![];
+	let mut migrated = 0;
+	while let Some(submit) = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+		let submit: T = bson::from_document(submit).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		if pending.last().map_or(false, |f| f.meta().vote_id != submit.meta().vote_id) {
+			migrated += migrate_voter(category, current, history, counters, std::mem::take(&mut pending)).await?;
+		}
+		pending.push(submit);
+	}
+	if !pending.is_empty() {
+		migrated += migrate_voter(category, current, history, counters, pending).await?;
+	}
+	println!("{}: migrated {} voters", raw_name, migrated);
+	Ok(())
+}
+
+async fn migrate_voter<T>(category: VoteCategory, current: &Collection<T>, history: &Collection<T>, counters: &Collection<Document>, mut submits: Vec<T>) -> Result<u64, ServiceError>
+	where T: Serialize + DeserializeOwned + SubmitWithMeta + Unpin + Send + Sync {
+	let vote_id = submits[0].meta().vote_id.clone();
+	let existing = current.count_documents(doc! { "meta.vote_id": &vote_id }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	if existing != 0 {
+		return Ok(0);
+	}
+	for (i, submit) in submits.iter_mut().enumerate() {
+		submit.meta_mut().attempt = Some(i as i32 + 1);
+	}
+	let num_attempts = submits.len() as i32;
+	let latest = submits.pop().unwrap();
+	// 中途失败后重新迁移时历史版本按(vote_id, attempt)覆盖，不会重复
+	for submit in submits {
+		upsert_history(history, submit).await?;
+	}
+	current.insert_one(latest, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	counters.update_one(
+		doc! { "_id": format!("{}-{}", category.name(), vote_id) },
+		doc! { "$max": { "seq": num_attempts } },
+		UpdateOptions::builder().upsert(true).build()
+	).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(1)
+}
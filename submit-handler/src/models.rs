@@ -7,6 +7,25 @@ pub struct VoteTokenClaim {
 	pub vote_id: Option<String>
 }
 
+/// 可投票的角色或音乐（与result-query共用chars/musics集合）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VotableItem {
+	pub vote_year: i32,
+	pub name: String
+}
+
+/// 投票时间安排
+#[derive(Debug, Clone)]
+pub struct VoteSchedule {
+	pub vote_year: u32,
+	pub vote_start: chrono::DateTime<chrono::Utc>,
+	pub vote_end: chrono::DateTime<chrono::Utc>,
+	/// 投票结束后的宽限时间
+	pub grace_period: chrono::Duration,
+	/// 只读模式，拒绝所有提交
+	pub voting_closed: bool
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SubmitMetadata {
 	/// 投票人id
@@ -18,7 +37,10 @@ pub struct SubmitMetadata {
 	/// 用户IP
 	pub user_ip: String,
 	/// 额外用户指纹信息
-	pub additional_fingreprint: Option<String>
+	pub additional_fingreprint: Option<String>,
+	/// 撤回信息，撤回后视为该类别未投票（由本程序生成，无需提交）
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub withdrawal: Option<Withdrawal>
 }
 impl SubmitMetadata {
 	pub fn new() -> SubmitMetadata {
@@ -27,11 +49,42 @@ impl SubmitMetadata {
 			attempt: None,
 			created_at: bson::DateTime::now(),
 			user_ip: "<unknown>".into(),
-			additional_fingreprint: None
+			additional_fingreprint: None,
+			withdrawal: None
 		}
 	}
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Withdrawal {
+	/// 撤回者，投票人本人为voter，其他服务为服务名
+	pub withdrawn_by: String,
+	pub reason: Option<String>,
+	/// 撤回时间
+	pub withdrawn_at: bson::DateTime
+}
+
+/// 所有带有提交信息的提交
+pub trait SubmitWithMeta {
+	fn meta(&self) -> &SubmitMetadata;
+	fn meta_mut(&mut self) -> &mut SubmitMetadata;
+}
+
+macro_rules! impl_submit_with_meta {
+	($($t:ty),*) => {
+		$(impl SubmitWithMeta for $t {
+			fn meta(&self) -> &SubmitMetadata {
+				&self.meta
+			}
+			fn meta_mut(&mut self) -> &mut SubmitMetadata {
+				&mut self.meta
+			}
+		})*
+	};
+}
+
+impl_submit_with_meta!(CharacterSubmitRest, MusicSubmitRest, WorkSubmitRest, CPSubmitRest, PaperSubmitRest, DojinSubmitRest);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CharacterSubmitRest {
 	pub characters: Vec<CharacterSubmit>,
@@ -75,7 +128,24 @@ pub struct DojinSubmit {
 	pub title: String,
 	pub author: String,
 	pub reason: String,
-	pub image_url: Option<String>
+	pub image_url: Option<String>,
+	/// 作品id，相同URL的作品共用同一个id（由本程序生成，无需提交）
+	#[serde(default)]
+	pub work_id: Option<String>
+}
+
+/// 同人作品登记，每个规范化后的URL对应一个作品id
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DojinWork {
+	#[serde(rename = "_id")]
+	pub work_id: bson::oid::ObjectId,
+	pub url: String,
+	/// 首次提名时的作品信息
+	pub dojin_type: String,
+	pub title: String,
+	pub author: String,
+	/// 首次提名时间
+	pub created_at: bson::DateTime
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -97,29 +167,41 @@ pub struct CPSubmit {
 	pub reason: Option<String>
 }
 
-impl PartialEq for CPSubmit {
-    fn eq(&self, other: &Self) -> bool {
-        if self.id_c.is_some() ^ other.id_c.is_some() {
-			return false;
-		}
-        if self.active.is_some() ^ other.active.is_some() {
-			return false;
+/// CP的规范形式，成员按名字排序
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CanonicalCP<'a> {
+	pub members: Vec<&'a str>,
+	pub active: Option<&'a str>
+}
+
+impl std::fmt::Display for CanonicalCP<'_> {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.members.join("×"))?;
+		if let Some(active) = self.active {
+			write!(f, "（主动方：{}）", active)?;
 		}
-		if let (Some(c1), Some(c2)) = (&self.active, &other.active) {
-			if c1 != c2 {
-				return false;
-			}
+		Ok(())
+	}
+}
+
+impl CPSubmit {
+	pub fn canonical(&self) -> CanonicalCP<'_> {
+		let mut members = vec![self.id_a.as_str(), self.id_b.as_str()];
+		if let Some(id_c) = &self.id_c {
+			members.push(id_c.as_str());
 		}
-		if let (Some(c1), Some(c2)) = (&self.id_c, &other.id_c) {
-			((self.id_a == other.id_a) && (self.id_b == other.id_b) && (*c1 == *c2)) ||
-			((self.id_a == other.id_b) && (self.id_b == other.id_a) && (*c1 == *c2)) ||
-			((self.id_a == *c2) && (self.id_b == other.id_b) && (*c1 == other.id_a)) ||
-			((self.id_a == other.id_a) && (self.id_b == *c2) && (*c1 == other.id_b))
-		} else {
-			((self.id_a == other.id_a) && (self.id_b == other.id_b)) ||
-			((self.id_a == other.id_b) && (self.id_b == other.id_a))
+		members.sort();
+		CanonicalCP {
+			members,
+			active: self.active.as_deref()
 		}
-    }
+	}
+}
+
+impl PartialEq for CPSubmit {
+	fn eq(&self, other: &Self) -> bool {
+		self.canonical() == other.canonical()
+	}
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -132,7 +214,8 @@ pub struct MusicSubmit {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkSubmit {
 	pub id: String,
-	pub reason: Option<String>
+	pub reason: Option<String>,
+	pub first: Option<bool>
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -152,15 +235,204 @@ pub struct VotingStatus {
 	pub papers: bool,
 	/// 同人作品是否提交
 	pub dojin: bool,
+	/// 作品是否完成
+	pub works: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HourlySubmitCount {
+	/// 整点时间（UTC），如2021-10-01T13:00:00Z
+	pub hour: String,
+	/// 该小时内各类别的提交次数（含被覆盖的提交）
+	pub count: u64
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VotingStatistics {
+	/// 提交过任意类别的投票人数
 	pub num_user: u64,
+	/// 提交问卷的人数
 	pub num_finished_paper: u64,
+	/// 人物、音乐、CP和问卷均已提交的人数
 	pub num_finished_voting: u64,
 	pub num_character: u64,
 	pub num_cp: u64,
 	pub num_music: u64,
 	pub num_dojin: u64,
+	pub num_work: u64,
+	/// 按小时统计的提交次数，从早到晚排列
+	pub hourly_submits: Vec<HourlySubmitCount>
+}
+
+/// 投票类别
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteCategory {
+	Character,
+	Music,
+	Work,
+	CP,
+	Paper,
+	Dojin
+}
+
+impl VoteCategory {
+	pub fn name(&self) -> &'static str {
+		match self {
+			VoteCategory::Character => "character",
+			VoteCategory::Music => "music",
+			VoteCategory::Work => "work",
+			VoteCategory::CP => "cp",
+			VoteCategory::Paper => "paper",
+			VoteCategory::Dojin => "dojin",
+		}
+	}
+}
+
+/// 草稿内容不做校验，只检查是合法的JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SaveDraftRequest {
+	pub vote_id: String,
+	pub category: VoteCategory,
+	pub content: String
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DraftRequest {
+	pub vote_id: String,
+	pub category: VoteCategory
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Draft {
+	pub category: VoteCategory,
+	/// 没有草稿或草稿已过期时为None
+	pub content: Option<String>,
+	pub saved_at: Option<chrono::DateTime<chrono::Utc>>
+}
+
+/// 撤回当前提交
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawRequest {
+	pub vote_id: String,
+	/// 为None时撤回所有类别
+	pub category: Option<VoteCategory>,
+	pub withdrawn_by: String,
+	#[serde(default)]
+	pub reason: Option<String>
+}
+
+/// 管理员撤回提交，不受投票时间限制（如注销账号）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminWithdrawRequest {
+	pub admin_token: String,
+	pub vote_id: String,
+	/// 为None时撤回所有类别
+	pub category: Option<VoteCategory>,
+	#[serde(default)]
+	pub reason: Option<String>
+}
+
+/// 实际被撤回的类别
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WithdrawResponse {
+	pub withdrawn: Vec<VoteCategory>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FraudReportsRequest {
+	pub admin_token: String,
+	/// 只列出分数不低于该值的报告
+	#[serde(default)]
+	pub min_score: f64,
+	#[serde(default)]
+	pub offset: u64,
+	/// 默认100
+	#[serde(default)]
+	pub limit: Option<i64>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FraudReportsResponse {
+	pub reports: Vec<crate::fraud::SuspicionReport>
+}
+
+/// 人工审核
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FraudReviewRequest {
+	/// 审核人为令牌对应的管理员
+	pub admin_token: String,
+	pub vote_id: String,
+	pub decision: crate::fraud::ReviewDecision,
+	#[serde(default)]
+	pub note: Option<String>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryRequest {
+	pub vote_id: String,
+	pub category: VoteCategory
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryDiffRequest {
+	pub vote_id: String,
+	pub category: VoteCategory,
+	/// 较早的提交次数
+	pub from_attempt: i32,
+	/// 较晚的提交次数
+	pub to_attempt: i32
+}
+
+/// 一次提交中的一项
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryItem {
+	/// 角色/音乐/作品id，CP成员，问题id或同人作品URL
+	pub id: String,
+	/// 在本次提交中的排位，从1开始，问卷为0
+	pub rank: usize,
+	/// 是否本命
+	pub first: bool,
+	/// 理由或回答内容
+	pub content: Option<String>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitAttempt {
+	pub meta: SubmitMetadata,
+	pub items: Vec<HistoryItem>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitHistory {
+	pub category: VoteCategory,
+	/// 按提交时间从早到晚排列
+	pub attempts: Vec<SubmitAttempt>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RerankedItem {
+	pub id: String,
+	pub from_rank: usize,
+	pub to_rank: usize
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FirstChangedItem {
+	pub id: String,
+	pub from_first: bool,
+	pub to_first: bool
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitDiff {
+	pub category: VoteCategory,
+	pub from_attempt: i32,
+	pub to_attempt: i32,
+	pub added: Vec<HistoryItem>,
+	pub removed: Vec<HistoryItem>,
+	pub reranked: Vec<RerankedItem>,
+	pub first_changed: Vec<FirstChangedItem>,
+	/// 理由或回答内容有变化的项
+	pub content_changed: Vec<String>
 }
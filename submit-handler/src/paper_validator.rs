@@ -1,2 +1,261 @@
 
+use std::collections::{BTreeMap, HashSet};
 
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize};
+
+use crate::common::SERVICE_NAME;
+
+/// 问卷定义文件位置
+pub const PAPER_DEFINITION_PATH: &str = "../keys/paper.json";
+
+/// 性别问题，result-query统计男女票数时读取其第一个选项，无论定义文件如何设置都必须选择
+pub const GENDER_QUESTION: &str = "q11011";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QuestionKind {
+	/// 单选
+	Single,
+	/// 多选
+	Multiple,
+	/// 填空
+	Text
+}
+
+/// 仅当`question`的回答中包含`any_of`中任一选项时才需要回答
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuestionCondition {
+	pub question: String,
+	pub any_of: Vec<String>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuestionDefinition {
+	/// 问题id，如q11011
+	pub id: String,
+	pub kind: QuestionKind,
+	/// 可选项id
+	#[serde(default)]
+	pub options: Vec<String>,
+	/// 是否必答
+	#[serde(default)]
+	pub required: bool,
+	/// 多选题最少选择数
+	#[serde(default)]
+	pub min_choices: Option<usize>,
+	/// 多选题最多选择数
+	#[serde(default)]
+	pub max_choices: Option<usize>,
+	/// 选择题是否允许附带文字回答（如“其他”）
+	#[serde(default)]
+	pub allow_text: bool,
+	/// 文字回答最大长度（字符数）
+	#[serde(default = "default_max_length")]
+	pub max_length: usize,
+	/// 条件问题
+	#[serde(default)]
+	pub condition: Option<QuestionCondition>
+}
+
+fn default_max_length() -> usize {
+	4096
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaperDefinition {
+	pub questions: Vec<QuestionDefinition>
+}
+
+/// 单个问题的回答，与result-query中的SinglePaperItem一致
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PaperAnswer {
+	#[serde(default)]
+	pub opt: Vec<String>,
+	#[serde(default)]
+	pub ans: String
+}
+
+impl PaperAnswer {
+	fn is_empty(&self) -> bool {
+		self.opt.is_empty() && self.ans.is_empty()
+	}
+}
+
+fn invalid(msg: String) -> ServiceError {
+	ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", msg)
+}
+
+impl PaperDefinition {
+	pub fn load(path: &str) -> Result<Self, String> {
+		let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read questionnaire definition {}: {}", path, e))?;
+		let definition: PaperDefinition = serde_json::from_str(&content).map_err(|e| format!("Questionnaire definition {} is not valid: {}", path, e))?;
+		definition.check().map_err(|e| format!("Questionnaire definition {} is not valid: {}", path, e))?;
+		Ok(definition)
+	}
+
+	/// 检查定义自身是否合法，条件问题只能依赖在其之前定义的选择题
+	fn check(&self) -> Result<(), String> {
+		let mut seen: HashSet<&str> = HashSet::new();
+		for q in self.questions.iter() {
+			if let Some(cond) = &q.condition {
+				if !seen.contains(cond.question.as_str()) {
+					return Err(format!("question {} depends on {} which is not defined before it", q.id, cond.question));
+				}
+			}
+			if !seen.insert(q.id.as_str()) {
+				return Err(format!("question {} defined more than once", q.id));
+			}
+		}
+		match self.questions.iter().find(|q| q.id == GENDER_QUESTION) {
+			Some(q) if q.kind != QuestionKind::Text && !q.options.is_empty() && q.condition.is_none() => Ok(()),
+			Some(_) => Err(format!("question {} must be an unconditional choice question with options", GENDER_QUESTION)),
+			None => Err(format!("question {} is missing", GENDER_QUESTION))
+		}
+	}
+
+	/// 校验问卷回答，返回规范化后的JSON
+	pub fn validate(&self, papers_json: &str) -> Result<String, ServiceError> {
+		let answers: BTreeMap<String, PaperAnswer> = serde_json::from_str(papers_json).map_err(|_| invalid("问卷格式错误".into()))?;
+		for qid in answers.keys() {
+			if !self.questions.iter().any(|q| q.id == *qid) {
+				return Err(invalid(format!("问题{}不存在", qid)));
+			}
+		}
+		for q in self.questions.iter() {
+			let answer = answers.get(&q.id).filter(|a| !a.is_empty());
+			let active = match &q.condition {
+				Some(cond) => answers.get(&cond.question).map_or(false, |a| a.opt.iter().any(|o| cond.any_of.contains(o))),
+				None => true
+			};
+			let answer = match (answer, active) {
+				(Some(_), false) => return Err(invalid(format!("问题{}无需回答", q.id))),
+				(None, true) if q.required || q.id == GENDER_QUESTION => return Err(invalid(format!("问题{}未回答", q.id))),
+				(Some(answer), true) => answer,
+				(None, _) => continue
+			};
+			if answer.ans.chars().count() > q.max_length {
+				return Err(invalid(format!("问题{}回答过长", q.id)));
+			}
+			match q.kind {
+				QuestionKind::Text => {
+					if !answer.opt.is_empty() {
+						return Err(invalid(format!("问题{}为填空题", q.id)));
+					}
+				},
+				QuestionKind::Single | QuestionKind::Multiple => {
+					if !q.allow_text && !answer.ans.is_empty() {
+						return Err(invalid(format!("问题{}不接受文字回答", q.id)));
+					}
+					let mut chosen: HashSet<&str> = HashSet::new();
+					for o in answer.opt.iter() {
+						if !q.options.contains(o) {
+							return Err(invalid(format!("问题{}不存在选项{}", q.id, o)));
+						}
+						if !chosen.insert(o.as_str()) {
+							return Err(invalid(format!("问题{}选项{}重复", q.id, o)));
+						}
+					}
+					let (min, max) = if q.kind == QuestionKind::Single {
+						(1, 1)
+					} else {
+						(q.min_choices.unwrap_or(1), q.max_choices.unwrap_or(q.options.len()))
+					};
+					// 允许文字回答时可以不选择任何选项，性别问题除外
+					let min = if q.id == GENDER_QUESTION {
+						min.max(1)
+					} else if q.allow_text && !answer.ans.is_empty() {
+						0
+					} else {
+						min
+					};
+					if chosen.len() < min || chosen.len() > max {
+						return Err(invalid(format!("问题{}选项数量{}不在范围内[{},{}]", q.id, chosen.len(), min, max)));
+					}
+				}
+			}
+		}
+		let answers: BTreeMap<&String, &PaperAnswer> = answers.iter().filter(|(_, a)| !a.is_empty()).collect();
+		serde_json::to_string(&answers).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn definition() -> PaperDefinition {
+		serde_json::from_str(r#"{ "questions": [
+			{ "id": "q11011", "kind": "single", "options": ["1101101", "1101102"] },
+			{ "id": "q12011", "kind": "multiple", "options": ["1201101", "1201102", "1201103"], "min_choices": 2, "max_choices": 2, "allow_text": true, "max_length": 5 },
+			{ "id": "q12021", "kind": "text", "required": true, "max_length": 4, "condition": { "question": "q12011", "any_of": ["1201103"] } },
+			{ "id": "q22011", "kind": "text", "max_length": 3 }
+		] }"#).unwrap()
+	}
+
+	fn error_message(e: ServiceError) -> String {
+		match e {
+			ServiceError::Error { resp } => resp.human_readable_message.unwrap_or_default()
+		}
+	}
+
+	#[test]
+	fn single_choice_takes_exactly_one_known_option() {
+		let paper = definition();
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101102"]}}"#).is_ok());
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101","1101102"]}}"#).is_err());
+		assert!(paper.validate(r#"{"q11011":{"opt":["1109999"]}}"#).is_err());
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"],"ans":"x"}}"#).is_err());
+		assert!(paper.validate(r#"{"q99999":{"ans":"x"},"q11011":{"opt":["1101101"]}}"#).is_err());
+	}
+
+	#[test]
+	fn multiple_choice_respects_bounds_and_duplicates() {
+		let paper = definition();
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q12011":{"opt":["1201101","1201102"]}}"#).is_ok());
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q12011":{"opt":["1201101"]}}"#).is_err());
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q12011":{"opt":["1201101","1201101"]}}"#).is_err());
+		// 附带文字回答时可以不选
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q12011":{"ans":"其他"}}"#).is_ok());
+	}
+
+	#[test]
+	fn text_length_is_counted_in_characters() {
+		let paper = definition();
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q22011":{"ans":"灵梦啊"}}"#).is_ok());
+		assert!(error_message(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q22011":{"ans":"灵梦啊啊"}}"#).unwrap_err()).contains("过长"));
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q22011":{"opt":["1"]}}"#).is_err());
+	}
+
+	#[test]
+	fn conditional_question_follows_its_trigger() {
+		let paper = definition();
+		// 未选择1201103时不能回答q12021
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q12011":{"opt":["1201101","1201102"]},"q12021":{"ans":"a"}}"#).is_err());
+		// 选择1201103后q12021必答
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q12011":{"opt":["1201101","1201103"]}}"#).is_err());
+		assert!(paper.validate(r#"{"q11011":{"opt":["1101101"]},"q12011":{"opt":["1201101","1201103"]},"q12021":{"ans":"a"}}"#).is_ok());
+	}
+
+	#[test]
+	fn gender_question_is_always_required() {
+		let paper = definition();
+		assert!(!paper.questions[0].required);
+		assert!(paper.validate("{}").is_err());
+		assert!(paper.validate(r#"{"q11011":{"opt":[]}}"#).is_err());
+		let mut with_text = definition();
+		with_text.questions[0].allow_text = true;
+		assert!(with_text.validate(r#"{"q11011":{"ans":"秘密"}}"#).is_err());
+	}
+
+	#[test]
+	fn definition_without_gender_choices_is_rejected() {
+		let mut paper = definition();
+		paper.questions[0].options.clear();
+		assert!(paper.check().is_err());
+		paper.questions.remove(0);
+		assert!(paper.check().is_err());
+		assert!(definition().check().is_ok());
+		assert!(PaperDefinition::load("/nonexistent/paper.json").unwrap_err().contains("/nonexistent/paper.json"));
+	}
+}
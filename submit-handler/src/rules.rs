@@ -0,0 +1,181 @@
+
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize};
+
+use crate::common::SERVICE_NAME;
+
+/// 本命规则
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FirstRule {
+	/// 必须选择一个本命
+	Required,
+	/// 最多选择一个本命
+	Optional
+}
+
+/// 人物、音乐、作品和CP通用的规则
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryRules {
+	/// 最少投票数
+	pub min_count: usize,
+	/// 最多投票数
+	pub max_count: usize,
+	pub first: FirstRule,
+	/// 理由最大长度（字符数）
+	#[serde(default = "default_max_reason_length")]
+	pub max_reason_length: usize
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CPRules {
+	#[serde(flatten)]
+	pub common: CategoryRules,
+	/// 是否允许三人CP
+	#[serde(default = "default_allow_three_person")]
+	pub allow_three_person: bool
+}
+
+/// 每届投票的规则，从 ../keys/rules-{vote_year}.toml 读取
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VotingRules {
+	#[serde(default = "default_character_rules")]
+	pub character: CategoryRules,
+	#[serde(default = "default_music_rules")]
+	pub music: CategoryRules,
+	#[serde(default = "default_work_rules")]
+	pub work: CategoryRules,
+	#[serde(default = "default_cp_rules")]
+	pub cp: CPRules
+}
+
+fn default_max_reason_length() -> usize {
+	4096
+}
+
+fn default_allow_three_person() -> bool {
+	true
+}
+
+fn default_rules(max_count: usize) -> CategoryRules {
+	CategoryRules {
+		min_count: 1,
+		max_count,
+		first: FirstRule::Optional,
+		max_reason_length: default_max_reason_length()
+	}
+}
+
+fn default_character_rules() -> CategoryRules {
+	default_rules(8)
+}
+
+fn default_music_rules() -> CategoryRules {
+	default_rules(12)
+}
+
+fn default_work_rules() -> CategoryRules {
+	default_rules(8)
+}
+
+fn default_cp_rules() -> CPRules {
+	CPRules {
+		common: default_rules(4),
+		allow_three_person: default_allow_three_person()
+	}
+}
+
+impl Default for VotingRules {
+	fn default() -> Self {
+		VotingRules {
+			character: default_character_rules(),
+			music: default_music_rules(),
+			work: default_work_rules(),
+			cp: default_cp_rules()
+		}
+	}
+}
+
+impl VotingRules {
+	/// 规则文件不存在时使用默认规则，文件存在但无法解析时返回错误
+	pub fn load(vote_year: u32) -> Result<Self, String> {
+		let path = format!("../keys/rules-{}.toml", vote_year);
+		let content = match std::fs::read_to_string(&path) {
+			Ok(content) => content,
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+				println!("{} not found, using default voting rules", path);
+				return Ok(VotingRules::default());
+			},
+			Err(e) => return Err(format!("Failed to read voting rules {}: {}", path, e))
+		};
+		toml::from_str(&content).map_err(|e| format!("Voting rules {} is not valid: {}", path, e))
+	}
+}
+
+impl CategoryRules {
+	pub fn check_count(&self, count: usize) -> Result<(), ServiceError> {
+		if count < self.min_count || count > self.max_count {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("数量{}不在范围内[{},{}]", count, self.min_count, self.max_count)));
+		}
+		Ok(())
+	}
+	pub fn check_reason(&self, reason: &Option<String>) -> Result<(), ServiceError> {
+		if reason.as_ref().map_or(0, |f| f.chars().count()) > self.max_reason_length {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "理由过长".into()));
+		}
+		Ok(())
+	}
+	/// 在检查完所有项之后调用
+	pub fn check_first(&self, first_set: bool) -> Result<(), ServiceError> {
+		if self.first == FirstRule::Required && !first_set {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "未选择本命".into()));
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn count_must_be_within_range() {
+		let rules = default_rules(3);
+		assert!(rules.check_count(0).is_err());
+		assert!(rules.check_count(1).is_ok());
+		assert!(rules.check_count(3).is_ok());
+		assert!(rules.check_count(4).is_err());
+	}
+
+	#[test]
+	fn reason_length_counts_chars_not_bytes() {
+		let mut rules = default_rules(3);
+		rules.max_reason_length = 4;
+		assert!(rules.check_reason(&None).is_ok());
+		// 4个汉字共12字节
+		assert!(rules.check_reason(&Some("博丽灵梦".into())).is_ok());
+		assert!(rules.check_reason(&Some("博丽灵梦！".into())).is_err());
+		assert!(rules.check_reason(&Some("abcd".into())).is_ok());
+		assert!(rules.check_reason(&Some("abcde".into())).is_err());
+	}
+
+	#[test]
+	fn first_is_only_enforced_when_required() {
+		let mut rules = default_rules(3);
+		assert!(rules.check_first(false).is_ok());
+		assert!(rules.check_first(true).is_ok());
+		rules.first = FirstRule::Required;
+		assert!(rules.check_first(false).is_err());
+		assert!(rules.check_first(true).is_ok());
+	}
+
+	#[test]
+	fn missing_sections_use_defaults() {
+		let rules: VotingRules = toml::from_str("[character]\nmin_count = 2\nmax_count = 6\nfirst = \"required\"\n").unwrap();
+		assert_eq!(rules.character.max_count, 6);
+		assert_eq!(rules.character.first, FirstRule::Required);
+		assert_eq!(rules.character.max_reason_length, default_max_reason_length());
+		assert_eq!(rules.music.max_count, VotingRules::default().music.max_count);
+		assert!(rules.cp.allow_three_person);
+	}
+}
@@ -1,14 +1,148 @@
-use std::collections::HashSet;
+use std::sync::Arc;
 
-use bson::{doc, oid::ObjectId};
-use futures_util::{TryStreamExt};
-use mongodb::{Collection, Database};
+use bson::{doc, Document};
+use futures_util::TryStreamExt;
+use mongodb::{Collection, Database, IndexModel, options::{FindOneAndUpdateOptions, IndexOptions, ReplaceOptions, ReturnDocument}};
 use pvrustlib::ServiceError;
-use redlock::RedLock;
+use redis::AsyncCommands;
+use serde::{Serialize, de::DeserializeOwned};
 
-use crate::models::{CPSubmitRest, CharacterSubmitRest, MusicSubmitRest, PaperSubmitRest, WorkSubmitRest, VotingStatus, SubmitMetadata, DojinSubmitRest, VotingStatistics};
-use crate::{models, validator};
-use crate::common::{SERVICE_NAME};
+use crate::models::{VoteSchedule, CPSubmitRest, CharacterSubmitRest, MusicSubmitRest, PaperSubmitRest, WorkSubmitRest, VotingStatus, SubmitMetadata, DojinSubmitRest, VotingStatistics, VoteCategory, SubmitHistory, SubmitDiff, SubmitWithMeta, HourlySubmitCount, Draft, Withdrawal};
+use crate::{models, validator, history, throttle, fraud, admin};
+use crate::paper_validator::PaperDefinition;
+use crate::rules::VotingRules;
+use crate::dojin::DojinMetadataResolver;
+use crate::lock::{SubmitLock, with_submit_lock};
+use crate::common::{SERVICE_NAME, SUBMIT_LOCK_TTL, STATISTICS_CACHE_TTL_IN_SECONDS, DRAFT_TTL_IN_SECONDS, DRAFT_MAX_SIZE_IN_BYTES, DRAFT_RATE_LIMIT_WINDOW_IN_SECONDS, DRAFT_RATE_LIMIT_MAX_REQUESTS};
+
+const STATISTICS_CACHE_KEY: &str = "voting-statistics-v1";
+
+fn draft_key(category: VoteCategory, vote_id: &str) -> String {
+	format!("draft-v1-{}-{}", category.name(), vote_id)
+}
+
+/// 以(vote_id, attempt)为键写入历史版本，重复写入同一版本不会产生多份
+pub(crate) async fn upsert_history<T>(history: &Collection<T>, submit: T) -> Result<(), ServiceError>
+	where T: Serialize + SubmitWithMeta + Send + Sync {
+	let meta = submit.meta();
+	match meta.attempt {
+		Some(attempt) => {
+			let query = doc! { "meta.vote_id": meta.vote_id.clone(), "meta.attempt": attempt };
+			let options = ReplaceOptions::builder().upsert(true).build();
+			history.replace_one(query, submit, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		},
+		// 没有提交次数的旧数据无法去重
+		None => {
+			history.insert_one(submit, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		}
+	}
+	Ok(())
+}
+
+/// 检查所有时间（毫秒）均在投票开始到结束加宽限时间之内
+fn check_voting_open_at(schedule: &VoteSchedule, times: &[i64]) -> Result<(), ServiceError> {
+	if schedule.voting_closed {
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "VOTING_CLOSED", "投票已关闭".into()));
+	}
+	let start = schedule.vote_start.timestamp_millis();
+	let deadline = (schedule.vote_end + schedule.grace_period).timestamp_millis();
+	for &t in times {
+		if t < start {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "VOTING_CLOSED", "投票尚未开始".into()));
+		}
+		if t > deadline {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "VOTING_CLOSED", "投票已结束".into()));
+		}
+	}
+	Ok(())
+}
+
+/// 草稿只检查大小和是否为JSON
+fn check_draft(content: &str) -> Result<(), ServiceError> {
+	if content.len() > DRAFT_MAX_SIZE_IN_BYTES {
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "DRAFT_TOO_LARGE", "草稿过大".into()));
+	}
+	if serde_json::from_str::<serde_json::Value>(content).is_err() {
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "草稿格式错误".into()));
+	}
+	Ok(())
+}
+
+/// 读取保存的草稿，不存在或无法解析时返回空草稿
+fn parse_draft(category: VoteCategory, saved: Option<String>) -> Draft {
+	saved.and_then(|f| serde_json::from_str::<Draft>(&f).ok()).unwrap_or(Draft {
+		category,
+		content: None,
+		saved_at: None
+	})
+}
+
+/// 替换当前提交，并将之前的版本移入历史
+///
+/// 先写历史再替换，替换时要求当前提交仍是刚读到的版本，中途失败重试不会丢失或重复历史版本
+async fn replace_current<T>(current: &Collection<T>, history: &Collection<T>, data: T) -> Result<(), ServiceError>
+	where T: Serialize + DeserializeOwned + SubmitWithMeta + Unpin + Send + Sync {
+	let vote_id = data.meta().vote_id.clone();
+	let previous = current.find_one(doc! { "meta.vote_id": vote_id.clone() }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let previous = match previous {
+		Some(previous) => previous,
+		None => {
+			// meta.vote_id上有唯一索引，并发的首次提交只有一个能成功
+			current.insert_one(data, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+			return Ok(());
+		}
+	};
+	let previous_attempt = previous.meta().attempt;
+	upsert_history(history, previous).await?;
+	let result = current.replace_one(doc! { "meta.vote_id": vote_id, "meta.attempt": previous_attempt }, data, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	if result.matched_count == 0 {
+		return Err(ServiceError::new_human_readable(SERVICE_NAME, "SUBMIT_IN_PROGRESS", "上一次提交仍在处理中，请稍后重试".into()));
+	}
+	Ok(())
+}
+
+/// 读取当前提交，并隐藏用户信息。已撤回的提交视为不存在
+async fn find_current<T>(current: &Collection<T>, vote_id: &str) -> Result<Option<T>, ServiceError>
+	where T: DeserializeOwned + SubmitWithMeta + Unpin + Send + Sync {
+	let mut submit = current.find_one(doc! { "meta.vote_id": vote_id, "meta.withdrawal": null }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	if let Some(submit) = submit.as_mut() {
+		let meta = submit.meta_mut();
+		meta.additional_fingreprint = None;
+		meta.user_ip = "".to_string();
+		meta.vote_id = "".to_string();
+	}
+	Ok(submit)
+}
+
+/// $sum的结果可能是int32或int64
+fn get_count(doc: &Document, key: &str) -> u64 {
+	match doc.get(key) {
+		Some(bson::Bson::Int32(v)) => *v as u64,
+		Some(bson::Bson::Int64(v)) => *v as u64,
+		_ => 0
+	}
+}
+
+async fn has_current<T>(current: &Collection<T>, query: &Document) -> Result<bool, ServiceError> {
+	let count = current.count_documents(query.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(count != 0)
+}
+
+/// 未撤回的当前提交数
+async fn count_current<T>(current: &Collection<T>) -> Result<u64, ServiceError> {
+	current.count_documents(doc! { "meta.withdrawal": null }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))
+}
+
+/// 将当前提交标记为已撤回，不存在或已撤回时返回false
+async fn withdraw_current<T>(current: &Collection<T>, vote_id: &str, withdrawal: &Withdrawal) -> Result<bool, ServiceError> {
+	let withdrawal = bson::to_bson(withdrawal).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let result = current.update_one(
+		doc! { "meta.vote_id": vote_id, "meta.withdrawal": null },
+		doc! { "$set": { "meta.withdrawal": withdrawal } },
+		None
+	).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(result.modified_count != 0)
+}
 
 #[derive(Clone)]
 pub struct SubmitServiceV1 {
@@ -18,248 +152,419 @@ pub struct SubmitServiceV1 {
 	pub work_coll: Collection<WorkSubmitRest>,
 	pub paper_coll: Collection<PaperSubmitRest>,
 	pub dojin_coll: Collection<DojinSubmitRest>,
+	pub character_history_coll: Collection<CharacterSubmitRest>,
+	pub music_history_coll: Collection<MusicSubmitRest>,
+	pub cp_history_coll: Collection<CPSubmitRest>,
+	pub work_history_coll: Collection<WorkSubmitRest>,
+	pub paper_history_coll: Collection<PaperSubmitRest>,
+	pub dojin_history_coll: Collection<DojinSubmitRest>,
+	pub attempt_counter_coll: Collection<Document>,
+	pub dojin_works_coll: Collection<models::DojinWork>,
 	pub validator: validator::SubmitValidatorV1,
-	pub lock: RedLock,
+	pub throttle: throttle::SubmitThrottle,
+	pub fraud: fraud::FraudReports,
+	pub admin: admin::AdminConfig,
+	pub schedule: VoteSchedule,
+	pub lock: Arc<dyn SubmitLock>,
 	pub redis_client: redis::Client
 }
 
 impl SubmitServiceV1 {
-	pub async fn new(db: Database, redis: redis::Client, lock: RedLock) -> SubmitServiceV1 {
+	pub async fn new(db: Database, catalog_db: Database, schedule: VoteSchedule, paper: PaperDefinition, rules: VotingRules, throttle_config: throttle::ThrottleConfig, fraud_config: fraud::FraudConfig, admin: admin::AdminConfig, dojin_resolver: Arc<dyn DojinMetadataResolver>, redis: redis::Client, lock: Arc<dyn SubmitLock>) -> SubmitServiceV1 {
+		for category in ["character", "music", "cp", "work", "paper", "dojin"] {
+			let current_index = IndexModel::builder().keys(doc! { "meta.vote_id": 1 }).options(IndexOptions::builder().unique(true).build()).build();
+			db.collection::<Document>(&format!("current_{}", category)).create_index(current_index, None).await.expect("Failed to create index");
+			let history_index = IndexModel::builder().keys(doc! { "meta.vote_id": 1, "meta.created_at": 1 }).build();
+			db.collection::<Document>(&format!("history_{}", category)).create_index(history_index, None).await.expect("Failed to create index");
+			let history_attempt_index = IndexModel::builder().keys(doc! { "meta.vote_id": 1, "meta.attempt": 1 }).options(IndexOptions::builder().unique(true).partial_filter_expression(doc! { "meta.attempt": { "$type": "int" } }).build()).build();
+			db.collection::<Document>(&format!("history_{}", category)).create_index(history_attempt_index, None).await.expect("Failed to create index");
+		}
+		let dojin_url_index = IndexModel::builder().keys(doc! { "url": 1 }).options(IndexOptions::builder().unique(true).build()).build();
+		db.collection::<Document>("dojin_works").create_index(dojin_url_index, None).await.expect("Failed to create index");
+		let throttled_index = IndexModel::builder().keys(doc! { "vote_id": 1 }).build();
+		db.collection::<Document>("throttled_attempts").create_index(throttled_index, None).await.expect("Failed to create index");
+		let report_score_index = IndexModel::builder().keys(doc! { "score": -1 }).build();
+		db.collection::<Document>("fraud_reports").create_index(report_score_index, None).await.expect("Failed to create index");
+		let review_index = IndexModel::builder().keys(doc! { "vote_id": 1, "review.reviewed_at": 1 }).build();
+		db.collection::<Document>("fraud_reviews").create_index(review_index, None).await.expect("Failed to create index");
 		SubmitServiceV1 { 
-			character_coll: db.collection::<CharacterSubmitRest>("raw_character"),
-			music_coll: db.collection::<MusicSubmitRest>("raw_music"),
-			cp_coll: db.collection::<CPSubmitRest>("raw_cp"),
-			work_coll: db.collection::<WorkSubmitRest>("raw_work"),
-			paper_coll: db.collection::<PaperSubmitRest>("raw_paper"),
-			dojin_coll: db.collection::<DojinSubmitRest>("raw_dojin"),
-			validator: validator::SubmitValidatorV1::new().await,
+			character_coll: db.collection::<CharacterSubmitRest>("current_character"),
+			music_coll: db.collection::<MusicSubmitRest>("current_music"),
+			cp_coll: db.collection::<CPSubmitRest>("current_cp"),
+			work_coll: db.collection::<WorkSubmitRest>("current_work"),
+			paper_coll: db.collection::<PaperSubmitRest>("current_paper"),
+			dojin_coll: db.collection::<DojinSubmitRest>("current_dojin"),
+			character_history_coll: db.collection::<CharacterSubmitRest>("history_character"),
+			music_history_coll: db.collection::<MusicSubmitRest>("history_music"),
+			cp_history_coll: db.collection::<CPSubmitRest>("history_cp"),
+			work_history_coll: db.collection::<WorkSubmitRest>("history_work"),
+			paper_history_coll: db.collection::<PaperSubmitRest>("history_paper"),
+			dojin_history_coll: db.collection::<DojinSubmitRest>("history_dojin"),
+			attempt_counter_coll: db.collection::<Document>("attempt_counters"),
+			dojin_works_coll: db.collection::<models::DojinWork>("dojin_works"),
+			throttle: throttle::SubmitThrottle {
+				config: throttle_config,
+				throttled_coll: db.collection::<throttle::ThrottledAttempt>("throttled_attempts")
+			},
+			fraud: fraud::FraudReports {
+				config: fraud_config,
+				reports_coll: db.collection::<fraud::SuspicionReport>("fraud_reports"),
+				reviews_coll: db.collection::<fraud::FraudReviewLog>("fraud_reviews")
+			},
+			admin: admin,
+			validator: validator::SubmitValidatorV1::new(catalog_db, schedule.vote_year, paper, rules, dojin_resolver).await,
+			schedule: schedule,
 			lock: lock,
 			redis_client: redis
 		}
 	}
 
-	pub async fn submit_charcater(&self, verified_data: models::CharacterSubmitRest) -> Result<ObjectId, ServiceError> {
-		match self.character_coll.insert_one(verified_data.clone(), None).await {
-			Ok(insert_result) => return Ok(insert_result.inserted_id.as_object_id().unwrap().clone()),
-			Err(e) => { return Err(ServiceError::new(SERVICE_NAME, format!("{:?}", e))); },
-		}
+	/// 检查服务器时间和提交时间均在投票时间内
+	pub fn check_voting_open(&self, meta: &SubmitMetadata) -> Result<(), ServiceError> {
+		check_voting_open_at(&self.schedule, &[chrono::Utc::now().timestamp_millis(), meta.created_at.timestamp_millis()])
+	}
+
+	/// 每个投票人每个类别的提交次数，从1开始递增
+	async fn next_attempt(&self, category: VoteCategory, vote_id: &str) -> Result<i32, ServiceError> {
+		let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+		let counter = self.attempt_counter_coll.find_one_and_update(
+			doc! { "_id": format!("{}-{}", category.name(), vote_id) },
+			doc! { "$inc": { "seq": 1i32 } },
+			options
+		).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let counter = counter.ok_or_else(|| ServiceError::new(SERVICE_NAME, "attempt counter missing after upsert".into()))?;
+		counter.get_i32("seq").map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))
+	}
+
+	pub async fn submit_charcater(&self, mut verified_data: models::CharacterSubmitRest) -> Result<(), ServiceError> {
+		verified_data.meta.attempt = Some(self.next_attempt(VoteCategory::Character, &verified_data.meta.vote_id).await?);
+		replace_current(&self.character_coll, &self.character_history_coll, verified_data).await
 	}
 
 	pub async fn get_submit_charcater(&self, vote_id: String) -> Result<CharacterSubmitRest, ServiceError> {
-		let stages = vec![
-			doc!{"$match": {"meta.vote_id": vote_id}},
-			doc!{"$sort": {"meta.created_at": -1}}
-		];
-		let mut cursor = self.character_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let submit = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		match submit {
-			Some(submit) => {
-				let mut submit: CharacterSubmitRest = bson::from_document(submit).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-				submit.meta.additional_fingreprint = None;
-				submit.meta.user_ip = "".to_string();
-				submit.meta.vote_id = "".to_string();
-				Ok(submit)
-			},
-			None => {
-				Ok(CharacterSubmitRest {
-					characters: vec![],
-					meta: SubmitMetadata::new()
-				})
-			},
-		}
+		Ok(find_current(&self.character_coll, &vote_id).await?.unwrap_or_else(|| CharacterSubmitRest {
+			characters: vec![],
+			meta: SubmitMetadata::new()
+		}))
 	}
 
-	pub async fn submit_music(&self, verified_data: models::MusicSubmitRest) -> Result<ObjectId, ServiceError> {
-		match self.music_coll.insert_one(verified_data.clone(), None).await {
-			Ok(insert_result) => return Ok(insert_result.inserted_id.as_object_id().unwrap().clone()),
-			Err(e) => { return Err(ServiceError::new(SERVICE_NAME, format!("{:?}", e))); },
-		}
+	pub async fn submit_music(&self, mut verified_data: models::MusicSubmitRest) -> Result<(), ServiceError> {
+		verified_data.meta.attempt = Some(self.next_attempt(VoteCategory::Music, &verified_data.meta.vote_id).await?);
+		replace_current(&self.music_coll, &self.music_history_coll, verified_data).await
 	}
 
 	pub async fn get_submit_music(&self, vote_id: String) -> Result<MusicSubmitRest, ServiceError> {
-		let stages = vec![
-			doc!{"$match": {"meta.vote_id": vote_id}},
-			doc!{"$sort": {"meta.created_at": -1}}
-		];
-		let mut cursor = self.music_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let submit = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		match submit {
-			Some(submit) => {
-				let mut submit: MusicSubmitRest = bson::from_document(submit).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-				submit.meta.additional_fingreprint = None;
-				submit.meta.user_ip = "".to_string();
-				submit.meta.vote_id = "".to_string();
-				Ok(submit)
-			},
-			None => {
-				Ok(MusicSubmitRest {
-					music: vec![],
-					meta: SubmitMetadata::new()
-				})
-			},
-		}
+		Ok(find_current(&self.music_coll, &vote_id).await?.unwrap_or_else(|| MusicSubmitRest {
+			music: vec![],
+			meta: SubmitMetadata::new()
+		}))
 	}
 
-	
-	pub async fn submit_cp(&self, verified_data: models::CPSubmitRest) -> Result<ObjectId, ServiceError> {
-		match self.cp_coll.insert_one(verified_data.clone(), None).await {
-			Ok(insert_result) => return Ok(insert_result.inserted_id.as_object_id().unwrap().clone()),
-			Err(e) => { return Err(ServiceError::new(SERVICE_NAME, format!("{:?}", e))); },
-		}
+	pub async fn submit_work(&self, mut verified_data: models::WorkSubmitRest) -> Result<(), ServiceError> {
+		verified_data.meta.attempt = Some(self.next_attempt(VoteCategory::Work, &verified_data.meta.vote_id).await?);
+		replace_current(&self.work_coll, &self.work_history_coll, verified_data).await
+	}
+
+	pub async fn get_submit_work(&self, vote_id: String) -> Result<WorkSubmitRest, ServiceError> {
+		Ok(find_current(&self.work_coll, &vote_id).await?.unwrap_or_else(|| WorkSubmitRest {
+			works: vec![],
+			meta: SubmitMetadata::new()
+		}))
+	}
+
+	pub async fn submit_cp(&self, mut verified_data: models::CPSubmitRest) -> Result<(), ServiceError> {
+		verified_data.meta.attempt = Some(self.next_attempt(VoteCategory::CP, &verified_data.meta.vote_id).await?);
+		replace_current(&self.cp_coll, &self.cp_history_coll, verified_data).await
 	}
 
 	pub async fn get_submit_cp(&self, vote_id: String) -> Result<CPSubmitRest, ServiceError> {
-		let stages = vec![
-			doc!{"$match": {"meta.vote_id": vote_id}},
-			doc!{"$sort": {"meta.created_at": -1}}
-		];
-		let mut cursor = self.cp_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let submit = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		match submit {
-			Some(submit) => {
-				let mut submit: CPSubmitRest = bson::from_document(submit).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-				submit.meta.additional_fingreprint = None;
-				submit.meta.user_ip = "".to_string();
-				submit.meta.vote_id = "".to_string();
-				Ok(submit)
-			},
-			None => {
-				Ok(CPSubmitRest {
-					cps: vec![],
-					meta: SubmitMetadata::new()
-				})
-			},
-		}
+		Ok(find_current(&self.cp_coll, &vote_id).await?.unwrap_or_else(|| CPSubmitRest {
+			cps: vec![],
+			meta: SubmitMetadata::new()
+		}))
 	}
 
-	pub async fn submit_paper(&self, verified_data: models::PaperSubmitRest) -> Result<ObjectId, ServiceError> {
-		match self.paper_coll.insert_one(verified_data.clone(), None).await {
-			Ok(insert_result) => return Ok(insert_result.inserted_id.as_object_id().unwrap().clone()),
-			Err(e) => { return Err(ServiceError::new(SERVICE_NAME, format!("{:?}", e))); },
-		}
+	pub async fn submit_paper(&self, mut verified_data: models::PaperSubmitRest) -> Result<(), ServiceError> {
+		verified_data.meta.attempt = Some(self.next_attempt(VoteCategory::Paper, &verified_data.meta.vote_id).await?);
+		replace_current(&self.paper_coll, &self.paper_history_coll, verified_data).await
 	}
 
 	pub async fn get_submit_paper(&self, vote_id: String) -> Result<PaperSubmitRest, ServiceError> {
-		let stages = vec![
-			doc!{"$match": {"meta.vote_id": vote_id}},
-			doc!{"$sort": {"meta.created_at": -1}}
-		];
-		let mut cursor = self.paper_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let submit = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		match submit {
-			Some(submit) => {
-				let mut submit: PaperSubmitRest = bson::from_document(submit).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-				submit.meta.additional_fingreprint = None;
-				submit.meta.user_ip = "".to_string();
-				submit.meta.vote_id = "".to_string();
-				Ok(submit)
-			},
-			None => {
-				Ok(PaperSubmitRest {
-					papers_json: "{}".into(),
-					meta: SubmitMetadata::new()
-				})
-			},
-		}
+		Ok(find_current(&self.paper_coll, &vote_id).await?.unwrap_or_else(|| PaperSubmitRest {
+			papers_json: "{}".into(),
+			meta: SubmitMetadata::new()
+		}))
+	}
+
+	/// 取得作品id，URL第一次被提名时登记
+	async fn dojin_work_id(&self, item: &models::DojinSubmit, created_at: bson::DateTime) -> Result<String, ServiceError> {
+		let options = FindOneAndUpdateOptions::builder().upsert(true).return_document(ReturnDocument::After).build();
+		let update = doc! {
+			"$setOnInsert": {
+				"dojin_type": &item.dojin_type,
+				"title": &item.title,
+				"author": &item.author,
+				"created_at": created_at
+			}
+		};
+		let work = self.dojin_works_coll.find_one_and_update(doc! { "url": &item.url }, update, options).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let work = work.ok_or_else(|| ServiceError::new(SERVICE_NAME, format!("dojin work {} missing after upsert", item.url)))?;
+		Ok(work.work_id.to_hex())
 	}
 
-	pub async fn submit_dojin(&self, verified_data: models::DojinSubmitRest) -> Result<ObjectId, ServiceError> {
-		match self.dojin_coll.insert_one(verified_data.clone(), None).await {
-			Ok(insert_result) => return Ok(insert_result.inserted_id.as_object_id().unwrap().clone()),
-			Err(e) => { return Err(ServiceError::new(SERVICE_NAME, format!("{:?}", e))); },
+	pub async fn submit_dojin(&self, mut verified_data: models::DojinSubmitRest) -> Result<(), ServiceError> {
+		for i in 0..verified_data.dojins.len() {
+			let work_id = self.dojin_work_id(&verified_data.dojins[i], verified_data.meta.created_at).await?;
+			verified_data.dojins[i].work_id = Some(work_id);
 		}
+		verified_data.meta.attempt = Some(self.next_attempt(VoteCategory::Dojin, &verified_data.meta.vote_id).await?);
+		replace_current(&self.dojin_coll, &self.dojin_history_coll, verified_data).await
 	}
 
 	pub async fn get_submit_dojin(&self, vote_id: String) -> Result<DojinSubmitRest, ServiceError> {
-		let stages = vec![
-			doc!{"$match": {"meta.vote_id": vote_id}},
-			doc!{"$sort": {"meta.created_at": -1}}
-		];
-		let mut cursor = self.dojin_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let submit = cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		match submit {
-			Some(submit) => {
-				let mut submit: DojinSubmitRest = bson::from_document(submit).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-				submit.meta.additional_fingreprint = None;
-				submit.meta.user_ip = "".to_string();
-				submit.meta.vote_id = "".to_string();
-				Ok(submit)
-			},
-			None => {
-				Ok(DojinSubmitRest {
-					dojins: vec![],
-					meta: SubmitMetadata::new()
-				})
-			},
+		Ok(find_current(&self.dojin_coll, &vote_id).await?.unwrap_or_else(|| DojinSubmitRest {
+			dojins: vec![],
+			meta: SubmitMetadata::new()
+		}))
+	}
+
+	/// 撤回一个或所有类别的当前提交，返回实际被撤回的类别
+	pub async fn withdraw(&self, vote_id: String, category: Option<VoteCategory>, withdrawn_by: String, reason: Option<String>, ignore_schedule: bool) -> Result<Vec<VoteCategory>, ServiceError> {
+		if !ignore_schedule {
+			check_voting_open_at(&self.schedule, &[chrono::Utc::now().timestamp_millis()])?;
+		}
+		let categories = match category {
+			Some(category) => vec![category],
+			None => vec![VoteCategory::Character, VoteCategory::Music, VoteCategory::Work, VoteCategory::CP, VoteCategory::Paper, VoteCategory::Dojin]
+		};
+		let withdrawal = Withdrawal {
+			withdrawn_by,
+			reason,
+			withdrawn_at: bson::DateTime::now()
+		};
+		let mut withdrawn = vec![];
+		for category in categories {
+			// 与提交使用同一把锁，避免撤回与正在进行的提交交错
+			let lockid = format!("lock-submit_{}_v1-{}", category.name(), vote_id);
+			let modified = with_submit_lock(self.lock.as_ref(), &lockid, SUBMIT_LOCK_TTL, async {
+				match category {
+					VoteCategory::Character => withdraw_current(&self.character_coll, &vote_id, &withdrawal).await,
+					VoteCategory::Music => withdraw_current(&self.music_coll, &vote_id, &withdrawal).await,
+					VoteCategory::Work => withdraw_current(&self.work_coll, &vote_id, &withdrawal).await,
+					VoteCategory::CP => withdraw_current(&self.cp_coll, &vote_id, &withdrawal).await,
+					VoteCategory::Paper => withdraw_current(&self.paper_coll, &vote_id, &withdrawal).await,
+					VoteCategory::Dojin => withdraw_current(&self.dojin_coll, &vote_id, &withdrawal).await,
+				}
+			}).await?;
+			if modified {
+				withdrawn.push(category);
+			}
 		}
+		Ok(withdrawn)
 	}
 
 	pub async fn get_voting_status(&self, vote_id: String) -> Result<VotingStatus, ServiceError> {
+		let query = doc!{"meta.vote_id": vote_id, "meta.withdrawal": null};
+		Ok(VotingStatus {
+			characters: has_current(&self.character_coll, &query).await?,
+			musics: has_current(&self.music_coll, &query).await?,
+			cps: has_current(&self.cp_coll, &query).await?,
+			papers: has_current(&self.paper_coll, &query).await?,
+			dojin: has_current(&self.dojin_coll, &query).await?,
+			works: has_current(&self.work_coll, &query).await?
+		})
+	}
+	pub async fn get_voting_statistics(&self) -> Result<VotingStatistics, ServiceError> {
+		let mut conn = self.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let cached: Option<String> = conn.get(STATISTICS_CACHE_KEY).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		if let Some(cached) = cached.and_then(|f| serde_json::from_str::<VotingStatistics>(&f).ok()) {
+			return Ok(cached);
+		}
+		let stats = self.compute_voting_statistics().await?;
+		let serialized = serde_json::to_string(&stats).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		conn.set_ex(STATISTICS_CACHE_KEY, serialized, STATISTICS_CACHE_TTL_IN_SECONDS).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		Ok(stats)
+	}
+
+	async fn compute_voting_statistics(&self) -> Result<VotingStatistics, ServiceError> {
+		// 每个投票人提交过且未撤回的类别
+		let not_withdrawn = || doc!{"$match": {"meta.withdrawal": null}};
+		let tag = |category: VoteCategory| doc!{"$project": {"_id": 0, "vote_id": "$meta.vote_id", "category": category.name()}};
+		let union_with = |coll: &str, category: VoteCategory| doc!{"$unionWith": {"coll": coll, "pipeline": [not_withdrawn(), tag(category)]}};
 		let stages = vec![
-			doc!{"$match": {"meta.vote_id": vote_id}},
-			doc!{"$sort": {"meta.created_at": -1}}
+			not_withdrawn(),
+			tag(VoteCategory::Character),
+			union_with(self.music_coll.name(), VoteCategory::Music),
+			union_with(self.work_coll.name(), VoteCategory::Work),
+			union_with(self.cp_coll.name(), VoteCategory::CP),
+			union_with(self.paper_coll.name(), VoteCategory::Paper),
+			union_with(self.dojin_coll.name(), VoteCategory::Dojin),
+			doc!{"$group": {"_id": "$vote_id", "categories": {"$addToSet": "$category"}}},
+			doc!{"$group": {
+				"_id": null,
+				"num_user": {"$sum": 1},
+				"num_finished_voting": {"$sum": {"$cond": [{"$setIsSubset": [["character", "music", "cp", "paper"], "$categories"]}, 1, 0]}}
+			}}
 		];
-		
-		let ch = {
-			let mut cursor = self.character_coll.aggregate(stages.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?.is_some()
-		};
-		let music = {
-			let mut cursor = self.music_coll.aggregate(stages.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?.is_some()
-		};
-		let cp = {
-			let mut cursor = self.cp_coll.aggregate(stages.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?.is_some()
+		let mut cursor = self.character_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let (num_user, num_finished_voting) = match cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))? {
+			Some(totals) => (get_count(&totals, "num_user"), get_count(&totals, "num_finished_voting")),
+			None => (0, 0)
 		};
-		let paper = {
-			let mut cursor = self.paper_coll.aggregate(stages.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?.is_some()
+
+		// 所有提交（含历史版本）按小时计数
+		let created_at = || doc!{"$project": {"_id": 0, "created_at": "$meta.created_at"}};
+		let union_with = |coll: &str| doc!{"$unionWith": {"coll": coll, "pipeline": [created_at()]}};
+		let stages = vec![
+			created_at(),
+			union_with(self.music_coll.name()),
+			union_with(self.work_coll.name()),
+			union_with(self.cp_coll.name()),
+			union_with(self.paper_coll.name()),
+			union_with(self.dojin_coll.name()),
+			union_with(self.character_history_coll.name()),
+			union_with(self.music_history_coll.name()),
+			union_with(self.work_history_coll.name()),
+			union_with(self.cp_history_coll.name()),
+			union_with(self.paper_history_coll.name()),
+			union_with(self.dojin_history_coll.name()),
+			doc!{"$group": {"_id": {"$dateToString": {"format": "%Y-%m-%dT%H:00:00Z", "date": "$created_at"}}, "count": {"$sum": 1}}},
+			doc!{"$sort": {"_id": 1}}
+		];
+		let cursor = self.character_coll.aggregate(stages, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let hours: Vec<Document> = cursor.try_collect().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let hourly_submits = hours.iter().map(|f| HourlySubmitCount {
+			hour: f.get_str("_id").unwrap_or_default().to_string(),
+			count: get_count(f, "count")
+		}).collect();
+
+		// 每个投票人在current_*中只有一条
+		Ok(VotingStatistics {
+			num_user,
+			num_finished_paper: count_current(&self.paper_coll).await?,
+			num_finished_voting,
+			num_character: count_current(&self.character_coll).await?,
+			num_cp: count_current(&self.cp_coll).await?,
+			num_music: count_current(&self.music_coll).await?,
+			num_dojin: count_current(&self.dojin_coll).await?,
+			num_work: count_current(&self.work_coll).await?,
+			hourly_submits
+		})
+	}
+
+	/// 草稿保存在Redis中，不经过校验，也不会进入current_*和history_*
+	pub async fn save_draft(&self, vote_id: String, category: VoteCategory, content: String) -> Result<(), ServiceError> {
+		check_draft(&content)?;
+		let mut conn = self.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		pvrustlib::rate_limit::rate_limit(SERVICE_NAME, &format!("draft-{}", vote_id), DRAFT_RATE_LIMIT_MAX_REQUESTS, DRAFT_RATE_LIMIT_WINDOW_IN_SECONDS, &mut conn).await?;
+		let draft = Draft {
+			category,
+			content: Some(content),
+			saved_at: Some(chrono::Utc::now())
 		};
-		let dojin = {
-			let mut cursor = self.dojin_coll.aggregate(stages.clone(), None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			cursor.try_next().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?.is_some()
+		let serialized = serde_json::to_string(&draft).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		conn.set_ex(draft_key(category, &vote_id), serialized, DRAFT_TTL_IN_SECONDS).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		Ok(())
+	}
+
+	pub async fn get_draft(&self, vote_id: String, category: VoteCategory) -> Result<Draft, ServiceError> {
+		let mut conn = self.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		let saved: Option<String> = conn.get(draft_key(category, &vote_id)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+		Ok(parse_draft(category, saved))
+	}
+
+	pub async fn get_history(&self, vote_id: String, category: VoteCategory) -> Result<SubmitHistory, ServiceError> {
+		let attempts = match category {
+			VoteCategory::Character => history::load_attempts(&self.character_coll, &self.character_history_coll, &vote_id).await?,
+			VoteCategory::Music => history::load_attempts(&self.music_coll, &self.music_history_coll, &vote_id).await?,
+			VoteCategory::Work => history::load_attempts(&self.work_coll, &self.work_history_coll, &vote_id).await?,
+			VoteCategory::CP => history::load_attempts(&self.cp_coll, &self.cp_history_coll, &vote_id).await?,
+			VoteCategory::Paper => history::load_attempts(&self.paper_coll, &self.paper_history_coll, &vote_id).await?,
+			VoteCategory::Dojin => history::load_attempts(&self.dojin_coll, &self.dojin_history_coll, &vote_id).await?,
 		};
-		Ok(VotingStatus {
-			characters: ch,
-			musics: music,
-			cps: cp,
-			papers: paper,
-			dojin: dojin
+		Ok(SubmitHistory {
+			category,
+			attempts
 		})
 	}
-	pub async fn get_voting_statistics(&self) -> Result<VotingStatistics, ServiceError> {
-		let all_ch_voter = self.character_coll.distinct("meta.vote_id", doc!{}, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let all_cp_voter = self.cp_coll.distinct("meta.vote_id", doc!{}, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let all_music_voter = self.music_coll.distinct("meta.vote_id", doc!{}, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let mut all_voters = HashSet::new();
-		for voter in all_ch_voter.iter() {
-			let item: CharacterSubmitRest = bson::from_bson(voter.clone()).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			all_voters.insert(item.meta.vote_id);
-		}
-		for voter in all_cp_voter.iter() {
-			let item: CPSubmitRest = bson::from_bson(voter.clone()).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			all_voters.insert(item.meta.vote_id);
-		}
-		for voter in all_music_voter.iter() {
-			let item: MusicSubmitRest = bson::from_bson(voter.clone()).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			all_voters.insert(item.meta.vote_id);
+
+	pub async fn get_history_diff(&self, vote_id: String, category: VoteCategory, from_attempt: i32, to_attempt: i32) -> Result<SubmitDiff, ServiceError> {
+		let history = self.get_history(vote_id, category).await?;
+		let find = |attempt: i32| {
+			history.attempts.iter().find(|f| f.meta.attempt == Some(attempt)).ok_or_else(|| ServiceError::new_not_found(SERVICE_NAME, Some(format!("attempt {}", attempt))))
+		};
+		Ok(history::diff_attempts(category, find(from_attempt)?, find(to_attempt)?))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn error_kind(e: &ServiceError) -> &str {
+		match e {
+			ServiceError::Error { resp } => &resp.error_kind
 		}
-		let mut all_voters_inc_paper = all_voters.clone();
-		let all_paper_voter = self.paper_coll.distinct("meta.vote_id", doc!{}, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		let all_dojin_voter = self.dojin_coll.distinct("meta.vote_id", doc!{}, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-		for voter in all_paper_voter.iter() {
-			let item: PaperSubmitRest = bson::from_bson(voter.clone()).map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
-			all_voters_inc_paper.insert(item.meta.vote_id);
+	}
+
+	#[test]
+	fn draft_size_is_capped() {
+		let at_limit = format!("\"{}\"", "a".repeat(DRAFT_MAX_SIZE_IN_BYTES - 2));
+		assert!(check_draft(&at_limit).is_ok());
+		let over_limit = format!("\"{}\"", "a".repeat(DRAFT_MAX_SIZE_IN_BYTES - 1));
+		assert_eq!(error_kind(&check_draft(&over_limit).unwrap_err()), "DRAFT_TOO_LARGE");
+		// 按字节计算
+		let multibyte = format!("\"{}\"", "灵".repeat(DRAFT_MAX_SIZE_IN_BYTES / 3));
+		assert_eq!(error_kind(&check_draft(&multibyte).unwrap_err()), "DRAFT_TOO_LARGE");
+	}
+
+	#[test]
+	fn draft_must_be_json() {
+		assert!(check_draft(r#"{"characters":[{"id":"reimu"}]}"#).is_ok());
+		assert!(check_draft("[]").is_ok());
+		assert_eq!(error_kind(&check_draft("{\"characters\":").unwrap_err()), "INVALID_CONTENT");
+		assert_eq!(error_kind(&check_draft("").unwrap_err()), "INVALID_CONTENT");
+	}
+
+	#[test]
+	fn missing_or_corrupt_draft_is_empty() {
+		let draft = parse_draft(VoteCategory::Music, None);
+		assert_eq!(draft.category, VoteCategory::Music);
+		assert!(draft.content.is_none() && draft.saved_at.is_none());
+		assert!(parse_draft(VoteCategory::Music, Some("not json".into())).content.is_none());
+		let saved = serde_json::to_string(&Draft { category: VoteCategory::Music, content: Some("{}".into()), saved_at: Some(chrono::Utc::now()) }).unwrap();
+		assert_eq!(parse_draft(VoteCategory::Music, Some(saved)).content.as_deref(), Some("{}"));
+	}
+
+	fn schedule(voting_closed: bool) -> VoteSchedule {
+		VoteSchedule {
+			vote_year: 2022,
+			vote_start: chrono::DateTime::parse_from_rfc3339("2022-07-01T00:00:00+08:00").unwrap().with_timezone(&chrono::Utc),
+			vote_end: chrono::DateTime::parse_from_rfc3339("2022-07-15T00:00:00+08:00").unwrap().with_timezone(&chrono::Utc),
+			grace_period: chrono::Duration::minutes(5),
+			voting_closed
 		}
-		Ok(VotingStatistics {
-			num_user: all_voters_inc_paper.len() as _,
-			num_finished_paper: 0,
-			num_finished_voting: all_voters.len() as _,
-			num_character: all_ch_voter.len() as _,
-			num_cp: all_cp_voter.len() as _,
-			num_music: all_music_voter.len() as _,
-			num_dojin: all_dojin_voter.len() as _,
-		})
+	}
+
+	#[test]
+	fn voting_is_open_between_start_and_grace_deadline() {
+		let schedule = schedule(false);
+		let start = schedule.vote_start.timestamp_millis();
+		let end = schedule.vote_end.timestamp_millis();
+		let deadline = (schedule.vote_end + schedule.grace_period).timestamp_millis();
+		assert!(check_voting_open_at(&schedule, &[start, end]).is_ok());
+		// 宽限时间内仍可提交
+		assert!(check_voting_open_at(&schedule, &[end + 60_000, deadline]).is_ok());
+		assert_eq!(error_kind(&check_voting_open_at(&schedule, &[start - 1]).unwrap_err()), "VOTING_CLOSED");
+		assert_eq!(error_kind(&check_voting_open_at(&schedule, &[deadline + 1]).unwrap_err()), "VOTING_CLOSED");
+		// 任何一个时间不在投票时间内都拒绝
+		assert_eq!(error_kind(&check_voting_open_at(&schedule, &[start, deadline + 1]).unwrap_err()), "VOTING_CLOSED");
+	}
+
+	#[test]
+	fn closed_voting_rejects_everything() {
+		let schedule = schedule(true);
+		assert_eq!(error_kind(&check_voting_open_at(&schedule, &[schedule.vote_start.timestamp_millis()]).unwrap_err()), "VOTING_CLOSED");
+		assert_eq!(error_kind(&check_voting_open_at(&schedule, &[]).unwrap_err()), "VOTING_CLOSED");
 	}
 }
@@ -0,0 +1,178 @@
+
+use std::net::IpAddr;
+
+use mongodb::Collection;
+use pvrustlib::{ServiceError, rate_limit::rate_limit};
+use serde::{Serialize, Deserialize};
+
+use crate::{common::SERVICE_NAME, models::{SubmitMetadata, VoteCategory}};
+
+/// 单层限流规则：令牌桶容量为`max_requests`，每`window_seconds`秒补满
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ThrottleRule {
+	pub window_seconds: i64,
+	pub max_requests: i64
+}
+
+/// 提交限流配置，对应config.toml中的[throttle]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ThrottleConfig {
+	#[serde(default = "default_vote_id_rule")]
+	pub vote_id: ThrottleRule,
+	#[serde(default = "default_ip_rule")]
+	pub ip: ThrottleRule,
+	/// IPv4按/24，IPv6按/64
+	#[serde(default = "default_subnet_rule")]
+	pub subnet: ThrottleRule,
+	#[serde(default = "default_fingerprint_rule")]
+	pub fingerprint: ThrottleRule
+}
+
+fn default_vote_id_rule() -> ThrottleRule {
+	ThrottleRule { window_seconds: 60, max_requests: 30 }
+}
+
+fn default_ip_rule() -> ThrottleRule {
+	ThrottleRule { window_seconds: 3600, max_requests: 120 }
+}
+
+fn default_subnet_rule() -> ThrottleRule {
+	ThrottleRule { window_seconds: 3600, max_requests: 600 }
+}
+
+fn default_fingerprint_rule() -> ThrottleRule {
+	ThrottleRule { window_seconds: 3600, max_requests: 120 }
+}
+
+impl Default for ThrottleConfig {
+	fn default() -> Self {
+		Self {
+			vote_id: default_vote_id_rule(),
+			ip: default_ip_rule(),
+			subnet: default_subnet_rule(),
+			fingerprint: default_fingerprint_rule()
+		}
+	}
+}
+
+/// 因限流被拒绝的提交，供后续作弊分析使用
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottledAttempt {
+	pub vote_id: String,
+	pub category: VoteCategory,
+	/// 触发限流的层，如ip、subnet
+	pub layer: String,
+	/// 限流使用的键
+	pub key: String,
+	pub user_ip: String,
+	pub additional_fingreprint: Option<String>,
+	pub created_at: bson::DateTime
+}
+
+/// 将IP地址转换成所在的网段，无法解析时返回None
+pub fn subnet_of(ip: &str) -> Option<String> {
+	match ip.parse::<IpAddr>().ok()? {
+		IpAddr::V4(ip) => {
+			let o = ip.octets();
+			Some(format!("{}.{}.{}.0/24", o[0], o[1], o[2]))
+		},
+		IpAddr::V6(ip) => {
+			let s = ip.segments();
+			Some(format!("{:x}:{:x}:{:x}:{:x}::/64", s[0], s[1], s[2], s[3]))
+		}
+	}
+}
+
+impl ThrottleConfig {
+	/// 按检查顺序返回各层的名字、限流键和规则，IP无法解析时跳过网段，没有指纹时跳过指纹
+	fn layers(&self, meta: &SubmitMetadata) -> Vec<(&'static str, String, &ThrottleRule)> {
+		let mut layers = vec![("vote_id", format!("vote_id-{}", meta.vote_id), &self.vote_id)];
+		layers.push(("ip", format!("ip-{}", meta.user_ip), &self.ip));
+		if let Some(subnet) = subnet_of(&meta.user_ip) {
+			layers.push(("subnet", format!("subnet-{}", subnet), &self.subnet));
+		}
+		if let Some(fingerprint) = meta.additional_fingreprint.as_ref().filter(|f| !f.is_empty()) {
+			layers.push(("fingerprint", format!("fingerprint-{}", fingerprint), &self.fingerprint));
+		}
+		layers
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct SubmitThrottle {
+	pub config: ThrottleConfig,
+	pub throttled_coll: Collection<ThrottledAttempt>
+}
+
+impl SubmitThrottle {
+	/// 依次检查vote_id、IP、网段和指纹，任何一层超限都会拒绝提交并记录
+	pub async fn check(&self, category: VoteCategory, meta: &SubmitMetadata, conn: &mut redis::aio::Connection) -> Result<(), ServiceError> {
+		for (layer, key, rule) in self.config.layers(meta) {
+			if let Err(e) = rate_limit(SERVICE_NAME, &key, rule.max_requests, rule.window_seconds, conn).await {
+				if !matches!(&e, ServiceError::Error { resp } if resp.error_kind == "REQUEST_TOO_FREQUENT") {
+					return Err(e);
+				}
+				let attempt = ThrottledAttempt {
+					vote_id: meta.vote_id.clone(),
+					category,
+					layer: layer.into(),
+					key,
+					user_ip: meta.user_ip.clone(),
+					additional_fingreprint: meta.additional_fingreprint.clone(),
+					created_at: bson::DateTime::now()
+				};
+				self.throttled_coll.insert_one(attempt, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+				return Err(e);
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn meta(user_ip: &str, fingerprint: Option<&str>) -> SubmitMetadata {
+		let mut meta = SubmitMetadata::new();
+		meta.vote_id = "thvote-2022-abc".into();
+		meta.user_ip = user_ip.into();
+		meta.additional_fingreprint = fingerprint.map(|f| f.to_string());
+		meta
+	}
+
+	fn keys(config: &ThrottleConfig, meta: &SubmitMetadata) -> Vec<(&'static str, String)> {
+		config.layers(meta).into_iter().map(|(layer, key, _)| (layer, key)).collect()
+	}
+
+	#[test]
+	fn subnets_are_v4_24_and_v6_64() {
+		assert_eq!(subnet_of("203.0.113.57").as_deref(), Some("203.0.113.0/24"));
+		assert_eq!(subnet_of("2001:db8:0:1a2b:3:4:5:6").as_deref(), Some("2001:db8:0:1a2b::/64"));
+		assert_eq!(subnet_of("::1").as_deref(), Some("0:0:0:0::/64"));
+		assert_eq!(subnet_of("not an ip"), None);
+		assert_eq!(subnet_of(""), None);
+	}
+
+	#[test]
+	fn layers_are_checked_in_order() {
+		let config = ThrottleConfig::default();
+		assert_eq!(keys(&config, &meta("203.0.113.57", Some("fp1"))), vec![
+			("vote_id", "vote_id-thvote-2022-abc".to_string()),
+			("ip", "ip-203.0.113.57".to_string()),
+			("subnet", "subnet-203.0.113.0/24".to_string()),
+			("fingerprint", "fingerprint-fp1".to_string())
+		]);
+		let layers = config.layers(&meta("203.0.113.57", None));
+		assert_eq!(layers.iter().map(|f| f.2).collect::<Vec<_>>(), vec![&config.vote_id, &config.ip, &config.subnet]);
+	}
+
+	#[test]
+	fn unparsable_ip_and_empty_fingerprint_are_skipped() {
+		let config = ThrottleConfig::default();
+		assert_eq!(keys(&config, &meta("<unknown>", Some(""))), vec![
+			("vote_id", "vote_id-thvote-2022-abc".to_string()),
+			("ip", "ip-<unknown>".to_string())
+		]);
+	}
+}
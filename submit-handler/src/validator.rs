@@ -1,27 +1,112 @@
 
-use std::collections::HashSet;
+use std::{collections::HashSet, sync::{Arc, RwLock}, time::Duration};
 
 use actix_web::Error;
 use bson::doc;
 use futures_util::TryStreamExt;
-use mongodb::Collection;
+use mongodb::{Collection, Database};
 use pvrustlib::ServiceError;
 
-use crate::{models::{self, *}, common::SERVICE_NAME};
+use crate::{models::{self, *}, common::{SERVICE_NAME, CATALOG_REFRESH_INTERVAL_IN_SECONDS}, paper_validator::PaperDefinition, rules::{VotingRules, CPRules}, dojin::{self, DojinSite, DojinMetadataResolver}};
 
 #[derive(Debug, Clone)]
 pub struct SubmitValidatorV1 {
-	pub all_characters: HashSet<String>,
-	pub all_music: HashSet<String>
+	pub all_characters: Arc<RwLock<HashSet<String>>>,
+	pub all_music: Arc<RwLock<HashSet<String>>>,
+	pub all_works: Arc<RwLock<HashSet<String>>>,
+	pub paper: Arc<PaperDefinition>,
+	pub rules: Arc<VotingRules>,
+	pub dojin_resolver: Arc<dyn DojinMetadataResolver>
 }
 
-impl SubmitValidatorV1 {
-	pub async fn new() -> Self {
-		Self {
-			all_characters: HashSet::new(),
-			all_music: HashSet::new()
+async fn load_catalog(coll: &Collection<VotableItem>, vote_year: u32) -> Result<HashSet<String>, ServiceError> {
+	let cursor = coll.find(doc! { "vote_year": vote_year as i32 }, None).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	let items: Vec<VotableItem> = cursor.try_collect().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+	Ok(items.into_iter().map(|f| f.name).collect())
+}
+
+/// 检查所有id均在可投票列表中，否则报告所有未知的id
+fn check_known<'a>(catalog: &RwLock<HashSet<String>>, ids: impl Iterator<Item = &'a String>, kind: &str) -> Result<(), ServiceError> {
+	let catalog = catalog.read().unwrap();
+	let mut unknown: Vec<&str> = ids.filter(|id| !catalog.contains(*id)).map(|id| id.as_str()).collect();
+	if unknown.is_empty() {
+		return Ok(());
+	}
+	unknown.sort();
+	unknown.dedup();
+	Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("未知的{}：{}", kind, unknown.join(", "))))
+}
+
+/// 检查CP的数量、理由和本命，成员不能重复，主动方必须是成员之一
+///
+/// 成员相同的CP视为同一个CP，与顺序和主动方无关
+fn check_cps(cp_rules: &CPRules, cps: &[CPSubmit]) -> Result<(), ServiceError> {
+	let mut first_set = false;
+	let rules = &cp_rules.common;
+	rules.check_count(cps.len())?;
+	for c in cps.iter() {
+		rules.check_reason(&c.reason)?;
+		if c.first.unwrap_or_default() {
+			if first_set {
+				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "多个本命".into()));
+			}
+			first_set = true;
+		}
+		if c.id_c.is_some() && !cp_rules.allow_three_person {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "不允许三人CP".into()));
+		}
+		let canonical = c.canonical();
+		if canonical.members.windows(2).any(|f| f[0] == f[1]) {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("{}成员重复", canonical)));
+		}
+		if let Some(active) = canonical.active {
+			if !canonical.members.contains(&active) {
+				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("主动方{}不存在", active)));
+			}
+		}
+	}
+	for (i, a) in cps.iter().enumerate() {
+		if let Some(j) = cps[..i].iter().position(|b| a.canonical().members == b.canonical().members) {
+			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("第{}个和第{}个CP重复：{}", j + 1, i + 1, a.canonical().members.join("×"))));
 		}
 	}
+	rules.check_first(first_set)?;
+	Ok(())
+}
+
+impl SubmitValidatorV1 {
+	/// 读取当届可投票的角色、音乐和作品，并在后台定期刷新
+	pub async fn new(catalog_db: Database, vote_year: u32, paper: PaperDefinition, rules: VotingRules, dojin_resolver: Arc<dyn DojinMetadataResolver>) -> Self {
+		let ret = Self {
+			all_characters: Arc::new(RwLock::new(HashSet::new())),
+			all_music: Arc::new(RwLock::new(HashSet::new())),
+			all_works: Arc::new(RwLock::new(HashSet::new())),
+			paper: Arc::new(paper),
+			rules: Arc::new(rules),
+			dojin_resolver
+		};
+		ret.refresh_catalog(&catalog_db, vote_year).await.expect("Failed to load votable catalog");
+		let refresher = ret.clone();
+		tokio::spawn(async move {
+			loop {
+				tokio::time::sleep(Duration::from_secs(CATALOG_REFRESH_INTERVAL_IN_SECONDS)).await;
+				if let Err(e) = refresher.refresh_catalog(&catalog_db, vote_year).await {
+					// keep serving with the previous catalog
+					println!("Failed to refresh votable catalog: {:?}", e);
+				}
+			}
+		});
+		ret
+	}
+	async fn refresh_catalog(&self, catalog_db: &Database, vote_year: u32) -> Result<(), ServiceError> {
+		let all_characters = load_catalog(&catalog_db.collection("chars"), vote_year).await?;
+		let all_music = load_catalog(&catalog_db.collection("musics"), vote_year).await?;
+		let all_works = load_catalog(&catalog_db.collection("works"), vote_year).await?;
+		*self.all_characters.write().unwrap() = all_characters;
+		*self.all_music.write().unwrap() = all_music;
+		*self.all_works.write().unwrap() = all_works;
+		Ok(())
+	}
 	pub async fn validate_character(&self, mut data: models::CharacterSubmitRest, coll: &Collection<CharacterSubmitRest>) -> Result<models::CharacterSubmitRest, ServiceError> {
 		// step 2: retrieve and check if user attempts are allowed
 
@@ -33,16 +118,13 @@ impl SubmitValidatorV1 {
 			Ok(a) => a,
 			Err(e) => { return Err(ServiceError::new(SERVICE_NAME, format!("{:?}", e))); }
 		};
-		// step 3: check ranks are unique from 1 to 6 and only one 本命
+		// step 3: check counts and reasons against the rules, ids are unique and at most one 本命
 		let mut chset: HashSet<String> = HashSet::new();
 		let mut first_set = false;
-		if data.characters.len() < 1 || data.characters.len() > 8 {
-			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("数量{}不在范围内[1,8]", data.characters.len())));
-		}
+		let rules = &self.rules.character;
+		rules.check_count(data.characters.len())?;
 		for c in data.characters.iter() {
-			if c.reason.as_ref().map_or(0, |f| f.len()) > 4096 {
-				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "理由过长".into()));
-			}
+			rules.check_reason(&c.reason)?;
 			if c.first.unwrap_or_default() {
 				if first_set {
 					return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "多个本命".into()));
@@ -54,7 +136,9 @@ impl SubmitValidatorV1 {
 			}
 			chset.insert(c.id.clone());
 		}
+		rules.check_first(first_set)?;
 		// step 4: check all names are correct
+		check_known(&self.all_characters, data.characters.iter().map(|c| &c.id), "角色")?;
 		// step 5: return
 		Ok(data)
 	}
@@ -62,16 +146,13 @@ impl SubmitValidatorV1 {
 		let query = doc! {
 			"meta.vote_id": data.meta.vote_id.clone()
 		};
-		// step 3: check ranks are unique from 1 to 6 and only one 本命
+		// step 3: check counts and reasons against the rules, ids are unique and at most one 本命
 		let mut chset: HashSet<String> = HashSet::new();
 		let mut first_set = false;
-		if data.music.len() < 1 || data.music.len() > 12 {
-			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("数量{}不在范围内[1,12]", data.music.len())));
-		}
+		let rules = &self.rules.music;
+		rules.check_count(data.music.len())?;
 		for c in data.music.iter() {
-			if c.reason.as_ref().map_or(0, |f| f.len()) > 4096 {
-				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "理由过长".into()));
-			}
+			rules.check_reason(&c.reason)?;
 			if c.first.unwrap_or_default() {
 				if first_set {
 					return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "多个本命".into()));
@@ -83,50 +164,54 @@ impl SubmitValidatorV1 {
 			}
 			chset.insert(c.id.clone());
 		}
+		rules.check_first(first_set)?;
 		// step 4: check all names are correct
+		check_known(&self.all_music, data.music.iter().map(|c| &c.id), "音乐")?;
 		// step 5: return
 		Ok(data)
 	}
-	pub async fn validate_cp(&self, mut data: models::CPSubmitRest, coll: &Collection<CPSubmitRest>) -> Result<models::CPSubmitRest, ServiceError> {
-		let query = doc! {
-			"meta.vote_id": data.meta.vote_id.clone()
-		};
-		// step 3: check ranks are unique from 1 to 6 and only one 本命
+	pub async fn validate_work(&self, data: models::WorkSubmitRest, coll: &Collection<WorkSubmitRest>) -> Result<models::WorkSubmitRest, ServiceError> {
+		// step 3: check counts and reasons against the rules, ids are unique and at most one 本命
+		let mut chset: HashSet<String> = HashSet::new();
 		let mut first_set = false;
-		if data.cps.len() < 1 || data.cps.len() > 4 {
-			return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("数量{}不在范围内[1,4]", data.cps.len())));
-		}
-		for c in data.cps.iter() {
-			if c.reason.as_ref().map_or(0, |f| f.len()) > 4096 {
-				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "理由过长".into()));
-			}
+		let rules = &self.rules.work;
+		rules.check_count(data.works.len())?;
+		for c in data.works.iter() {
+			rules.check_reason(&c.reason)?;
 			if c.first.unwrap_or_default() {
 				if first_set {
 					return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", "多个本命".into()));
 				}
 				first_set = true;
 			}
-			if let Some(active) = &c.active {
-				if *active != c.id_a && *active != c.id_b && {
-					if let Some(id_c) = &c.id_c {
-						*id_c != *active
-					} else {
-						true
-					}
-				} {
-					return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("主动方{}不存在", active)));
-				}
+			if chset.contains(&c.id) {
+				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("{}已存在", c.id)));
 			}
+			chset.insert(c.id.clone());
 		}
+		rules.check_first(first_set)?;
 		// step 4: check all names are correct
+		check_known(&self.all_works, data.works.iter().map(|c| &c.id), "作品")?;
 		// step 5: return
 		Ok(data)
 	}
-	pub async fn validate_paper(&self, data: models::PaperSubmitRest, coll: &Collection<PaperSubmitRest>) -> Result<models::PaperSubmitRest, ServiceError> {
+	pub async fn validate_cp(&self, mut data: models::CPSubmitRest, coll: &Collection<CPSubmitRest>) -> Result<models::CPSubmitRest, ServiceError> {
+		let query = doc! {
+			"meta.vote_id": data.meta.vote_id.clone()
+		};
+		// step 3: check counts and reasons against the rules, ids are unique and at most one 本命
+		check_cps(&self.rules.cp, &data.cps)?;
+		// step 4: check all names are correct
+		check_known(&self.all_characters, data.cps.iter().flat_map(|c| std::iter::once(&c.id_a).chain(std::iter::once(&c.id_b)).chain(c.id_c.iter())), "角色")?;
+		// step 5: return
+		Ok(data)
+	}
+	pub async fn validate_paper(&self, mut data: models::PaperSubmitRest, coll: &Collection<PaperSubmitRest>) -> Result<models::PaperSubmitRest, ServiceError> {
+		data.papers_json = self.paper.validate(&data.papers_json)?;
 		Ok(data)
 	}
 	pub async fn validate_dojin(&self, mut data: models::DojinSubmitRest, coll: &Collection<DojinSubmitRest>) -> Result<models::DojinSubmitRest, ServiceError> {
-		for item in &data.dojins {
+		for item in data.dojins.iter_mut() {
 			if item.author.len() > 4096 {
 				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("作者名过长")));
 			}
@@ -139,7 +224,151 @@ impl SubmitValidatorV1 {
 			if item.url.len() > 4096 {
 				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("URL过长")));
 			}
+			if !dojin::DOJIN_TYPES.contains(&item.dojin_type.as_str()) {
+				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("未知的作品类型：{}", item.dojin_type)));
+			}
+			let url = dojin::normalize_url(&item.url)?;
+			let url = self.dojin_resolver.expand_short_link(&url).await?;
+			item.url = url.to_string();
+			let site = match DojinSite::detect(&url) {
+				Some(site) => site,
+				// 不支持的网站保留用户填写的信息
+				None => continue
+			};
+			if let Some(allowed) = site.allowed_types() {
+				if !allowed.contains(&item.dojin_type.as_str()) {
+					return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("{}不是{}类作品", item.url, item.dojin_type)));
+				}
+			}
+			match self.dojin_resolver.resolve(&url).await {
+				Ok(Some(metadata)) => {
+					item.title = metadata.title;
+					if !metadata.authors.is_empty() {
+						item.author = metadata.authors.join(", ");
+					}
+					if item.image_url.is_none() {
+						item.image_url = metadata.cover;
+					}
+				},
+				Ok(None) => return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("作品不存在：{}", item.url))),
+				Err(e) => {
+					// scraper不可用时保留用户填写的信息
+					println!("Failed to resolve dojin metadata for {}: {:?}", item.url, e);
+				}
+			}
+		}
+		// 同一个作品只能提名一次
+		for i in 0..data.dojins.len() {
+			if let Some(j) = data.dojins[..i].iter().position(|f| f.url == data.dojins[i].url) {
+				return Err(ServiceError::new_human_readable(SERVICE_NAME, "INVALID_CONTENT", format!("第{}个和第{}个作品重复：{}", j + 1, i + 1, data.dojins[i].url)));
+			}
 		}
 		Ok(data)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use mongodb::{Client, options::ClientOptions};
+
+	use crate::dojin::{DojinMetadata, StubMetadataResolver};
+
+	use super::*;
+
+	fn error_message(e: ServiceError) -> (String, String) {
+		match e {
+			ServiceError::Error { resp } => (resp.error_kind, resp.human_readable_message.unwrap_or_default())
+		}
+	}
+
+	#[test]
+	fn unknown_ids_are_reported() {
+		let catalog = RwLock::new(["博丽灵梦", "雾雨魔理沙"].iter().map(|f| f.to_string()).collect::<HashSet<_>>());
+		let known = vec!["博丽灵梦".to_string(), "雾雨魔理沙".to_string()];
+		assert!(check_known(&catalog, known.iter(), "角色").is_ok());
+		let ids = vec!["博丽灵梦".to_string(), "琪露诺".to_string(), "东风谷早苗".to_string(), "琪露诺".to_string()];
+		let (kind, message) = error_message(check_known(&catalog, ids.iter(), "角色").unwrap_err());
+		assert_eq!(kind, "INVALID_CONTENT");
+		assert_eq!(message, "未知的角色：东风谷早苗, 琪露诺");
+	}
+
+	fn cp(id_a: &str, id_b: &str, id_c: Option<&str>, active: Option<&str>) -> CPSubmit {
+		CPSubmit {
+			id_a: id_a.into(),
+			id_b: id_b.into(),
+			id_c: id_c.map(|f| f.to_string()),
+			active: active.map(|f| f.to_string()),
+			first: None,
+			reason: None
+		}
+	}
+
+	#[test]
+	fn swapped_cp_members_are_the_same_cp() {
+		let rules = VotingRules::default().cp;
+		let cps = vec![cp("博丽灵梦", "雾雨魔理沙", None, Some("博丽灵梦")), cp("琪露诺", "大妖精", None, None), cp("雾雨魔理沙", "博丽灵梦", None, Some("雾雨魔理沙"))];
+		let (kind, message) = error_message(check_cps(&rules, &cps).unwrap_err());
+		assert_eq!(kind, "INVALID_CONTENT");
+		assert_eq!(message, "第1个和第3个CP重复：博丽灵梦×雾雨魔理沙");
+		assert!(check_cps(&rules, &cps[..2]).is_ok());
+	}
+
+	#[test]
+	fn three_person_cp_follows_rules() {
+		let mut rules = VotingRules::default().cp;
+		let cps = vec![cp("博丽灵梦", "雾雨魔理沙", Some("爱丽丝·玛格特洛依德"), Some("爱丽丝·玛格特洛依德")), cp("博丽灵梦", "雾雨魔理沙", None, None)];
+		// 两人CP与包含相同两人的三人CP不重复
+		assert!(check_cps(&rules, &cps).is_ok());
+		let swapped = vec![cps[0].clone(), cp("爱丽丝·玛格特洛依德", "博丽灵梦", Some("雾雨魔理沙"), None)];
+		assert_eq!(error_message(check_cps(&rules, &swapped).unwrap_err()).1, "第1个和第2个CP重复：博丽灵梦×爱丽丝·玛格特洛依德×雾雨魔理沙");
+		rules.allow_three_person = false;
+		assert_eq!(error_message(check_cps(&rules, &cps).unwrap_err()).1, "不允许三人CP");
+	}
+
+	#[test]
+	fn cp_members_must_differ_and_include_active() {
+		let rules = VotingRules::default().cp;
+		let (kind, _) = error_message(check_cps(&rules, &[cp("博丽灵梦", "博丽灵梦", None, None)]).unwrap_err());
+		assert_eq!(kind, "INVALID_CONTENT");
+		assert!(check_cps(&rules, &[cp("博丽灵梦", "雾雨魔理沙", Some("博丽灵梦"), None)]).is_err());
+		let (kind, message) = error_message(check_cps(&rules, &[cp("博丽灵梦", "雾雨魔理沙", None, Some("琪露诺"))]).unwrap_err());
+		assert_eq!(kind, "INVALID_CONTENT");
+		assert_eq!(message, "主动方琪露诺不存在");
+	}
+
+	fn dojin(url: &str) -> DojinSubmit {
+		DojinSubmit {
+			dojin_type: "Video".into(),
+			url: url.into(),
+			title: "title".into(),
+			author: "author".into(),
+			reason: "reason".into(),
+			image_url: None,
+			work_id: None
+		}
+	}
+
+	#[actix_rt::test]
+	async fn normalized_duplicate_dojin_urls_are_rejected() {
+		let mut resolver = StubMetadataResolver::default();
+		resolver.entries.insert("https://www.bilibili.com/video/BV1xx411c7mD".into(), DojinMetadata { title: "t".into(), authors: vec![], cover: None });
+		let validator = SubmitValidatorV1 {
+			all_characters: Arc::new(RwLock::new(HashSet::new())),
+			all_music: Arc::new(RwLock::new(HashSet::new())),
+			all_works: Arc::new(RwLock::new(HashSet::new())),
+			paper: Arc::new(serde_json::from_str(r#"{ "questions": [] }"#).unwrap()),
+			rules: Arc::new(VotingRules::default()),
+			dojin_resolver: Arc::new(resolver)
+		};
+		// validate_dojin不读写数据库，不需要连接
+		let coll = Client::with_options(ClientOptions::builder().build()).unwrap().database("submits_v1").collection::<DojinSubmitRest>("current_dojin");
+		let submit = |urls: &[&str]| DojinSubmitRest { dojins: urls.iter().map(|f| dojin(f)).collect(), meta: SubmitMetadata::new() };
+
+		let ok = validator.validate_dojin(submit(&["https://www.bilibili.com/video/BV1xx411c7mD", "https://example.com/works/1"]), &coll).await.unwrap();
+		assert_eq!(ok.dojins[0].title, "t");
+		let e = validator.validate_dojin(submit(&["https://www.bilibili.com/video/BV1xx411c7mD?spm_id_from=333.999", "https://example.com/works/1", "http://www.bilibili.com/video/BV1xx411c7mD#reply"]), &coll).await.unwrap_err();
+		assert_eq!(error_message(e), ("INVALID_CONTENT".into(), "第1个和第3个作品重复：https://www.bilibili.com/video/BV1xx411c7mD".into()));
+		let e = validator.validate_dojin(submit(&["http://example.com/works/1?utm_source=x", "https://example.com/works/1"]), &coll).await.unwrap_err();
+		assert_eq!(error_message(e), ("INVALID_CONTENT".into(), "第1个和第2个作品重复：https://example.com/works/1".into()));
+	}
+}
@@ -1,10 +1,10 @@
 use argon2::Config;
 use bson::{doc, oid::ObjectId, DateTime};
-use pvrustlib::ServiceError;
+use pvrustlib::{ServiceError, json_request};
 use rand::{RngCore, rngs::OsRng};
 use redis::AsyncCommands;
 
-use crate::{context::AppContext, common::{SERVICE_NAME, rate_limit}, log, models::ActivityLogEntry};
+use crate::{context::AppContext, common::{SERVICE_NAME, rate_limit}, log, models::{ActivityLogEntry, WithdrawVotesRequest}};
 
 
 pub async fn update_email(ctx: &AppContext, uid: ObjectId, email: String, verify_code: String, ip: Option<String>, additional_fingerprint: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
@@ -188,11 +188,30 @@ pub async fn update_password(ctx: &AppContext, uid: ObjectId, old_password: Opti
 
 pub async fn remove_voter(ctx: &AppContext, uid: ObjectId, ip: Option<String>, additional_fingerprint: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
 	if let Some(mut voter) = ctx.voters_coll.find_one(doc! { "_id": uid.clone() }, None).await? {
+		// 先撤回今年的投票，失败时账号保持不变，可以重试
+		// 未验证手机或邮箱的用户无法投票
+		if let Ok(vote_id) = voter.generate_vote_id(ctx.vote_year, ctx.patchyvideo_verifies()) {
+			let admin_token = match ctx.submit_admin_token.as_ref() {
+				Some(admin_token) => admin_token.clone(),
+				None => return Err(ServiceError::new_error_kind(SERVICE_NAME, "SUBMIT_ADMIN_TOKEN_NOT_CONFIGURED").into())
+			};
+			// 投票结束后也要撤回，只能通过管理接口
+			let req = WithdrawVotesRequest {
+				admin_token,
+				vote_id,
+				category: None,
+				reason: Some("用户注销".into())
+			};
+			let _: serde_json::Value = json_request(SERVICE_NAME, &format!("{}/v1/admin-withdraw/", crate::comm::SERVICE_SUBMIT_HANDLER_ADDRESS), req).await?;
+		}
 		voter.removed = Some(true);
 		voter.email = None;
 		voter.email_verified = false;
 		voter.phone = None;
 		voter.phone_verified = false;
+		voter.patchyvideo_uid = None;
+		voter.qq_openid = None;
+		voter.thbwiki_uid = None;
 		ctx.voters_coll.replace_one(doc! { "_id": uid.clone() }, voter.clone(), None).await?;
 		log(ctx, ActivityLogEntry::RemoveVoter {
 			created_at: DateTime::now(),
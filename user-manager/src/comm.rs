@@ -22,3 +22,9 @@ pub const SERVICE_EMAIL_ADDRESS: &'static str = "http://127.0.0.1:5011";
 
 #[cfg(not(debug_assertions))]
 pub const SERVICE_EMAIL_ADDRESS: &'static str = "http://email-service";
+
+#[cfg(debug_assertions)]
+pub const SERVICE_SUBMIT_HANDLER_ADDRESS: &'static str = "http://127.0.0.1:1101";
+
+#[cfg(not(debug_assertions))]
+pub const SERVICE_SUBMIT_HANDLER_ADDRESS: &'static str = "http://submit-handler";
@@ -1,10 +1,17 @@
 use std::sync::Arc;
 use std::cell::Cell;
 
-use jwt_simple::prelude::ES256kKeyPair;
+use std::str::FromStr;
+
+use jwt_simple::prelude::{ECDSAP256kPublicKeyLike, ES256kKeyPair, JWTClaims};
+use bson::{DateTime, doc, oid::ObjectId};
 use mongodb::{Collection, Database};
+use rand::{RngCore, rngs::OsRng};
+use redis::AsyncCommands;
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize};
 
-use crate::models::{ActivityLogEntry, Voter};
+use crate::{common::SERVICE_NAME, models::{ActivityLogEntry, VoteTokenClaim, Voter}, patchyvideo_link::PatchyVideoConfig, qq_binding::QQConnectProvider, thbwiki_login::ThbwikiConfig};
 
 #[derive(Clone, Debug)]
 pub struct AppContext {
@@ -15,21 +22,275 @@ pub struct AppContext {
     pub db: Database,
     pub voters_coll: Collection<Voter>,
     pub logs_coll: Collection<ActivityLogEntry>,
-    pub redis_client: redis::Client
+    pub redis_client: redis::Client,
+    /// 未配置时THBWiki登录不可用
+    pub thbwiki: Option<ThbwikiConfig>,
+    /// 未配置时QQ登录不可用
+    pub qq: Option<Arc<dyn QQConnectProvider>>,
+    /// 未配置时不能关联PatchyVideo账号
+    pub patchyvideo: Option<PatchyVideoConfig>,
+    /// 注销账号时撤回投票用，未配置时无法注销已能投票的账号
+    pub submit_admin_token: Option<String>
 }
 
-#[derive(Clone, Debug)]
+/// 第三方登录后尚未关联投票人时暂存的信息，登录或注册时合并到Voter
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LoginSession {
     pub thbwiki_uid: Option<String>,
     pub qq_openid: Option<String>,
     pub signup_ip: Option<String>
 }
 
+impl LoginSession {
+    /// voter已经绑定了其他THBWiki或QQ账号时拒绝合并，不会覆盖已有的绑定
+    pub fn merge_into(&self, voter: &mut Voter) -> Result<(), ServiceError> {
+        if let (Some(thbwiki_uid), Some(existing)) = (&self.thbwiki_uid, &voter.thbwiki_uid) {
+            if thbwiki_uid != existing {
+                return Err(ServiceError::new_error_kind(SERVICE_NAME, "THBWIKI_ALREADY_BOUND"));
+            }
+        }
+        if let (Some(qq_openid), Some(existing)) = (&self.qq_openid, &voter.qq_openid) {
+            if qq_openid != existing {
+                return Err(ServiceError::new_error_kind(SERVICE_NAME, "QQ_ALREADY_BOUND"));
+            }
+        }
+        if let Some(thbwiki_uid) = &self.thbwiki_uid {
+            voter.thbwiki_uid = Some(thbwiki_uid.clone());
+        }
+        if let Some(qq_openid) = &self.qq_openid {
+            voter.qq_openid = Some(qq_openid.clone());
+        }
+        Ok(())
+    }
+}
+
+/// holder为已绑定该第三方账号的投票人，不是voter本人时返回error_kind
+fn check_not_bound_to_other(voter: &Voter, holder: Option<Voter>, error_kind: &str) -> Result<(), ServiceError> {
+    match holder {
+        Some(holder) if voter._id.is_none() || holder._id != voter._id => Err(ServiceError::new_error_kind(SERVICE_NAME, error_kind)),
+        _ => Ok(())
+    }
+}
+
+/// 第三方登录回调的结果
+pub enum ThirdPartyLoginOutcome {
+    /// 已有投票人，直接登录
+    Login(Voter),
+    /// 没有对应的投票人，需要用手机或邮箱注册，注册时使用sid合并第三方账号
+    Signup {
+        sid: String,
+        nickname: Option<String>
+    }
+}
+
+/// 登录会话有效期，秒
+const LOGIN_SESSION_TTL: usize = 1800;
+
+/// 第三方授权请求的state有效期，秒
+const OAUTH_STATE_TTL: usize = 600;
+
+/// 用户token有效期，秒，与Voter::generate_user_auth一致
+const USER_TOKEN_TTL: usize = 7 * 24 * 3600;
+
+/// token是否在revoked_at_ms或之前签发
+///
+/// 没有issued_at_ms的token只能按iat的秒比较，与撤销在同一秒内签发的也视为已撤销
+pub fn is_revoked(claim: &JWTClaims<VoteTokenClaim>, revoked_at_ms: i64) -> bool {
+    match (claim.custom.issued_at_ms, claim.issued_at) {
+        (Some(issued_at_ms), _) => issued_at_ms <= revoked_at_ms,
+        (None, Some(issued_at)) => issued_at.as_secs() as i64 <= revoked_at_ms.div_euclid(1000),
+        (None, None) => true
+    }
+}
+
 impl AppContext {
-    pub fn create_login_session(&self, sess: LoginSession) -> String {
-        todo!()
+    /// 关联PatchyVideo账号是否可以代替手机或邮箱验证
+    pub fn patchyvideo_verifies(&self) -> bool {
+        self.patchyvideo.as_ref().map_or(false, |f| f.counts_as_verification)
+    }
+    /// 保存登录会话，返回放入sid cookie的会话ID
+    pub async fn create_login_session(&self, sess: LoginSession) -> Result<String, Box<dyn std::error::Error>> {
+        let mut sid = [0u8; 32];
+        OsRng.fill_bytes(&mut sid);
+        let sid = hex::encode(sid);
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let _: () = conn.set_ex(format!("login-session-{}", sid), serde_json::to_string(&sess)?, LOGIN_SESSION_TTL).await?;
+        Ok(sid)
+    }
+    /// 读取并删除登录会话，同一个会话只能使用一次
+    pub async fn get_login_session(&self, sid: &str) -> Result<Option<LoginSession>, Box<dyn std::error::Error>> {
+        let key = format!("login-session-{}", sid);
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let (sess,): (Option<String>,) = redis::pipe().atomic().get(&key).del(&key).ignore().query_async(&mut conn).await?;
+        match sess {
+            Some(sess) => Ok(Some(serde_json::from_str(&sess)?)),
+            None => Ok(None)
+        }
+    }
+    /// 生成第三方授权请求的state，provider区分不同的登录方式
+    pub async fn create_oauth_state(&self, provider: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let mut state = [0u8; 16];
+        OsRng.fill_bytes(&mut state);
+        let state = hex::encode(state);
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let _: () = conn.set_ex(format!("{}-oauth-state-{}", provider, state), "state", OAUTH_STATE_TTL).await?;
+        Ok(state)
+    }
+    /// 校验并删除state，同一个state只能使用一次
+    pub async fn consume_oauth_state(&self, provider: &str, state: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let key = format!("{}-oauth-state-{}", provider, state);
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let (saved,): (Option<String>,) = redis::pipe().atomic().get(&key).del(&key).ignore().query_async(&mut conn).await?;
+        Ok(saved.is_some())
+    }
+    /// 校验用户token并返回投票人ID，重置密码之前签发的token视为无效
+    pub async fn verify_user_token(&self, user_token: &str) -> Result<ObjectId, ServiceError> {
+        let claim = self.key_pair.public_key().verify_token::<VoteTokenClaim>(user_token, None).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
+        let uid = claim.custom.vote_id.as_deref().and_then(|f| ObjectId::from_str(f).ok()).ok_or_else(|| ServiceError::new_jwt_error(SERVICE_NAME, None))?;
+        let mut conn = self.redis_client.get_async_connection().await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+        let revoked_at_ms: Option<i64> = conn.get(format!("user-token-revoked-ms-{}", uid)).await.map_err(|e| ServiceError::new(SERVICE_NAME, format!("{:?}", e)))?;
+        if let Some(revoked_at_ms) = revoked_at_ms {
+            if is_revoked(&claim, revoked_at_ms) {
+                return Err(ServiceError::new_jwt_error(SERVICE_NAME, Some("token revoked".into())));
+            }
+        }
+        Ok(uid)
     }
-    pub async fn get_login_session(&self, sid: &str) -> Option<LoginSession> {
-        todo!()
+    /// 使该投票人此前签发的全部用户token失效，记录保留到这些token自然过期
+    pub async fn revoke_user_tokens(&self, uid: &ObjectId) -> Result<(), Box<dyn std::error::Error>> {
+        let mut conn = self.redis_client.get_async_connection().await?;
+        let _: () = conn.set_ex(format!("user-token-revoked-ms-{}", uid), chrono::Utc::now().timestamp_millis(), USER_TOKEN_TTL).await?;
+        Ok(())
+    }
+    /// 把登录会话中的第三方账号合并到voter，第三方账号已被其他投票人绑定时拒绝
+    ///
+    /// qq_openid和thbwiki_uid上有唯一索引，并发绑定同一个账号时只有一个能写入
+    pub async fn merge_login_session(&self, sess: &LoginSession, voter: &mut Voter) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(qq_openid) = &sess.qq_openid {
+            let holder = self.voters_coll.find_one(doc! { "qq_openid": qq_openid, "removed": { "$ne": true } }, None).await?;
+            check_not_bound_to_other(voter, holder, "QQ_IN_USE")?;
+        }
+        if let Some(thbwiki_uid) = &sess.thbwiki_uid {
+            let holder = self.voters_coll.find_one(doc! { "thbwiki_uid": thbwiki_uid, "removed": { "$ne": true } }, None).await?;
+            check_not_bound_to_other(voter, holder, "THBWIKI_IN_USE")?;
+        }
+        sess.merge_into(voter)?;
+        Ok(())
+    }
+    /// 记录通过登录会话完成的第三方账号绑定，uid为合并后的投票人
+    pub async fn log_login_session(&self, uid: &ObjectId, sess: &LoginSession, ip: Option<String>, additional_fingerprint: Option<String>) {
+        if let Some(qq_openid) = &sess.qq_openid {
+            crate::log(self, ActivityLogEntry::BindQQ {
+                created_at: DateTime::now(),
+                uid: uid.clone(),
+                qq_openid: qq_openid.clone(),
+                requester_ip: ip.clone(),
+                requester_additional_fingerprint: additional_fingerprint.clone()
+            }).await;
+        }
+        if let Some(thbwiki_uid) = &sess.thbwiki_uid {
+            crate::log(self, ActivityLogEntry::LinkThbwiki {
+                created_at: DateTime::now(),
+                uid: uid.clone(),
+                thbwiki_uid: thbwiki_uid.clone(),
+                requester_ip: ip,
+                requester_additional_fingerprint: additional_fingerprint
+            }).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::DateTime;
+    use jwt_simple::prelude::{Claims, Duration};
+
+    use super::*;
+
+    fn voter() -> Voter {
+        Voter {
+            _id: Some(ObjectId::new()),
+            phone: None,
+            phone_verified: false,
+            email: Some("reimu@example.com".into()),
+            email_verified: true,
+            password_hashed: None,
+            salt: None,
+            created_at: DateTime::now(),
+            nickname: None,
+            signup_ip: None,
+            qq_openid: None,
+            pfp: None,
+            thbwiki_uid: None,
+            patchyvideo_uid: None,
+            removed: None
+        }
+    }
+
+    fn claim(key_pair: &ES256kKeyPair, token: &str) -> JWTClaims<VoteTokenClaim> {
+        key_pair.public_key().verify_token::<VoteTokenClaim>(token, None).unwrap()
+    }
+
+    #[test]
+    fn tokens_issued_before_revocation_are_rejected() {
+        let key_pair = ES256kKeyPair::generate();
+        let voter = voter();
+        let before = claim(&key_pair, &voter.generate_user_auth(&key_pair));
+        let revoked_at_ms = before.custom.issued_at_ms.unwrap();
+        assert!(is_revoked(&before, revoked_at_ms));
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        // 撤销之后签发的token即使与撤销在同一秒内也有效
+        let after = claim(&key_pair, &voter.generate_user_auth(&key_pair));
+        assert!(!is_revoked(&after, revoked_at_ms));
+    }
+
+    #[test]
+    fn tokens_without_issued_at_ms_use_seconds() {
+        let legacy = Claims::with_custom_claims(VoteTokenClaim { vote_id: None, issued_at_ms: None }, Duration::from_hours(1));
+        let issued_at_ms = legacy.issued_at.unwrap().as_secs() as i64 * 1000;
+        assert!(is_revoked(&legacy, issued_at_ms + 999));
+        assert!(!is_revoked(&legacy, issued_at_ms - 1));
+    }
+
+    fn error_kind(e: ServiceError) -> String {
+        match e {
+            ServiceError::Error { resp } => resp.error_kind
+        }
+    }
+
+    fn session(thbwiki_uid: Option<&str>, qq_openid: Option<&str>) -> LoginSession {
+        LoginSession { thbwiki_uid: thbwiki_uid.map(|f| f.to_string()), qq_openid: qq_openid.map(|f| f.to_string()), signup_ip: None }
+    }
+
+    #[test]
+    fn merge_refuses_to_replace_existing_bindings() {
+        let mut bound = voter();
+        bound.thbwiki_uid = Some("thbwiki-1".into());
+        bound.qq_openid = Some("openid-1".into());
+        assert_eq!(error_kind(session(Some("thbwiki-2"), None).merge_into(&mut bound.clone()).unwrap_err()), "THBWIKI_ALREADY_BOUND");
+        assert_eq!(error_kind(session(None, Some("openid-2")).merge_into(&mut bound.clone()).unwrap_err()), "QQ_ALREADY_BOUND");
+        // 拒绝时不修改voter
+        let mut merged = bound.clone();
+        assert!(session(Some("thbwiki-1"), Some("openid-2")).merge_into(&mut merged).is_err());
+        assert_eq!(merged.thbwiki_uid.as_deref(), Some("thbwiki-1"));
+        assert_eq!(merged.qq_openid.as_deref(), Some("openid-1"));
+        // 相同的绑定和未绑定的账号可以合并
+        session(Some("thbwiki-1"), Some("openid-1")).merge_into(&mut merged).unwrap();
+        let mut unbound = voter();
+        session(Some("thbwiki-2"), Some("openid-2")).merge_into(&mut unbound).unwrap();
+        assert_eq!(unbound.thbwiki_uid.as_deref(), Some("thbwiki-2"));
+        assert_eq!(unbound.qq_openid.as_deref(), Some("openid-2"));
+    }
+
+    #[test]
+    fn accounts_bound_to_other_voters_are_in_use() {
+        let existing = voter();
+        assert!(check_not_bound_to_other(&existing, None, "QQ_IN_USE").is_ok());
+        assert!(check_not_bound_to_other(&existing, Some(existing.clone()), "QQ_IN_USE").is_ok());
+        assert_eq!(error_kind(check_not_bound_to_other(&existing, Some(voter()), "QQ_IN_USE").unwrap_err()), "QQ_IN_USE");
+        // 注册中的投票人还没有_id
+        let mut signing_up = voter();
+        signing_up._id = None;
+        assert_eq!(error_kind(check_not_bound_to_other(&signing_up, Some(existing), "THBWIKI_IN_USE").unwrap_err()), "THBWIKI_IN_USE");
     }
 }
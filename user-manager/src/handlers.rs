@@ -1,20 +1,17 @@
 
-use std::str::FromStr;
-
-use actix_web::{App, HttpMessage, HttpRequest, HttpServer, Responder, web};
-use bson::oid::ObjectId;
-use jwt_simple::prelude::{Claims, ECDSAP256kPublicKeyLike};
+use actix_web::{App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder, cookie::Cookie, web};
+use jwt_simple::prelude::{Claims};
 use pvrustlib::{ServiceError, EmptyJSON};
-use crate::{account_management, context::AppContext, legacy_login, models::{VoteTokenClaim}, new_login, common::SERVICE_NAME};
+use crate::{account_management, context::{AppContext, ThirdPartyLoginOutcome}, legacy_login, new_login, password_reset, patchyvideo_link, qq_binding, thbwiki_login, common::SERVICE_NAME};
 
 use super::models;
 
 pub async fn login_email_password(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::EmailLoginInputsForExistingVoters>) -> Result<web::Json<models::LoginResults>, ServiceError> {
-	let sid = request.cookie("sid").map(|f| f.to_string());
+	let sid = request.cookie("sid").map(|f| f.value().to_string());
 	let result = legacy_login::login_email_password(&ctx, body.email.clone(), body.password.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone(), sid).await;
 	match result {
 		Ok(r) => {
-			let vote_token = r.generate_vote_token(ctx.vote_year, ctx.vote_start, ctx.vote_end, &ctx.key_pair)?;
+			let vote_token = r.generate_vote_token(ctx.vote_year, ctx.patchyvideo_verifies(), ctx.vote_start, ctx.vote_end, &ctx.key_pair)?;
 			let user_token = r.generate_user_auth(&ctx.key_pair);
 			return Ok(web::Json(models::LoginResults { user: r.to_fe_voter(&ctx.key_pair), vote_token: vote_token, session_token: user_token }));
 		},
@@ -25,11 +22,11 @@ pub async fn login_email_password(ctx: web::Data<AppContext>, request: HttpReque
 }
 
 pub async fn login_email(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::EmailLoginInputs>) -> Result<web::Json<models::LoginResults>, ServiceError> {
-	let sid = request.cookie("sid").map(|f| f.to_string());
+	let sid = request.cookie("sid").map(|f| f.value().to_string());
 	let result = new_login::login_email(&ctx, body.email.clone(), body.verify_code.clone(), body.nickname.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone(), sid).await;
 	match result {
 		Ok(r) => {
-			let vote_token = r.generate_vote_token(ctx.vote_year, ctx.vote_start, ctx.vote_end, &ctx.key_pair)?;
+			let vote_token = r.generate_vote_token(ctx.vote_year, ctx.patchyvideo_verifies(), ctx.vote_start, ctx.vote_end, &ctx.key_pair)?;
 			let user_token = r.generate_user_auth(&ctx.key_pair);
 			return Ok(web::Json(models::LoginResults { user: r.to_fe_voter(&ctx.key_pair), vote_token: vote_token, session_token: user_token }));
 		},
@@ -40,11 +37,11 @@ pub async fn login_email(ctx: web::Data<AppContext>, request: HttpRequest, body:
 }
 
 pub async fn login_phone(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::PhoneLoginInputs>) -> Result<web::Json<models::LoginResults>, ServiceError> {
-	let sid = request.cookie("sid").map(|f| f.to_string());
+	let sid = request.cookie("sid").map(|f| f.value().to_string());
 	let result = new_login::login_phone(&ctx, body.phone.clone(), body.verify_code.clone(), body.nickname.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone(), sid).await;
 	match result {
 		Ok(r) => {
-			let vote_token = r.generate_vote_token(ctx.vote_year, ctx.vote_start, ctx.vote_end, &ctx.key_pair)?;
+			let vote_token = r.generate_vote_token(ctx.vote_year, ctx.patchyvideo_verifies(), ctx.vote_start, ctx.vote_end, &ctx.key_pair)?;
 			let user_token = r.generate_user_auth(&ctx.key_pair);
 			return Ok(web::Json(models::LoginResults { user: r.to_fe_voter(&ctx.key_pair), vote_token: vote_token, session_token: user_token }));
 		},
@@ -80,8 +77,7 @@ pub async fn send_email_verify_code(ctx: web::Data<AppContext>, body: actix_web:
 }
 
 pub async fn update_email(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::UpdateEmailInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
-	let claim = ctx.key_pair.public_key().verify_token::<VoteTokenClaim>(&body.user_token, None).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
-	let uid: ObjectId = ObjectId::from_str(&claim.custom.vote_id.unwrap()).unwrap();
+	let uid = ctx.verify_user_token(&body.user_token).await?;
 	let result = account_management::update_email(&ctx, uid, body.email.clone(), body.verify_code.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
 	match result {
 		Ok(r) => {
@@ -94,8 +90,7 @@ pub async fn update_email(ctx: web::Data<AppContext>, request: HttpRequest, body
 }
 
 pub async fn update_phone(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::UpdatePhoneInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
-	let claim = ctx.key_pair.public_key().verify_token::<VoteTokenClaim>(&body.user_token, None).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
-	let uid: ObjectId = ObjectId::from_str(&claim.custom.vote_id.unwrap()).unwrap();
+	let uid = ctx.verify_user_token(&body.user_token).await?;
 	let result = account_management::update_phone(&ctx, uid, body.phone.clone(), body.verify_code.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
 	match result {
 		Ok(r) => {
@@ -108,8 +103,7 @@ pub async fn update_phone(ctx: web::Data<AppContext>, request: HttpRequest, body
 }
 
 pub async fn update_nickname(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::UpdateNicknameInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
-	let claim = ctx.key_pair.public_key().verify_token::<VoteTokenClaim>(&body.user_token, None).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
-	let uid: ObjectId = ObjectId::from_str(&claim.custom.vote_id.unwrap()).unwrap();
+	let uid = ctx.verify_user_token(&body.user_token).await?;
 	let result = account_management::update_nickname(&ctx, uid, body.nickname.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
 	match result {
 		Ok(r) => {
@@ -122,8 +116,7 @@ pub async fn update_nickname(ctx: web::Data<AppContext>, request: HttpRequest, b
 }
 
 pub async fn update_password(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::UpdatePasswordInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
-	let claim = ctx.key_pair.public_key().verify_token::<VoteTokenClaim>(&body.user_token, None).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
-	let uid: ObjectId = ObjectId::from_str(&claim.custom.vote_id.unwrap()).unwrap();
+	let uid = ctx.verify_user_token(&body.user_token).await?;
 	let result = account_management::update_password(&ctx, uid, body.old_password.clone(), body.new_password.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
 	match result {
 		Ok(r) => {
@@ -137,13 +130,12 @@ pub async fn update_password(ctx: web::Data<AppContext>, request: HttpRequest, b
 
 
 pub async fn user_token_status(ctx: web::Data<AppContext>, body: actix_web::web::Json<models::TokenStatusInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
-	let claim = ctx.key_pair.public_key().verify_token::<VoteTokenClaim>(&body.user_token, None).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
+	ctx.verify_user_token(&body.user_token).await?;
 	return Ok(web::Json(EmptyJSON::new()))
 }
 
 pub async fn remove_voter(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::RemoveVoterRequest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
-	let claim = ctx.key_pair.public_key().verify_token::<VoteTokenClaim>(&body.user_token, None).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
-	let uid: ObjectId = ObjectId::from_str(&claim.custom.vote_id.unwrap()).unwrap();
+	let uid = ctx.verify_user_token(&body.user_token).await?;
 	let result = account_management::remove_voter(&ctx, uid, Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
 	match result {
 		Ok(r) => {
@@ -154,3 +146,120 @@ pub async fn remove_voter(ctx: web::Data<AppContext>, request: HttpRequest, body
 		},
 	}
 }
+
+/// 第三方登录回调的响应，需要注册时设置sid cookie
+fn third_party_login_response(ctx: &AppContext, outcome: ThirdPartyLoginOutcome) -> Result<HttpResponse, ServiceError> {
+	match outcome {
+		ThirdPartyLoginOutcome::Login(r) => {
+			let vote_token = r.generate_vote_token(ctx.vote_year, ctx.patchyvideo_verifies(), ctx.vote_start, ctx.vote_end, &ctx.key_pair)?;
+			let user_token = r.generate_user_auth(&ctx.key_pair);
+			let login = models::LoginResults { user: r.to_fe_voter(&ctx.key_pair), vote_token: vote_token, session_token: user_token };
+			Ok(HttpResponse::Ok().json(models::ThirdPartyLoginResults { login: Some(login), sid: None, nickname: None }))
+		},
+		ThirdPartyLoginOutcome::Signup { sid, nickname } => {
+			// 之后的手机或邮箱登录读取这个cookie完成关联
+			let cookie = Cookie::build("sid", sid.clone()).path("/").http_only(true).finish();
+			Ok(HttpResponse::Ok().cookie(cookie).json(models::ThirdPartyLoginResults { login: None, sid: Some(sid), nickname }))
+		}
+	}
+}
+
+pub async fn thbwiki_authorize(ctx: web::Data<AppContext>) -> Result<web::Json<models::AuthorizeUrlResponse>, ServiceError> {
+	let result = thbwiki_login::authorize(&ctx).await;
+	match result {
+		Ok(url) => {
+			return Ok(web::Json(models::AuthorizeUrlResponse { url }));
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
+
+pub async fn thbwiki_callback(ctx: web::Data<AppContext>, body: actix_web::web::Json<models::OAuthCallbackInputs>) -> Result<HttpResponse, ServiceError> {
+	let result = thbwiki_login::redirect_callback(&ctx, body.code.clone(), body.state.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
+	match result {
+		Ok(r) => {
+			return third_party_login_response(&ctx, r);
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
+
+pub async fn qq_authorize(ctx: web::Data<AppContext>) -> Result<web::Json<models::AuthorizeUrlResponse>, ServiceError> {
+	let result = qq_binding::authorize(&ctx).await;
+	match result {
+		Ok(url) => {
+			return Ok(web::Json(models::AuthorizeUrlResponse { url }));
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
+
+pub async fn qq_callback(ctx: web::Data<AppContext>, body: actix_web::web::Json<models::OAuthCallbackInputs>) -> Result<HttpResponse, ServiceError> {
+	let result = qq_binding::redirect_callback(&ctx, body.code.clone(), body.state.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
+	match result {
+		Ok(r) => {
+			return third_party_login_response(&ctx, r);
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
+
+pub async fn unbind_qq(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::UnbindQQInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	let uid = ctx.verify_user_token(&body.user_token).await?;
+	let result = qq_binding::unbind_qq(&ctx, uid, Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
+	match result {
+		Ok(r) => {
+			return Ok(web::Json(EmptyJSON::new()));
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
+
+pub async fn link_patchyvideo(ctx: web::Data<AppContext>, request: HttpRequest, body: actix_web::web::Json<models::LinkPatchyVideoInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	let uid = ctx.verify_user_token(&body.user_token).await?;
+	let result = patchyvideo_link::link_patchyvideo(&ctx, uid, body.patchyvideo_token.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
+	match result {
+		Ok(r) => {
+			return Ok(web::Json(EmptyJSON::new()));
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
+
+pub async fn send_password_reset_code(ctx: web::Data<AppContext>, body: actix_web::web::Json<models::SendPasswordResetCodeRequest>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	let target = password_reset::ResetTarget::new(body.email.clone(), body.phone.clone())?;
+	let result = password_reset::send_reset_code(&ctx, target, Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
+	match result {
+		Ok(r) => {
+			return Ok(web::Json(EmptyJSON::new()));
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
+
+pub async fn reset_password(ctx: web::Data<AppContext>, body: actix_web::web::Json<models::ResetPasswordInputs>) -> Result<web::Json<EmptyJSON>, ServiceError> {
+	let target = password_reset::ResetTarget::new(body.email.clone(), body.phone.clone())?;
+	let result = password_reset::reset_password(&ctx, target, body.verify_code.clone(), body.new_password.clone(), Some(body.meta.user_ip.clone()), body.meta.additional_fingureprint.clone()).await;
+	match result {
+		Ok(r) => {
+			return Ok(web::Json(EmptyJSON::new()));
+		},
+		Err(e) => {
+			return Err(ServiceError::from_dyn_error(SERVICE_NAME, e));
+		},
+	}
+}
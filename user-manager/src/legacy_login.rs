@@ -25,17 +25,17 @@ pub async fn login_email_password(ctx: &AppContext, email: String, password: Str
 					let mut voter = voter.clone();
 					voter.salt = None;
 					voter.password_hashed = Some(new_password_hashed.clone());
+					let mut merged_session = None;
 					if let Some(sid) = sid {
-						if let Some(sess) = ctx.get_login_session(&sid).await {
-							if let Some(thbwiki_uid) = sess.thbwiki_uid {
-								voter.thbwiki_uid = Some(thbwiki_uid);
-							}
-							if let Some(qq_openid) = sess.qq_openid {
-								voter.qq_openid = Some(qq_openid);
-							}
+						if let Some(sess) = ctx.get_login_session(&sid).await? {
+							ctx.merge_login_session(&sess, &mut voter).await?;
+							merged_session = Some(sess);
 						}
 					}
 					ctx.voters_coll.replace_one(doc! { "email": email.clone() }, voter.clone(), None).await?;
+					if let Some(sess) = merged_session {
+						ctx.log_login_session(voter._id.as_ref().unwrap(), &sess, ip.clone(), additional_fingerprint.clone()).await;
+					}
 					log(ctx, ActivityLogEntry::VoterLogin {
 						created_at: DateTime::now(),
 						uid: voter._id.as_ref().unwrap().clone(),
@@ -50,14 +50,10 @@ pub async fn login_email_password(ctx: &AppContext, email: String, password: Str
 			if argon2::verify_encoded(password_hashed, password.as_bytes())? {
 				let mut voter = voter.clone();
 				if let Some(sid) = sid {
-					if let Some(sess) = ctx.get_login_session(&sid).await {
-						if let Some(thbwiki_uid) = sess.thbwiki_uid {
-							voter.thbwiki_uid = Some(thbwiki_uid);
-						}
-						if let Some(qq_openid) = sess.qq_openid {
-							voter.qq_openid = Some(qq_openid);
-						}
+					if let Some(sess) = ctx.get_login_session(&sid).await? {
+						ctx.merge_login_session(&sess, &mut voter).await?;
 						ctx.voters_coll.replace_one(doc! { "email": email.clone() }, voter.clone(), None).await?;
+						ctx.log_login_session(voter._id.as_ref().unwrap(), &sess, ip.clone(), additional_fingerprint.clone()).await;
 					}
 				}
 				log(ctx, ActivityLogEntry::VoterLogin {
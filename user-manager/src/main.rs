@@ -13,6 +13,8 @@ pub mod legacy_login;
 pub mod new_login;
 pub mod thbwiki_login;
 pub mod qq_binding;
+pub mod patchyvideo_link;
+pub mod password_reset;
 
 pub mod account_management;
 
@@ -22,7 +24,7 @@ use actix_web::{App, HttpRequest, HttpServer, Responder, web::{self, Data}};
 use context::AppContext;
 use jwt::load_keys;
 use models::ActivityLogEntry;
-use mongodb::{Client, options::ClientOptions};
+use mongodb::{Client, IndexModel, bson::{doc, Document}, options::{ClientOptions, IndexOptions}};
 use serde::{Deserialize, Serialize};
 
 use redis::AsyncCommands;
@@ -38,9 +40,24 @@ pub struct Config_vote_date {
     pub vote_end: String
 }
 
+/// 调用submit-handler管理接口
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct SubmitHandlerConfig {
+    /// submit-handler的[admin]中配置的令牌
+    pub admin_token: String
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Config {
     pub vote_date: Config_vote_date,
+    #[serde(default)]
+    pub thbwiki: Option<thbwiki_login::ThbwikiConfig>,
+    #[serde(default)]
+    pub qq: Option<qq_binding::QQConnectConfig>,
+    #[serde(default)]
+    pub patchyvideo: Option<patchyvideo_link::PatchyVideoConfig>,
+    #[serde(default)]
+    pub submit_handler: Option<SubmitHandlerConfig>,
 }
 
 #[actix_web::main]
@@ -55,6 +72,22 @@ async fn main() -> std::io::Result<()> {
 
 	let db = client.database("thvote_users");
 
+    // 一个PatchyVideo账号只能关联一个投票人，未关联的投票人此字段为null
+    let patchyvideo_index = IndexModel::builder()
+        .keys(doc! { "patchyvideo_uid": 1 })
+        .options(IndexOptions::builder().unique(true).partial_filter_expression(doc! { "patchyvideo_uid": { "$type": "string" } }).build())
+        .build();
+    db.collection::<Document>("voters").create_index(patchyvideo_index, None).await.expect("Failed to create index");
+
+    // 一个QQ或THBWiki账号也只能绑定一个投票人
+    for field in ["qq_openid", "thbwiki_uid"] {
+        let index = IndexModel::builder()
+            .keys(doc! { field: 1 })
+            .options(IndexOptions::builder().unique(true).partial_filter_expression(doc! { field: { "$type": "string" } }).build())
+            .build();
+        db.collection::<Document>("voters").create_index(index, None).await.expect("Failed to create index");
+    }
+
     let redis_client = redis::Client::open(comm::REDIS_ADDRESS).unwrap();
 
     let ctx = context::AppContext {
@@ -66,6 +99,10 @@ async fn main() -> std::io::Result<()> {
         logs_coll: db.collection("voter_logs"),
         redis_client: redis_client,
         key_pair: load_keys().await.unwrap(),
+        thbwiki: config.thbwiki,
+        qq: config.qq.map(|c| Arc::new(qq_binding::QQConnectClient::new(c)) as Arc<dyn qq_binding::QQConnectProvider>),
+        patchyvideo: config.patchyvideo,
+        submit_admin_token: config.submit_handler.map(|c| c.admin_token),
     };
     HttpServer::new(move || {
         App::new().app_data(Data::new(ctx.clone()))
@@ -80,6 +117,14 @@ async fn main() -> std::io::Result<()> {
             .route("/v1/send-email-code", web::post().to(handlers::send_email_verify_code))
             .route("/v1/user-token-status", web::post().to(handlers::user_token_status))
             .route("/v1/remove-voter", web::post().to(handlers::remove_voter))
+            .route("/v1/thbwiki-authorize", web::post().to(handlers::thbwiki_authorize))
+            .route("/v1/thbwiki-callback", web::post().to(handlers::thbwiki_callback))
+            .route("/v1/qq-authorize", web::post().to(handlers::qq_authorize))
+            .route("/v1/qq-callback", web::post().to(handlers::qq_callback))
+            .route("/v1/unbind-qq", web::post().to(handlers::unbind_qq))
+            .route("/v1/link-patchyvideo", web::post().to(handlers::link_patchyvideo))
+            .route("/v1/send-password-reset-code", web::post().to(handlers::send_password_reset_code))
+            .route("/v1/reset-password", web::post().to(handlers::reset_password))
     })
     .bind("0.0.0.0:80")?
     .run()
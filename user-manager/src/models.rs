@@ -12,7 +12,10 @@ use crate::{context::LoginSession, common::SERVICE_NAME};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct VoteTokenClaim {
-	pub vote_id: Option<String>
+	pub vote_id: Option<String>,
+	/// 用户token的签发时间，毫秒，iat只精确到秒，撤销token时用这个比较
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub issued_at_ms: Option<i64>
 }
 
 
@@ -25,6 +28,7 @@ pub struct VoterFE {
 	pub phone: Option<String>,
 	pub email: Option<String>,
 	pub thbwiki: bool,
+	pub qq: bool,
 	pub patchyvideo: bool,
 	pub created_at: chrono::DateTime<chrono::Utc>,
 }
@@ -49,14 +53,18 @@ pub struct Voter {
 	pub qq_openid: Option<String>,
 	pub pfp: Option<String>,
 	pub thbwiki_uid: Option<String>,
+	/// 关联的PatchyVideo用户ID
+	pub patchyvideo_uid: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub removed: Option<bool>
 }
 
 impl Voter {
 	/// Generate a unique id connected to voter for a given year
-	pub fn generate_vote_id(&self, vote_year: u32) -> Result<String, ServiceError> {
-		if self.phone_verified || self.email_verified {
+	///
+	/// `patchyvideo_verifies`为true时关联了PatchyVideo账号也视为已验证
+	pub fn generate_vote_id(&self, vote_year: u32, patchyvideo_verifies: bool) -> Result<String, ServiceError> {
+		if self.phone_verified || self.email_verified || (patchyvideo_verifies && self.patchyvideo_uid.is_some()) {
 			let id = self._id.as_ref().unwrap().clone().to_string();
 			return Ok(format!("thvote-{}-{}", vote_year, id));
 		}
@@ -67,9 +75,10 @@ impl Voter {
 	/// 2. valid since
 	/// 3. valid until
 	/// 4. scope (vote or login)
-	pub fn generate_vote_token(&self, vote_year: u32, vote_start: chrono::DateTime<chrono::Utc>, vote_end: chrono::DateTime<chrono::Utc>, key: &ES256kKeyPair) -> Result<String, ServiceError> {
+	pub fn generate_vote_token(&self, vote_year: u32, patchyvideo_verifies: bool, vote_start: chrono::DateTime<chrono::Utc>, vote_end: chrono::DateTime<chrono::Utc>, key: &ES256kKeyPair) -> Result<String, ServiceError> {
 		let additional_info = VoteTokenClaim {
-			vote_id: Some(self.generate_vote_id(vote_year)?)
+			vote_id: Some(self.generate_vote_id(vote_year, patchyvideo_verifies)?),
+			issued_at_ms: None
 		};
 		let diff = vote_end - vote_start;
 		let claims = Claims::with_custom_claims_given_valid_period(
@@ -85,7 +94,8 @@ impl Voter {
 	/// 2. scope (vote or login)
 	pub fn generate_user_auth(&self, key: &ES256kKeyPair) -> String {
 		let additional_info = VoteTokenClaim {
-			vote_id: Some(self._id.as_ref().unwrap().clone().to_string())
+			vote_id: Some(self._id.as_ref().unwrap().clone().to_string()),
+			issued_at_ms: Some(Utc::now().timestamp_millis())
 		};
 		let claims = Claims::with_custom_claims(additional_info, Duration::from_hours(7 * 24))
 			.with_audience("userspace");
@@ -98,8 +108,9 @@ impl Voter {
 			password: self.password_hashed.is_some(),
 			phone: self.phone.clone(),
 			email: self.email.clone(),
-			thbwiki: false,
-			patchyvideo: false,
+			thbwiki: self.thbwiki_uid.is_some(),
+			qq: self.qq_openid.is_some(),
+			patchyvideo: self.patchyvideo_uid.is_some(),
 			created_at: self.created_at.to_chrono()
 		}
 	}
@@ -168,6 +179,37 @@ pub struct UpdatePasswordInputs {
     pub meta: UserEventMeta
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UnbindQQInputs {
+	pub user_token: String,
+    pub meta: UserEventMeta
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LinkPatchyVideoInputs {
+	pub user_token: String,
+	/// PatchyVideo签发的关联token
+    pub patchyvideo_token: String,
+    pub meta: UserEventMeta
+}
+
+/// email和phone二选一
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SendPasswordResetCodeRequest {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub meta: UserEventMeta
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ResetPasswordInputs {
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub verify_code: String,
+    pub new_password: String,
+    pub meta: UserEventMeta
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TokenStatusInputs {
 	pub user_token: String
@@ -191,6 +233,30 @@ pub struct LoginResults {
 	pub session_token: String
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuthorizeUrlResponse {
+	/// 第三方授权页面
+	pub url: String
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+/// 第三方授权回调收到的参数
+pub struct OAuthCallbackInputs {
+    pub code: String,
+    pub state: String,
+    pub meta: UserEventMeta
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ThirdPartyLoginResults {
+	/// 已有投票人时的登录结果
+	pub login: Option<LoginResults>,
+	/// 需要注册时的登录会话，注册请求需带上同名cookie
+	pub sid: Option<String>,
+	/// 第三方账号的昵称，用作注册时的默认昵称
+	pub nickname: Option<String>
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ActivityLogEntry {
 	SendEmail {
@@ -259,9 +325,55 @@ pub enum ActivityLogEntry {
 		uid: ObjectId,
 		requester_ip: Option<String>,
 		requester_additional_fingerprint: Option<String>
+	},
+	LinkThbwiki {
+		created_at: DateTime,
+		uid: ObjectId,
+		thbwiki_uid: String,
+		requester_ip: Option<String>,
+		requester_additional_fingerprint: Option<String>
+	},
+	BindQQ {
+		created_at: DateTime,
+		uid: ObjectId,
+		qq_openid: String,
+		requester_ip: Option<String>,
+		requester_additional_fingerprint: Option<String>
+	},
+	UnbindQQ {
+		created_at: DateTime,
+		uid: ObjectId,
+		qq_openid: String,
+		requester_ip: Option<String>,
+		requester_additional_fingerprint: Option<String>
+	},
+	LinkPatchyVideo {
+		created_at: DateTime,
+		uid: ObjectId,
+		patchyvideo_uid: String,
+		requester_ip: Option<String>,
+		requester_additional_fingerprint: Option<String>
+	},
+	PasswordReset {
+		created_at: DateTime,
+		uid: ObjectId,
+		email: Option<String>,
+		phone: Option<String>,
+		requester_ip: Option<String>,
+		requester_additional_fingerprint: Option<String>
 	}
 }
 
+/// submit-handler的/v1/admin-withdraw/
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WithdrawVotesRequest {
+	pub admin_token: String,
+	pub vote_id: String,
+	/// 为None时撤回所有类别
+	pub category: Option<String>,
+	pub reason: Option<String>
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RemoveVoterRequest {
 	pub user_token: String,
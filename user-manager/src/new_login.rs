@@ -36,16 +36,14 @@ pub async fn signup_email(ctx: &AppContext, email: String, verify_code: String,
 			qq_openid: None,
 			pfp: None,
 			thbwiki_uid: None,
+			patchyvideo_uid: None,
 			removed: None
 		};
+		let mut merged_session = None;
 		if let Some(sid) = sid {
-			if let Some(sess) = ctx.get_login_session(&sid).await {
-				if let Some(thbwiki_uid) = sess.thbwiki_uid {
-					voter.thbwiki_uid = Some(thbwiki_uid);
-				}
-				if let Some(qq_openid) = sess.qq_openid {
-					voter.qq_openid = Some(qq_openid);
-				}
+			if let Some(sess) = ctx.get_login_session(&sid).await? {
+				ctx.merge_login_session(&sess, &mut voter).await?;
+				merged_session = Some(sess);
 			}
 		}
 		let iid = ctx.voters_coll.insert_one(voter.clone(), None).await?;
@@ -60,6 +58,9 @@ pub async fn signup_email(ctx: &AppContext, email: String, verify_code: String,
 			requester_ip: ip.clone(),
 			requester_additional_fingerprint: additional_fingerprint.clone()
 		}).await;
+		if let Some(sess) = merged_session {
+			ctx.log_login_session(voter._id.as_ref().unwrap(), &sess, ip.clone(), additional_fingerprint.clone()).await;
+		}
 		Ok(voter)
 	} else {
 		return Err(ServiceError::new_error_kind(SERVICE_NAME, "USER_ALREADY_EXIST").into());
@@ -82,14 +83,10 @@ pub async fn login_email(ctx: &AppContext, email: String, verify_code: String, n
 	if let Some(voter) = ctx.voters_coll.find_one(doc! { "email": email.clone() }, None).await? {
 		let mut voter = voter.clone();
 		if let Some(sid) = sid {
-			if let Some(sess) = ctx.get_login_session(&sid).await {
-				if let Some(thbwiki_uid) = sess.thbwiki_uid {
-					voter.thbwiki_uid = Some(thbwiki_uid);
-				}
-				if let Some(qq_openid) = sess.qq_openid {
-					voter.qq_openid = Some(qq_openid);
-				}
+			if let Some(sess) = ctx.get_login_session(&sid).await? {
+				ctx.merge_login_session(&sess, &mut voter).await?;
 				ctx.voters_coll.replace_one(doc! { "email": email.clone() }, voter.clone(), None).await?;
+				ctx.log_login_session(voter._id.as_ref().unwrap(), &sess, ip.clone(), additional_fingerprint.clone()).await;
 			}
 		};
 		log(ctx, ActivityLogEntry::VoterLogin {
@@ -164,16 +161,14 @@ pub async fn signup_phone(ctx: &AppContext, phone: String, verify_code: String,
 			qq_openid: None,
 			pfp: None,
 			thbwiki_uid: None,
+			patchyvideo_uid: None,
 			removed: None
 		};
+		let mut merged_session = None;
 		if let Some(sid) = sid {
-			if let Some(sess) = ctx.get_login_session(&sid).await {
-				if let Some(thbwiki_uid) = sess.thbwiki_uid {
-					voter.thbwiki_uid = Some(thbwiki_uid);
-				}
-				if let Some(qq_openid) = sess.qq_openid {
-					voter.qq_openid = Some(qq_openid);
-				}
+			if let Some(sess) = ctx.get_login_session(&sid).await? {
+				ctx.merge_login_session(&sess, &mut voter).await?;
+				merged_session = Some(sess);
 			}
 		}
 		let iid = ctx.voters_coll.insert_one(voter.clone(), None).await?;
@@ -187,6 +182,9 @@ pub async fn signup_phone(ctx: &AppContext, phone: String, verify_code: String,
 			requester_ip: ip.clone(),
 			requester_additional_fingerprint: additional_fingerprint.clone()
 		}).await;
+		if let Some(sess) = merged_session {
+			ctx.log_login_session(voter._id.as_ref().unwrap(), &sess, ip.clone(), additional_fingerprint.clone()).await;
+		}
 		Ok(voter)
 	} else {
 		return Err(ServiceError::new_error_kind(SERVICE_NAME, "USER_ALREADY_EXIST").into());
@@ -247,14 +245,10 @@ pub async fn login_phone(ctx: &AppContext, phone: String, verify_code: String, n
 	if let Some(voter) = ctx.voters_coll.find_one(doc! { "phone": phone.clone() }, None).await? {
 		let mut voter = voter.clone();
 		if let Some(sid) = sid {
-			if let Some(sess) = ctx.get_login_session(&sid).await {
-				if let Some(thbwiki_uid) = sess.thbwiki_uid {
-					voter.thbwiki_uid = Some(thbwiki_uid);
-				}
-				if let Some(qq_openid) = sess.qq_openid {
-					voter.qq_openid = Some(qq_openid);
-				}
+			if let Some(sess) = ctx.get_login_session(&sid).await? {
+				ctx.merge_login_session(&sess, &mut voter).await?;
 				ctx.voters_coll.replace_one(doc! { "phone": phone.clone() }, voter.clone(), None).await?;
+				ctx.log_login_session(voter._id.as_ref().unwrap(), &sess, ip.clone(), additional_fingerprint.clone()).await;
 			}
 		};
 		log(ctx, ActivityLogEntry::VoterLogin {
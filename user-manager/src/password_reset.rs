@@ -0,0 +1,171 @@
+use std::ops::RangeInclusive;
+
+use crate::{context::AppContext, models::{ActivityLogEntry, Voter}, common::{SERVICE_NAME, rate_limit}};
+use argon2::Config;
+use bson::{DateTime, Document};
+use mongodb::bson::{doc};
+use pvrustlib::{ServiceError, EmptyJSON, json_request};
+use rand::{Rng, RngCore, rngs::OsRng};
+use redis::AsyncCommands;
+
+use crate::log;
+
+/// 同一邮箱或手机两次发送重置验证码的最短间隔，秒
+const PASSWORD_RESET_INTERVAL: usize = 120;
+/// 重置验证码有效期，秒
+const PASSWORD_RESET_CODE_TTL: usize = 1800;
+
+/// 重置密码的验证码发送目标
+pub enum ResetTarget {
+	Email(String),
+	Phone(String)
+}
+
+impl ResetTarget {
+	/// 邮箱按原样使用，与注册、登录和修改邮箱时保存的一致
+	pub fn new(email: Option<String>, phone: Option<String>) -> Result<ResetTarget, ServiceError> {
+		match (email, phone) {
+			(Some(email), None) => Ok(ResetTarget::Email(email)),
+			(None, Some(phone)) => Ok(ResetTarget::Phone(phone)),
+			_ => Err(ServiceError::new_error_kind(SERVICE_NAME, "EMAIL_OR_PHONE_REQUIRED"))
+		}
+	}
+	fn redis_key(&self) -> String {
+		match self {
+			ResetTarget::Email(email) => format!("password-reset-email-{}", email),
+			ResetTarget::Phone(phone) => format!("password-reset-phone-{}", phone)
+		}
+	}
+	fn guard_key(&self) -> String {
+		match self {
+			ResetTarget::Email(email) => format!("password-reset-email-guard-{}", email),
+			ResetTarget::Phone(phone) => format!("password-reset-phone-guard-{}", phone)
+		}
+	}
+	/// 只有验证过的邮箱或手机才能用于重置密码
+	fn voter_filter(&self) -> Document {
+		match self {
+			ResetTarget::Email(email) => doc! { "email": email.clone(), "email_verified": true, "removed": { "$ne": true } },
+			ResetTarget::Phone(phone) => doc! { "phone": phone.clone(), "phone_verified": true, "removed": { "$ne": true } }
+		}
+	}
+	fn rate_limit_id(&self) -> &str {
+		match self {
+			ResetTarget::Email(email) => email,
+			ResetTarget::Phone(phone) => phone
+		}
+	}
+}
+
+/// 向投票人验证过的邮箱或手机发送重置密码的验证码
+///
+/// 没有对应投票人时同样返回成功，避免被用来探测邮箱或手机是否注册
+pub async fn send_reset_code(ctx: &AppContext, target: ResetTarget, ip: Option<String>, additional_fingerprint: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+	let mut redis_conn = ctx.redis_client.get_async_connection().await?;
+	let guard: Option<String> = redis_conn.get(target.guard_key()).await?;
+	if let Some(guard) = guard {
+		if guard == "guard" {
+			return Err(ServiceError::new_error_kind(SERVICE_NAME, "REQUEST_TOO_FREQUENT").into());
+		}
+	}
+	redis_conn.set_ex(target.guard_key(), "guard", PASSWORD_RESET_INTERVAL).await?;
+	if ctx.voters_coll.find_one(target.voter_filter(), None).await?.is_none() {
+		return Ok(());
+	}
+	// generate 6 digits code
+	let code_u32 = OsRng.gen_range(RangeInclusive::new(0u32,  999999u32));
+	let code = format!("{:06}", code_u32);
+	redis_conn.set_ex(target.redis_key(), code.clone(), PASSWORD_RESET_CODE_TTL).await?;
+	match target {
+		ResetTarget::Email(email) => {
+			let req = crate::email_service::EmailRequest {
+				code: code.clone(),
+				email: email.clone()
+			};
+			let resp: EmptyJSON = json_request(SERVICE_NAME, &format!("{}/v1/vote-code", crate::comm::SERVICE_EMAIL_ADDRESS), req).await?;
+			log(ctx, ActivityLogEntry::SendEmail {
+				created_at: DateTime::now(),
+				target_email: email,
+				code: code,
+				requester_ip: ip,
+				requester_additional_fingerprint: additional_fingerprint
+			}).await;
+		},
+		ResetTarget::Phone(phone) => {
+			let req = crate::sms_service::SMSRequest {
+				code: code.clone(),
+				mobile: phone.clone()
+			};
+			let resp: EmptyJSON = json_request(SERVICE_NAME, &format!("{}/v1/vote-code", crate::comm::SERVICE_SMS_ADDRESS), req).await?;
+			log(ctx, ActivityLogEntry::SendSMS {
+				created_at: DateTime::now(),
+				target_phone: phone,
+				code: code,
+				requester_ip: ip,
+				requester_additional_fingerprint: additional_fingerprint
+			}).await;
+		}
+	}
+	Ok(())
+}
+
+/// 用验证码设置新密码，之前签发的用户token全部失效
+pub async fn reset_password(ctx: &AppContext, target: ResetTarget, verify_code: String, new_password: String, ip: Option<String>, additional_fingerprint: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+	let mut conn = ctx.redis_client.get_async_connection().await?;
+	rate_limit(&target.rate_limit_id(), &mut conn).await?;
+	// 读取的同时删除验证码，同一个验证码只能尝试一次，并发请求也只有一个能读到
+	let key = target.redis_key();
+	let (expected_code,): (Option<String>,) = redis::pipe().atomic().get(&key).del(&key).ignore().query_async(&mut conn).await?;
+	if expected_code.as_deref() != Some(verify_code.as_str()) {
+		return Err(ServiceError::new_error_kind(SERVICE_NAME, "INCORRECT_VERIFY_CODE").into());
+	}
+	let voter: Voter = match ctx.voters_coll.find_one(target.voter_filter(), None).await? {
+		Some(voter) => voter,
+		None => return Err(ServiceError::new_error_kind(SERVICE_NAME, "INCORRECT_VERIFY_CODE").into())
+	};
+	let uid = voter._id.as_ref().unwrap().clone();
+	let mut salt = [0u8; 16];
+	OsRng.fill_bytes(&mut salt);
+	let new_password_hashed = argon2::hash_encoded(new_password.as_bytes(), &salt, &Config::default())?;
+	// 清除salt，旧版bcrypt密码同时升级到argon2
+	ctx.voters_coll.update_one(doc! { "_id": uid.clone() }, doc! { "$set": { "password_hashed": new_password_hashed, "salt": null } }, None).await?;
+	ctx.revoke_user_tokens(&uid).await?;
+	let (email, phone) = match target {
+		ResetTarget::Email(email) => (Some(email), None),
+		ResetTarget::Phone(phone) => (None, Some(phone))
+	};
+	log(ctx, ActivityLogEntry::PasswordReset {
+		created_at: DateTime::now(),
+		uid: uid,
+		email: email,
+		phone: phone,
+		requester_ip: ip,
+		requester_additional_fingerprint: additional_fingerprint
+	}).await;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn target_requires_exactly_one_of_email_or_phone() {
+		assert!(ResetTarget::new(None, None).is_err());
+		assert!(ResetTarget::new(Some("a@example.com".into()), Some("13800000000".into())).is_err());
+		let target = ResetTarget::new(Some("reimu@example.com".into()), None).unwrap();
+		assert_eq!(target.redis_key(), "password-reset-email-reimu@example.com");
+		assert_eq!(target.guard_key(), "password-reset-email-guard-reimu@example.com");
+		let target = ResetTarget::new(None, Some("13800000000".into())).unwrap();
+		assert_eq!(target.redis_key(), "password-reset-phone-13800000000");
+	}
+
+	#[test]
+	fn mixed_case_email_matches_the_stored_address() {
+		// 注册时保存的是用户输入的原样，如Reimu@Example.com
+		let target = ResetTarget::new(Some("Reimu@Example.com".into()), None).unwrap();
+		assert_eq!(target.voter_filter(), doc! { "email": "Reimu@Example.com", "email_verified": true, "removed": { "$ne": true } });
+		assert_eq!(target.redis_key(), "password-reset-email-Reimu@Example.com");
+		assert_eq!(target.rate_limit_id(), "Reimu@Example.com");
+	}
+}
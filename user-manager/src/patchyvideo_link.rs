@@ -0,0 +1,177 @@
+use crate::{context::AppContext, models::ActivityLogEntry, common::SERVICE_NAME};
+use bson::{DateTime, oid::ObjectId};
+use jwt_simple::prelude::{Duration, HS256Key, HashSet, HashSetFromStringsLike, MACLike, VerificationOptions};
+use mongodb::bson::{doc};
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize};
+
+use crate::log;
+
+/// PatchyVideo账号关联，对应config.toml中的[patchyvideo]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PatchyVideoConfig {
+	/// 与PatchyVideo共享的HS256密钥
+	pub shared_secret: String,
+	/// 为true时关联了PatchyVideo账号的投票人无需验证手机或邮箱也能投票
+	#[serde(default)]
+	pub counts_as_verification: bool
+}
+
+/// PatchyVideo签发的关联token中的内容
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PatchyVideoLinkClaim {
+	/// PatchyVideo用户ID
+	pub uid: String,
+	pub username: Option<String>
+}
+
+/// 关联token的签发方和接收方
+const LINK_TOKEN_ISSUER: &str = "patchyvideo";
+const LINK_TOKEN_AUDIENCE: &str = "thvote";
+
+/// 关联token最长有效期，分钟
+const LINK_TOKEN_MAX_VALIDITY_MINS: u64 = 10;
+
+/// 校验PatchyVideo签发的关联token
+pub fn verify_link_token(config: &PatchyVideoConfig, token: &str) -> Result<PatchyVideoLinkClaim, ServiceError> {
+	let key = HS256Key::from_bytes(config.shared_secret.as_bytes());
+	let options = VerificationOptions {
+		allowed_issuers: Some(HashSet::from_strings(&[LINK_TOKEN_ISSUER])),
+		allowed_audiences: Some(HashSet::from_strings(&[LINK_TOKEN_AUDIENCE])),
+		max_validity: Some(Duration::from_mins(LINK_TOKEN_MAX_VALIDITY_MINS)),
+		..Default::default()
+	};
+	let claims = key.verify_token::<PatchyVideoLinkClaim>(token, Some(options)).map_err(|e| ServiceError::new_jwt_error(SERVICE_NAME, Some(format!("{:?}", e))))?;
+	if claims.custom.uid.is_empty() {
+		return Err(ServiceError::new_error_kind(SERVICE_NAME, "INVALID_PATCHYVIDEO_TOKEN"));
+	}
+	Ok(claims.custom)
+}
+
+fn patchyvideo_config(ctx: &AppContext) -> Result<&PatchyVideoConfig, Box<dyn std::error::Error>> {
+	ctx.patchyvideo.as_ref().ok_or_else(|| ServiceError::new_error_kind(SERVICE_NAME, "PATCHYVIDEO_LINK_DISABLED").into())
+}
+
+/// 把PatchyVideo账号关联到投票人，一个PatchyVideo账号只能关联一个投票人
+pub async fn link_patchyvideo(ctx: &AppContext, uid: ObjectId, token: String, ip: Option<String>, additional_fingerprint: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+	let config = patchyvideo_config(ctx)?;
+	let claim = verify_link_token(config, &token)?;
+	if let Some(existing_voter) = ctx.voters_coll.find_one(doc! { "patchyvideo_uid": claim.uid.clone(), "removed": { "$ne": true } }, None).await? {
+		if existing_voter._id.as_ref() != Some(&uid) {
+			return Err(ServiceError::new_error_kind(SERVICE_NAME, "PATCHYVIDEO_IN_USE").into());
+		}
+		return Ok(());
+	}
+	// patchyvideo_uid上有唯一索引，并发关联同一个账号时只有一个能成功
+	let result = ctx.voters_coll.update_one(doc! { "_id": uid.clone(), "removed": { "$ne": true } }, doc! { "$set": { "patchyvideo_uid": claim.uid.clone() } }, None).await?;
+	if result.matched_count == 0 {
+		return Err(ServiceError::new_not_found(SERVICE_NAME, None).into());
+	}
+	log(ctx, ActivityLogEntry::LinkPatchyVideo {
+		created_at: DateTime::now(),
+		uid: uid,
+		patchyvideo_uid: claim.uid,
+		requester_ip: ip,
+		requester_additional_fingerprint: additional_fingerprint
+	}).await;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use jwt_simple::prelude::{Claims, Clock, UnixTimeStamp};
+
+	use crate::models::Voter;
+
+	use super::*;
+
+	fn config() -> PatchyVideoConfig {
+		PatchyVideoConfig {
+			shared_secret: "secret".into(),
+			counts_as_verification: false
+		}
+	}
+
+	fn link_token(secret: &str, issuer: &str, audience: &str, issued_at: UnixTimeStamp, uid: &str) -> String {
+		let claim = PatchyVideoLinkClaim {
+			uid: uid.into(),
+			username: Some("Reimu".into())
+		};
+		let claims = Claims::with_custom_claims_given_valid_period(claim, issued_at, Duration::from_mins(5)).with_issuer(issuer).with_audience(audience);
+		HS256Key::from_bytes(secret.as_bytes()).authenticate(claims).unwrap()
+	}
+
+	fn error_kind(e: &ServiceError) -> &str {
+		match e {
+			ServiceError::Error { resp } => &resp.error_kind
+		}
+	}
+
+	fn voter(patchyvideo_uid: Option<&str>) -> Voter {
+		Voter {
+			_id: Some(ObjectId::new()),
+			phone: None,
+			phone_verified: false,
+			email: Some("reimu@example.com".into()),
+			email_verified: false,
+			password_hashed: None,
+			salt: None,
+			created_at: DateTime::now(),
+			nickname: None,
+			signup_ip: None,
+			qq_openid: None,
+			pfp: None,
+			thbwiki_uid: None,
+			patchyvideo_uid: patchyvideo_uid.map(|f| f.to_string()),
+			removed: None
+		}
+	}
+
+	#[test]
+	fn linked_account_verifies_only_when_enabled() {
+		let linked = voter(Some("5f1a"));
+		assert!(linked.generate_vote_id(2022, false).is_err());
+		assert!(linked.generate_vote_id(2022, true).unwrap().starts_with("thvote-2022-"));
+		assert!(voter(None).generate_vote_id(2022, true).is_err());
+	}
+
+	#[test]
+	fn verification_is_opt_in() {
+		let config: PatchyVideoConfig = toml::from_str(r#"shared_secret = "secret""#).unwrap();
+		assert!(!config.counts_as_verification);
+	}
+
+	#[test]
+	fn accepts_token_signed_for_thvote() {
+		let token = link_token("secret", "patchyvideo", "thvote", Clock::now_since_epoch(), "5f1a");
+		let claim = verify_link_token(&config(), &token).unwrap();
+		assert_eq!(claim.uid, "5f1a");
+		assert_eq!(claim.username.as_deref(), Some("Reimu"));
+	}
+
+	#[test]
+	fn rejects_token_signed_with_other_secret() {
+		let token = link_token("other-secret", "patchyvideo", "thvote", Clock::now_since_epoch(), "5f1a");
+		assert!(verify_link_token(&config(), &token).is_err());
+	}
+
+	#[test]
+	fn rejects_token_for_other_issuer_or_audience() {
+		let token = link_token("secret", "someone-else", "thvote", Clock::now_since_epoch(), "5f1a");
+		assert!(verify_link_token(&config(), &token).is_err());
+		let token = link_token("secret", "patchyvideo", "someone-else", Clock::now_since_epoch(), "5f1a");
+		assert!(verify_link_token(&config(), &token).is_err());
+	}
+
+	#[test]
+	fn rejects_expired_token() {
+		let token = link_token("secret", "patchyvideo", "thvote", Clock::now_since_epoch() - Duration::from_hours(1), "5f1a");
+		assert!(verify_link_token(&config(), &token).is_err());
+	}
+
+	#[test]
+	fn rejects_token_without_uid() {
+		let token = link_token("secret", "patchyvideo", "thvote", Clock::now_since_epoch(), "");
+		assert_eq!(error_kind(&verify_link_token(&config(), &token).unwrap_err()), "INVALID_PATCHYVIDEO_TOKEN");
+	}
+}
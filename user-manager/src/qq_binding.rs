@@ -1,16 +1,421 @@
-use crate::{context::{AppContext, LoginSession}, models::{Voter}};
-use argon2::Config;
+use std::collections::HashMap;
+
+use crate::{context::{AppContext, LoginSession, ThirdPartyLoginOutcome}, models::{ActivityLogEntry, Voter}, common::SERVICE_NAME};
+use async_trait::async_trait;
+use bson::{DateTime, oid::ObjectId};
 use mongodb::bson::{doc};
-use chrono::Utc;
-use chrono::prelude::*;
-use rand::{Rng, rngs::OsRng, RngCore};
-
-// pub async fn redirect_callback(ctx: &AppContext, openid: String, nickname: Option<String>, signup_ip: Option<String>) -> Result<Voter, Box<dyn std::error::Error>> {
-// 	let sess = LoginSession {
-// 		thbwiki_uid: None,
-// 		qq_openid: Some(openid),
-// 		signup_ip: signup_ip
-// 	};
-// 	let sid = ctx.create_login_session(sess);
-// 	return Err(Box::new(ServiceError::RedirectToSignup{ sid: sid, nickname: nickname }))
-// }
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize};
+
+use crate::log;
+
+fn default_api_base() -> String {
+	"https://graph.qq.com".into()
+}
+
+/// QQ互联应用，对应config.toml中的[qq]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct QQConnectConfig {
+	pub app_id: String,
+	pub app_key: String,
+	/// 前端接收code和state的页面
+	pub redirect_uri: String,
+	#[serde(default = "default_api_base")]
+	pub api_base: String
+}
+
+/// QQ用户
+#[derive(Debug, Clone, PartialEq)]
+pub struct QQUser {
+	pub openid: String,
+	pub nickname: Option<String>
+}
+
+/// QQ互联接口
+#[async_trait]
+pub trait QQConnectProvider: std::fmt::Debug + Send + Sync {
+	/// 授权页面地址
+	fn authorize_url(&self, state: &str) -> String;
+	/// 用回调中的code获取用户，code无效时返回错误
+	async fn fetch_user(&self, code: &str) -> Result<QQUser, ServiceError>;
+}
+
+#[derive(Deserialize)]
+struct QQTokenResponse {
+	#[serde(default)]
+	access_token: Option<String>,
+	#[serde(default)]
+	error_description: Option<String>
+}
+
+#[derive(Deserialize)]
+struct QQOpenIdResponse {
+	#[serde(default)]
+	openid: Option<String>,
+	#[serde(default)]
+	error_description: Option<String>
+}
+
+#[derive(Deserialize)]
+struct QQUserInfoResponse {
+	ret: i32,
+	#[serde(default)]
+	nickname: Option<String>
+}
+
+/// 调用graph.qq.com
+#[derive(Debug)]
+pub struct QQConnectClient {
+	config: QQConnectConfig,
+	client: reqwest::Client
+}
+
+impl QQConnectClient {
+	pub fn new(config: QQConnectConfig) -> Self {
+		Self {
+			config,
+			client: reqwest::Client::new()
+		}
+	}
+
+	async fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T, ServiceError> {
+		let url = format!("{}{}", self.config.api_base, path);
+		let resp = self.client.get(&url).query(query).send().await.map_err(|e| ServiceError::new_network_error(SERVICE_NAME, &url, Some(format!("{:?}", e))))?;
+		resp.json().await.map_err(|e| ServiceError::new_json_error(SERVICE_NAME, &url, Some(format!("{:?}", e))))
+	}
+}
+
+#[async_trait]
+impl QQConnectProvider for QQConnectClient {
+	fn authorize_url(&self, state: &str) -> String {
+		let base = format!("{}/oauth2.0/authorize", self.config.api_base);
+		reqwest::Url::parse_with_params(&base, &[
+			("response_type", "code"),
+			("client_id", self.config.app_id.as_str()),
+			("redirect_uri", self.config.redirect_uri.as_str()),
+			("state", state)
+		]).map(|f| f.to_string()).unwrap_or(base)
+	}
+	async fn fetch_user(&self, code: &str) -> Result<QQUser, ServiceError> {
+		let token: QQTokenResponse = self.get_json("/oauth2.0/token", &[
+			("grant_type", "authorization_code"),
+			("client_id", self.config.app_id.as_str()),
+			("client_secret", self.config.app_key.as_str()),
+			("code", code),
+			("redirect_uri", self.config.redirect_uri.as_str()),
+			("fmt", "json")
+		]).await?;
+		let access_token = match token.access_token {
+			Some(access_token) => access_token,
+			None => return Err(ServiceError::new(SERVICE_NAME, format!("QQ token: {}", token.error_description.unwrap_or_default())))
+		};
+		let me: QQOpenIdResponse = self.get_json("/oauth2.0/me", &[("access_token", access_token.as_str()), ("fmt", "json")]).await?;
+		let openid = match me.openid {
+			Some(openid) => openid,
+			None => return Err(ServiceError::new(SERVICE_NAME, format!("QQ openid: {}", me.error_description.unwrap_or_default())))
+		};
+		// 昵称只用于注册时的默认值，获取失败不影响登录
+		let info: Option<QQUserInfoResponse> = self.get_json("/user/get_user_info", &[
+			("access_token", access_token.as_str()),
+			("oauth_consumer_key", self.config.app_id.as_str()),
+			("openid", openid.as_str())
+		]).await.ok();
+		Ok(QQUser {
+			openid,
+			nickname: info.filter(|f| f.ret == 0).and_then(|f| f.nickname)
+		})
+	}
+}
+
+/// 固定返回预设用户，用于测试
+#[derive(Debug, Default)]
+pub struct StubQQConnect {
+	/// code到用户
+	pub users: HashMap<String, QQUser>
+}
+
+#[async_trait]
+impl QQConnectProvider for StubQQConnect {
+	fn authorize_url(&self, state: &str) -> String {
+		format!("https://graph.qq.com/oauth2.0/authorize?state={}", state)
+	}
+	async fn fetch_user(&self, code: &str) -> Result<QQUser, ServiceError> {
+		self.users.get(code).cloned().ok_or_else(|| ServiceError::new_error_kind(SERVICE_NAME, "QQ_OAUTH_FAILED"))
+	}
+}
+
+/// QQ登录和解绑读写的数据，AppContext使用MongoDB和Redis
+#[async_trait]
+pub trait QQBindingStore: Send + Sync {
+	/// 校验并删除授权请求的state
+	async fn consume_oauth_state(&self, state: &str) -> Result<bool, Box<dyn std::error::Error>>;
+	/// 绑定了openid的未注销投票人
+	async fn find_voter_by_openid(&self, openid: &str) -> Result<Option<Voter>, Box<dyn std::error::Error>>;
+	async fn find_voter(&self, uid: &ObjectId) -> Result<Option<Voter>, Box<dyn std::error::Error>>;
+	async fn unset_openid(&self, uid: &ObjectId) -> Result<(), Box<dyn std::error::Error>>;
+	async fn create_login_session(&self, sess: LoginSession) -> Result<String, Box<dyn std::error::Error>>;
+	async fn log(&self, entry: ActivityLogEntry);
+}
+
+#[async_trait]
+impl QQBindingStore for AppContext {
+	async fn consume_oauth_state(&self, state: &str) -> Result<bool, Box<dyn std::error::Error>> {
+		AppContext::consume_oauth_state(self, "qq", state).await
+	}
+	async fn find_voter_by_openid(&self, openid: &str) -> Result<Option<Voter>, Box<dyn std::error::Error>> {
+		Ok(self.voters_coll.find_one(doc! { "qq_openid": openid, "removed": { "$ne": true } }, None).await?)
+	}
+	async fn find_voter(&self, uid: &ObjectId) -> Result<Option<Voter>, Box<dyn std::error::Error>> {
+		Ok(self.voters_coll.find_one(doc! { "_id": uid.clone() }, None).await?)
+	}
+	async fn unset_openid(&self, uid: &ObjectId) -> Result<(), Box<dyn std::error::Error>> {
+		self.voters_coll.update_one(doc! { "_id": uid.clone() }, doc! { "$unset": { "qq_openid": "" } }, None).await?;
+		Ok(())
+	}
+	async fn create_login_session(&self, sess: LoginSession) -> Result<String, Box<dyn std::error::Error>> {
+		AppContext::create_login_session(self, sess).await
+	}
+	async fn log(&self, entry: ActivityLogEntry) {
+		log(self, entry).await
+	}
+}
+
+fn qq_provider(ctx: &AppContext) -> Result<&dyn QQConnectProvider, Box<dyn std::error::Error>> {
+	ctx.qq.as_deref().ok_or_else(|| ServiceError::new_error_kind(SERVICE_NAME, "QQ_LOGIN_DISABLED").into())
+}
+
+/// 生成QQ授权页面地址
+pub async fn authorize(ctx: &AppContext) -> Result<String, Box<dyn std::error::Error>> {
+	let provider = qq_provider(ctx)?;
+	let state = ctx.create_oauth_state("qq").await?;
+	Ok(provider.authorize_url(&state))
+}
+
+/// 处理QQ授权回调
+///
+/// openid已绑定投票人时直接登录，否则创建登录会话，之后的手机或邮箱登录完成首次绑定
+pub async fn redirect_callback(ctx: &AppContext, code: String, state: String, signup_ip: Option<String>, additional_fingerprint: Option<String>) -> Result<ThirdPartyLoginOutcome, Box<dyn std::error::Error>> {
+	let provider = qq_provider(ctx)?;
+	handle_callback(provider, ctx, code, state, signup_ip, additional_fingerprint).await
+}
+
+async fn handle_callback(provider: &dyn QQConnectProvider, store: &dyn QQBindingStore, code: String, state: String, signup_ip: Option<String>, additional_fingerprint: Option<String>) -> Result<ThirdPartyLoginOutcome, Box<dyn std::error::Error>> {
+	if !store.consume_oauth_state(&state).await? {
+		return Err(ServiceError::new_error_kind(SERVICE_NAME, "INVALID_OAUTH_STATE").into());
+	}
+	let user = provider.fetch_user(&code).await?;
+	if let Some(voter) = store.find_voter_by_openid(&user.openid).await? {
+		store.log(ActivityLogEntry::VoterLogin {
+			created_at: DateTime::now(),
+			uid: voter._id.as_ref().unwrap().clone(),
+			phone: None,
+			email: None,
+			requester_ip: signup_ip,
+			requester_additional_fingerprint: additional_fingerprint
+		}).await;
+		return Ok(ThirdPartyLoginOutcome::Login(voter));
+	}
+	let sess = LoginSession {
+		thbwiki_uid: None,
+		qq_openid: Some(user.openid),
+		signup_ip: signup_ip
+	};
+	let sid = store.create_login_session(sess).await?;
+	Ok(ThirdPartyLoginOutcome::Signup { sid: sid, nickname: user.nickname })
+}
+
+/// 在账号设置中解除QQ绑定
+pub async fn unbind_qq(ctx: &AppContext, uid: ObjectId, ip: Option<String>, additional_fingerprint: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+	handle_unbind(ctx, uid, ip, additional_fingerprint).await
+}
+
+async fn handle_unbind(store: &dyn QQBindingStore, uid: ObjectId, ip: Option<String>, additional_fingerprint: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+	let voter = match store.find_voter(&uid).await? {
+		Some(voter) => voter,
+		None => return Err(ServiceError::new_not_found(SERVICE_NAME, None).into())
+	};
+	let qq_openid = match voter.qq_openid {
+		Some(qq_openid) => qq_openid,
+		None => return Err(ServiceError::new_error_kind(SERVICE_NAME, "QQ_NOT_BOUND").into())
+	};
+	store.unset_openid(&uid).await?;
+	store.log(ActivityLogEntry::UnbindQQ {
+		created_at: DateTime::now(),
+		uid: uid,
+		qq_openid: qq_openid,
+		requester_ip: ip,
+		requester_additional_fingerprint: additional_fingerprint
+	}).await;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use std::{collections::HashSet, sync::Mutex};
+
+	use super::*;
+
+	/// 进程内的QQBindingStore
+	#[derive(Default)]
+	struct InMemoryStore {
+		states: Mutex<HashSet<String>>,
+		voters: Mutex<Vec<Voter>>,
+		sessions: Mutex<Vec<LoginSession>>,
+		logs: Mutex<Vec<ActivityLogEntry>>
+	}
+
+	#[async_trait]
+	impl QQBindingStore for InMemoryStore {
+		async fn consume_oauth_state(&self, state: &str) -> Result<bool, Box<dyn std::error::Error>> {
+			Ok(self.states.lock().unwrap().remove(state))
+		}
+		async fn find_voter_by_openid(&self, openid: &str) -> Result<Option<Voter>, Box<dyn std::error::Error>> {
+			Ok(self.voters.lock().unwrap().iter().find(|f| f.qq_openid.as_deref() == Some(openid) && f.removed != Some(true)).cloned())
+		}
+		async fn find_voter(&self, uid: &ObjectId) -> Result<Option<Voter>, Box<dyn std::error::Error>> {
+			Ok(self.voters.lock().unwrap().iter().find(|f| f._id.as_ref() == Some(uid)).cloned())
+		}
+		async fn unset_openid(&self, uid: &ObjectId) -> Result<(), Box<dyn std::error::Error>> {
+			for voter in self.voters.lock().unwrap().iter_mut().filter(|f| f._id.as_ref() == Some(uid)) {
+				voter.qq_openid = None;
+			}
+			Ok(())
+		}
+		async fn create_login_session(&self, sess: LoginSession) -> Result<String, Box<dyn std::error::Error>> {
+			let mut sessions = self.sessions.lock().unwrap();
+			sessions.push(sess);
+			Ok(format!("sid-{}", sessions.len()))
+		}
+		async fn log(&self, entry: ActivityLogEntry) {
+			self.logs.lock().unwrap().push(entry);
+		}
+	}
+
+	fn voter(qq_openid: Option<&str>) -> Voter {
+		Voter {
+			_id: Some(ObjectId::new()),
+			phone: None,
+			phone_verified: false,
+			email: Some("sanae@example.com".into()),
+			email_verified: true,
+			password_hashed: None,
+			salt: None,
+			created_at: DateTime::now(),
+			nickname: Some("Sanae".into()),
+			signup_ip: None,
+			qq_openid: qq_openid.map(|f| f.to_string()),
+			pfp: None,
+			thbwiki_uid: None,
+			patchyvideo_uid: None,
+			removed: None
+		}
+	}
+
+	fn provider() -> StubQQConnect {
+		let mut provider = StubQQConnect::default();
+		provider.users.insert("known-code".into(), QQUser { openid: "OPENID1".into(), nickname: Some("Sanae".into()) });
+		provider.users.insert("new-code".into(), QQUser { openid: "OPENID2".into(), nickname: Some("Suwako".into()) });
+		provider
+	}
+
+	fn store_with_state(state: &str) -> InMemoryStore {
+		let store = InMemoryStore::default();
+		store.states.lock().unwrap().insert(state.into());
+		store
+	}
+
+	fn error_kind(e: &Box<dyn std::error::Error>) -> String {
+		match e.downcast_ref::<ServiceError>() {
+			Some(ServiceError::Error { resp }) => resp.error_kind.clone(),
+			_ => String::new()
+		}
+	}
+
+	#[actix_rt::test]
+	async fn callback_requires_unused_state() {
+		let provider = provider();
+		let store = store_with_state("state-1");
+		let e = handle_callback(&provider, &store, "known-code".into(), "other-state".into(), None, None).await.err().unwrap();
+		assert_eq!(error_kind(&e), "INVALID_OAUTH_STATE");
+		assert!(handle_callback(&provider, &store, "new-code".into(), "state-1".into(), None, None).await.is_ok());
+		let e = handle_callback(&provider, &store, "new-code".into(), "state-1".into(), None, None).await.err().unwrap();
+		assert_eq!(error_kind(&e), "INVALID_OAUTH_STATE");
+		assert_eq!(store.sessions.lock().unwrap().len(), 1);
+	}
+
+	#[actix_rt::test]
+	async fn known_openid_logs_in() {
+		let store = store_with_state("state-1");
+		let bound = voter(Some("OPENID1"));
+		store.voters.lock().unwrap().push(bound.clone());
+		let outcome = handle_callback(&provider(), &store, "known-code".into(), "state-1".into(), Some("127.0.0.1".into()), None).await.unwrap();
+		match outcome {
+			ThirdPartyLoginOutcome::Login(voter) => assert_eq!(voter._id, bound._id),
+			ThirdPartyLoginOutcome::Signup { .. } => panic!("expected login")
+		}
+		assert!(store.sessions.lock().unwrap().is_empty());
+		let logs = store.logs.lock().unwrap();
+		assert!(matches!(logs.as_slice(), [ActivityLogEntry::VoterLogin { uid, requester_ip: Some(ip), .. }] if Some(uid) == bound._id.as_ref() && ip == "127.0.0.1"));
+	}
+
+	#[actix_rt::test]
+	async fn unknown_openid_creates_login_session() {
+		let store = store_with_state("state-1");
+		store.voters.lock().unwrap().push(voter(Some("OPENID1")));
+		let outcome = handle_callback(&provider(), &store, "new-code".into(), "state-1".into(), Some("127.0.0.1".into()), None).await.unwrap();
+		match outcome {
+			ThirdPartyLoginOutcome::Signup { sid, nickname } => {
+				assert_eq!(sid, "sid-1");
+				assert_eq!(nickname.as_deref(), Some("Suwako"));
+			},
+			ThirdPartyLoginOutcome::Login(_) => panic!("expected signup")
+		}
+		let sessions = store.sessions.lock().unwrap();
+		assert_eq!(sessions[0].qq_openid.as_deref(), Some("OPENID2"));
+		assert_eq!(sessions[0].signup_ip.as_deref(), Some("127.0.0.1"));
+		assert!(store.logs.lock().unwrap().is_empty());
+	}
+
+	#[actix_rt::test]
+	async fn unbind_clears_openid_and_logs() {
+		let store = InMemoryStore::default();
+		let bound = voter(Some("OPENID1"));
+		let uid = bound._id.clone().unwrap();
+		store.voters.lock().unwrap().push(bound);
+		handle_unbind(&store, uid.clone(), Some("127.0.0.1".into()), None).await.unwrap();
+		assert_eq!(store.voters.lock().unwrap()[0].qq_openid, None);
+		{
+			let logs = store.logs.lock().unwrap();
+			assert!(matches!(logs.as_slice(), [ActivityLogEntry::UnbindQQ { uid: logged, qq_openid, .. }] if *logged == uid && qq_openid == "OPENID1"));
+		}
+		let e = handle_unbind(&store, uid, None, None).await.err().unwrap();
+		assert_eq!(error_kind(&e), "QQ_NOT_BOUND");
+		assert_eq!(store.logs.lock().unwrap().len(), 1);
+	}
+
+	#[actix_rt::test]
+	async fn stub_returns_configured_users() {
+		let mut provider = StubQQConnect::default();
+		provider.users.insert("good-code".into(), QQUser { openid: "OPENID1".into(), nickname: Some("Sanae".into()) });
+		let provider: &dyn QQConnectProvider = &provider;
+		assert_eq!(provider.fetch_user("good-code").await.unwrap().openid, "OPENID1");
+		assert!(provider.fetch_user("bad-code").await.is_err());
+		assert!(provider.authorize_url("abc").ends_with("state=abc"));
+	}
+
+	#[test]
+	fn client_authorize_url_uses_app_id() {
+		let client = QQConnectClient::new(QQConnectConfig {
+			app_id: "101000".into(),
+			app_key: "key".into(),
+			redirect_uri: "https://vote.thwiki.cc/qq-callback".into(),
+			api_base: default_api_base()
+		});
+		let url = reqwest::Url::parse(&client.authorize_url("abc")).unwrap();
+		let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+		assert_eq!(url.path(), "/oauth2.0/authorize");
+		assert_eq!(params["client_id"], "101000");
+		assert_eq!(params["redirect_uri"], "https://vote.thwiki.cc/qq-callback");
+		assert_eq!(params["state"], "abc");
+	}
+}
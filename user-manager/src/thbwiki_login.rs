@@ -1,46 +1,274 @@
-use crate::{context::{AppContext, LoginSession}, models::{Voter}};
-use argon2::Config;
+use crate::{context::{AppContext, LoginSession, ThirdPartyLoginOutcome}, models::ActivityLogEntry, common::SERVICE_NAME};
+use bson::DateTime;
 use mongodb::bson::{doc};
-use chrono::Utc;
-use chrono::prelude::*;
-use rand::{Rng, rngs::OsRng, RngCore};
-
-// pub async fn redirect_callback(ctx: &AppContext, uid: String, email: Option<String>, nickname: Option<String>, signup_ip: Option<String>) -> Result<Voter, Box<dyn std::error::Error>> {
-// 	if email.is_none() {
-// 		// Email not verified by THBWiki
-// 		let sess = LoginSession {
-// 			thbwiki_uid: Some(uid),
-// 			qq_openid: None,
-// 			signup_ip: signup_ip
-// 		};
-// 		let sid = ctx.create_login_session(sess);
-// 		return Err(Box::new(ServiceError::RedirectToSignup{ sid: sid, nickname: nickname }))
-// 	}
-// 	let email = email.unwrap();
-// 	if let Some(voter) = ctx.voters_coll.find_one(doc! { "email": email.clone() }, None).await? {
-// 		let mut voter = voter.clone();
-// 		voter.thbwiki_uid = Some(uid);
-// 		ctx.voters_coll.replace_one(doc! { "email": email.clone() }, voter.clone(), None).await?;
-// 		Ok(voter)
-// 	} else {
-// 		let mut voter = Voter {
-// 			_id: None,
-// 			email: Some(email),
-// 			email_verified: true,
-// 			password_hashed: None,
-// 			salt: None,
-// 			created_at: bson::DateTime(Utc::now()),
-// 			nickname: nickname,
-// 			signup_ip: signup_ip,
-// 			qq_openid: None,
-// 			thbwiki_uid: Some(uid),
-// 			phone: None,
-// 			pfp: None,
-// 			phone_verified: false,
-// 			removed: None
-// 		};
-// 		let iid = ctx.voters_coll.insert_one(voter.clone(), None).await?;
-// 		voter._id = Some(iid.inserted_id.as_object_id().unwrap().clone());
-// 		Ok(voter)
-// 	}
-// }
+use pvrustlib::ServiceError;
+use serde::{Serialize, Deserialize, Deserializer};
+
+use crate::log;
+
+fn default_authorize_url() -> String {
+	"https://thwiki.cc/rest.php/oauth2/authorize".into()
+}
+
+fn default_token_url() -> String {
+	"https://thwiki.cc/rest.php/oauth2/access_token".into()
+}
+
+fn default_profile_url() -> String {
+	"https://thwiki.cc/rest.php/oauth2/resource/profile".into()
+}
+
+/// THBWiki OAuth2客户端，对应config.toml中的[thbwiki]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ThbwikiConfig {
+	pub client_id: String,
+	pub client_secret: String,
+	/// 前端接收code和state的页面
+	pub redirect_uri: String,
+	#[serde(default = "default_authorize_url")]
+	pub authorize_url: String,
+	#[serde(default = "default_token_url")]
+	pub token_url: String,
+	#[serde(default = "default_profile_url")]
+	pub profile_url: String
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TokenResponse {
+	access_token: String
+}
+
+/// THBWiki用户信息
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct ThbwikiProfile {
+	/// 用户ID
+	#[serde(deserialize_with = "string_or_number")]
+	pub sub: String,
+	#[serde(default)]
+	pub username: Option<String>,
+	#[serde(default)]
+	pub email: Option<String>,
+	/// 邮箱是否已在THBWiki验证
+	#[serde(default)]
+	pub confirmed_email: bool
+}
+
+impl ThbwikiProfile {
+	/// 只有THBWiki验证过的邮箱才能用于关联已有投票人
+	pub fn verified_email(&self) -> Option<&str> {
+		match &self.email {
+			Some(email) if self.confirmed_email && !email.is_empty() => Some(email.as_str()),
+			_ => None
+		}
+	}
+}
+
+fn string_or_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+	match serde_json::Value::deserialize(deserializer)? {
+		serde_json::Value::String(s) => Ok(s),
+		serde_json::Value::Number(n) => Ok(n.to_string()),
+		_ => Err(serde::de::Error::custom("expected string or number"))
+	}
+}
+
+fn thbwiki_config(ctx: &AppContext) -> Result<&ThbwikiConfig, Box<dyn std::error::Error>> {
+	ctx.thbwiki.as_ref().ok_or_else(|| ServiceError::new_error_kind(SERVICE_NAME, "THBWIKI_LOGIN_DISABLED").into())
+}
+
+pub fn build_authorize_url(config: &ThbwikiConfig, state: &str) -> Result<String, Box<dyn std::error::Error>> {
+	let url = reqwest::Url::parse_with_params(&config.authorize_url, &[
+		("response_type", "code"),
+		("client_id", config.client_id.as_str()),
+		("redirect_uri", config.redirect_uri.as_str()),
+		("state", state)
+	])?;
+	Ok(url.to_string())
+}
+
+/// 用回调中的code换取access token
+pub async fn exchange_code(config: &ThbwikiConfig, code: &str) -> Result<String, Box<dyn std::error::Error>> {
+	let resp = reqwest::Client::new()
+		.post(&config.token_url)
+		.form(&[
+			("grant_type", "authorization_code"),
+			("code", code),
+			("redirect_uri", config.redirect_uri.as_str()),
+			("client_id", config.client_id.as_str()),
+			("client_secret", config.client_secret.as_str())
+		])
+		.send()
+		.await?;
+	if !resp.status().is_success() {
+		return Err(ServiceError::new_error_kind(SERVICE_NAME, "THBWIKI_OAUTH_FAILED").into());
+	}
+	let token: TokenResponse = resp.json().await?;
+	Ok(token.access_token)
+}
+
+pub async fn fetch_profile(config: &ThbwikiConfig, access_token: &str) -> Result<ThbwikiProfile, Box<dyn std::error::Error>> {
+	let resp = reqwest::Client::new()
+		.get(&config.profile_url)
+		.bearer_auth(access_token)
+		.send()
+		.await?;
+	if !resp.status().is_success() {
+		return Err(ServiceError::new_error_kind(SERVICE_NAME, "THBWIKI_OAUTH_FAILED").into());
+	}
+	Ok(resp.json().await?)
+}
+
+/// 生成THBWiki授权页面地址，state保存在Redis中供回调时校验
+pub async fn authorize(ctx: &AppContext) -> Result<String, Box<dyn std::error::Error>> {
+	let config = thbwiki_config(ctx)?;
+	let state = ctx.create_oauth_state("thbwiki").await?;
+	build_authorize_url(config, &state)
+}
+
+/// 处理THBWiki授权回调
+///
+/// 已关联THBWiki账号，或THBWiki验证过的邮箱属于已有投票人时直接登录，否则创建登录会话等待注册
+pub async fn redirect_callback(ctx: &AppContext, code: String, state: String, signup_ip: Option<String>, additional_fingerprint: Option<String>) -> Result<ThirdPartyLoginOutcome, Box<dyn std::error::Error>> {
+	let config = thbwiki_config(ctx)?;
+	if !ctx.consume_oauth_state("thbwiki", &state).await? {
+		return Err(ServiceError::new_error_kind(SERVICE_NAME, "INVALID_OAUTH_STATE").into());
+	}
+	let access_token = exchange_code(config, &code).await?;
+	let profile = fetch_profile(config, &access_token).await?;
+
+	let mut voter = ctx.voters_coll.find_one(doc! { "thbwiki_uid": profile.sub.clone(), "removed": { "$ne": true } }, None).await?;
+	if voter.is_none() {
+		if let Some(email) = profile.verified_email() {
+			if let Some(mut existing) = ctx.voters_coll.find_one(doc! { "email": email, "email_verified": true, "removed": { "$ne": true } }, None).await? {
+				if existing.thbwiki_uid.as_ref().map_or(false, |f| f != &profile.sub) {
+					return Err(ServiceError::new_error_kind(SERVICE_NAME, "THBWIKI_ALREADY_BOUND").into());
+				}
+				let uid = existing._id.as_ref().unwrap().clone();
+				existing.thbwiki_uid = Some(profile.sub.clone());
+				ctx.voters_coll.update_one(doc! { "_id": uid.clone() }, doc! { "$set": { "thbwiki_uid": profile.sub.clone() } }, None).await?;
+				log(ctx, ActivityLogEntry::LinkThbwiki {
+					created_at: DateTime::now(),
+					uid,
+					thbwiki_uid: profile.sub.clone(),
+					requester_ip: signup_ip.clone(),
+					requester_additional_fingerprint: additional_fingerprint.clone()
+				}).await;
+				voter = Some(existing);
+			}
+		}
+	}
+	match voter {
+		Some(voter) => {
+			log(ctx, ActivityLogEntry::VoterLogin {
+				created_at: DateTime::now(),
+				uid: voter._id.as_ref().unwrap().clone(),
+				phone: None,
+				email: None,
+				requester_ip: signup_ip,
+				requester_additional_fingerprint: additional_fingerprint
+			}).await;
+			Ok(ThirdPartyLoginOutcome::Login(voter))
+		},
+		None => {
+			let sess = LoginSession {
+				thbwiki_uid: Some(profile.sub),
+				qq_openid: None,
+				signup_ip: signup_ip
+			};
+			let sid = ctx.create_login_session(sess).await?;
+			Ok(ThirdPartyLoginOutcome::Signup { sid: sid, nickname: profile.username })
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+
+	use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+
+	use super::*;
+
+	async fn mock_token(form: web::Form<HashMap<String, String>>) -> HttpResponse {
+		let field = |name: &str| form.get(name).map(|f| f.as_str());
+		if field("grant_type") == Some("authorization_code") && field("code") == Some("good-code") && field("client_id") == Some("thvote") && field("client_secret") == Some("secret") && field("redirect_uri") == Some("https://vote.thwiki.cc/thbwiki-callback") {
+			HttpResponse::Ok().json(serde_json::json!({ "token_type": "Bearer", "expires_in": 3600, "access_token": "token-123" }))
+		} else {
+			HttpResponse::BadRequest().json(serde_json::json!({ "error": "invalid_grant" }))
+		}
+	}
+
+	async fn mock_profile(request: HttpRequest) -> HttpResponse {
+		match request.headers().get("Authorization").and_then(|f| f.to_str().ok()) {
+			Some("Bearer token-123") => HttpResponse::Ok().json(serde_json::json!({ "sub": 42, "username": "Reimu", "email": "reimu@example.com", "confirmed_email": true })),
+			_ => HttpResponse::Unauthorized().finish()
+		}
+	}
+
+	/// 在随机端口启动模拟的THBWiki OAuth服务
+	fn start_mock_server() -> ThbwikiConfig {
+		let server = HttpServer::new(|| {
+			App::new()
+				.route("/oauth2/access_token", web::post().to(mock_token))
+				.route("/oauth2/resource/profile", web::get().to(mock_profile))
+		})
+		.workers(1)
+		.bind(("127.0.0.1", 0))
+		.unwrap();
+		let addr = server.addrs()[0];
+		actix_rt::spawn(server.run());
+		ThbwikiConfig {
+			client_id: "thvote".into(),
+			client_secret: "secret".into(),
+			redirect_uri: "https://vote.thwiki.cc/thbwiki-callback".into(),
+			authorize_url: format!("http://{}/oauth2/authorize", addr),
+			token_url: format!("http://{}/oauth2/access_token", addr),
+			profile_url: format!("http://{}/oauth2/resource/profile", addr)
+		}
+	}
+
+	#[test]
+	fn authorize_url_carries_state_and_encoded_redirect() {
+		let config = ThbwikiConfig {
+			client_id: "thvote".into(),
+			client_secret: "secret".into(),
+			redirect_uri: "https://vote.thwiki.cc/thbwiki-callback?from=login".into(),
+			authorize_url: default_authorize_url(),
+			token_url: default_token_url(),
+			profile_url: default_profile_url()
+		};
+		let url = reqwest::Url::parse(&build_authorize_url(&config, "abc123").unwrap()).unwrap();
+		assert_eq!(url.path(), "/rest.php/oauth2/authorize");
+		let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+		assert_eq!(params["response_type"], "code");
+		assert_eq!(params["client_id"], "thvote");
+		assert_eq!(params["redirect_uri"], "https://vote.thwiki.cc/thbwiki-callback?from=login");
+		assert_eq!(params["state"], "abc123");
+	}
+
+	#[actix_rt::test]
+	async fn exchanges_code_and_fetches_profile() {
+		let config = start_mock_server();
+		let token = exchange_code(&config, "good-code").await.unwrap();
+		assert_eq!(token, "token-123");
+		let profile = fetch_profile(&config, &token).await.unwrap();
+		assert_eq!(profile.sub, "42");
+		assert_eq!(profile.username.as_deref(), Some("Reimu"));
+		assert_eq!(profile.verified_email(), Some("reimu@example.com"));
+	}
+
+	#[actix_rt::test]
+	async fn rejected_code_and_token_are_errors() {
+		let config = start_mock_server();
+		assert!(exchange_code(&config, "bad-code").await.is_err());
+		assert!(fetch_profile(&config, "wrong-token").await.is_err());
+	}
+
+	#[test]
+	fn unconfirmed_email_is_not_used_for_linking() {
+		let profile: ThbwikiProfile = serde_json::from_str(r#"{ "sub": "42", "username": "Marisa", "email": "marisa@example.com", "confirmed_email": false }"#).unwrap();
+		assert_eq!(profile.verified_email(), None);
+		let profile: ThbwikiProfile = serde_json::from_str(r#"{ "sub": 7 }"#).unwrap();
+		assert_eq!(profile.sub, "7");
+		assert_eq!(profile.verified_email(), None);
+	}
+}